@@ -0,0 +1,293 @@
+//! Auditor annotations that survive regeneration.
+//!
+//! The PERDComp worksheet ends with three editable columns: "Análise",
+//! "Responsável" and "Status interno". Before a workbook is regenerated, the
+//! annotations are read back from the previous workbook and from a JSON
+//! sidecar, both keyed by `per_dcomp`, and merged into the new records. The
+//! sidecar is then rewritten, so the annotations outlive the workbook itself.
+
+use calamine::{Data, DataType, Reader, open_workbook_auto};
+use log::info;
+use rust_xlsxwriter::{DataValidation, DataValidationErrorStyle, Worksheet, XlsxError};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use crate::{MyResult, PERDCOMP_SHEET_NAME, PerDcomp, WithPath, normalize_text};
+
+/// Default sidecar file, written next to `perdcomp.xlsx`.
+pub const ANNOTATIONS_FILE: &str = "perdcomp.annotations.json";
+
+/// Headers of the annotation columns, in worksheet order.
+pub const ANNOTATION_HEADERS: [&str; 3] = ["Análise", "Responsável", "Status interno"];
+
+/// Choices of the "Status interno" dropdown.
+pub const STATUS_OPTIONS: [&str; 4] = ["Pendente", "Em análise", "Concluído", "Descartado"];
+
+/// Excel limit for the comma separated items of a dropdown list.
+const MAX_LIST_LENGTH: usize = 255;
+
+/// The annotations of one PER/DCOMP.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analise: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub responsavel: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_interno: Option<String>,
+}
+
+impl Annotation {
+    fn of(perdcomp: &PerDcomp) -> Self {
+        Self {
+            analise: perdcomp.analise.clone(),
+            responsavel: perdcomp.responsavel.clone(),
+            status_interno: perdcomp.status_interno.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.analise.is_none() && self.responsavel.is_none() && self.status_interno.is_none()
+    }
+
+    /// Keeps the fields of `self` and fills the missing ones from `other`.
+    pub fn or(self, other: Annotation) -> Annotation {
+        Annotation {
+            analise: self.analise.or(other.analise),
+            responsavel: self.responsavel.or(other.responsavel),
+            status_interno: self.status_interno.or(other.status_interno),
+        }
+    }
+}
+
+/// Annotations keyed by `per_dcomp`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Annotations {
+    pub entries: BTreeMap<String, Annotation>,
+}
+
+impl Annotations {
+    /// Reads the sidecar and the previous workbook, when they exist.
+    ///
+    /// The workbook wins for the PER/DCOMPs it lists, so clearing a cell in
+    /// Excel also clears the annotation.
+    pub fn load(sidecar: &Path, workbook: &Path) -> MyResult<Self> {
+        let mut annotations = if sidecar.exists() {
+            let json = fs::read_to_string(sidecar).with_path(sidecar)?;
+            serde_json::from_str(&json).with_path(sidecar)?
+        } else {
+            Annotations::default()
+        };
+
+        if workbook.exists() {
+            annotations.merge(Annotations::from_workbook(workbook)?);
+        }
+
+        Ok(annotations)
+    }
+
+    /// Reads the annotation columns of the "PERDComp" worksheets of a previous workbook.
+    ///
+    /// Columns are matched by header with [`normalize_text`]. Every row with a
+    /// `per_dcomp` gets an entry, even without annotations.
+    pub fn from_workbook(path: &Path) -> MyResult<Self> {
+        let mut workbook = open_workbook_auto(path).with_path(path)?;
+        let mut annotations = Annotations::default();
+
+        let sheet_names: Vec<String> = workbook
+            .sheet_names()
+            .into_iter()
+            .filter(|name| name.starts_with(PERDCOMP_SHEET_NAME))
+            .collect();
+
+        for sheet_name in sheet_names {
+            let range = workbook.worksheet_range(&sheet_name)?;
+            let mut rows = range.rows();
+            let Some(headers) = rows.next() else {
+                continue;
+            };
+
+            let position = |header: &str| {
+                let target = normalize_text(header);
+                headers
+                    .iter()
+                    .position(|cell| normalize_text(&cell.to_string()) == target)
+            };
+            let Some(key_col) = position("PER/DCOMP") else {
+                continue;
+            };
+            let [analise, responsavel, status_interno] = ANNOTATION_HEADERS.map(position);
+
+            let text = |row: &[Data], col: Option<usize>| {
+                col.and_then(|col| row.get(col))
+                    .and_then(DataType::as_string)
+                    .map(|text| text.trim().to_string())
+                    .filter(|text| !text.is_empty())
+            };
+
+            for row in rows {
+                let Some(key) = text(row, Some(key_col)) else {
+                    continue;
+                };
+                let annotation = Annotation {
+                    analise: text(row, analise),
+                    responsavel: text(row, responsavel),
+                    status_interno: text(row, status_interno),
+                };
+                annotations.entries.insert(key, annotation);
+            }
+        }
+
+        Ok(annotations)
+    }
+
+    /// Replaces the stored entries with the ones of `newer`; empty entries are removed.
+    pub fn merge(&mut self, newer: Annotations) {
+        self.entries.extend(newer.entries);
+        self.entries.retain(|_, annotation| !annotation.is_empty());
+    }
+
+    /// Fills the annotation fields of the records. Stored fields win over the input ones.
+    ///
+    /// Returns the number of records that received stored annotations.
+    pub fn apply(&self, perdcomps: &mut [PerDcomp]) -> usize {
+        let mut count = 0;
+
+        for perdcomp in perdcomps {
+            let Some(stored) = perdcomp
+                .per_dcomp
+                .as_ref()
+                .and_then(|key| self.entries.get(key))
+            else {
+                continue;
+            };
+
+            let annotation = stored.clone().or(Annotation::of(perdcomp));
+            perdcomp.analise = annotation.analise;
+            perdcomp.responsavel = annotation.responsavel;
+            perdcomp.status_interno = annotation.status_interno;
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Writes the sidecar, unless there is nothing to keep.
+    pub fn save(&self, path: &Path) -> MyResult<()> {
+        if self.entries.is_empty() && !path.exists() {
+            return Ok(());
+        }
+
+        info!("Write annotations: {path:?}");
+        fs::write(path, serde_json::to_string_pretty(self)?).with_path(path)?;
+        Ok(())
+    }
+
+    /// Adds the dropdowns of the "Responsável" and "Status interno" columns.
+    ///
+    /// "Status interno" offers [`STATUS_OPTIONS`] and warns about other
+    /// values; "Responsável" offers the names already used and accepts new ones.
+    pub fn add_validations(
+        &self,
+        worksheet: &mut Worksheet,
+        headers: &[String],
+        last_row: u32,
+    ) -> Result<(), XlsxError> {
+        if last_row == 0 {
+            return Ok(());
+        }
+
+        let column = |header: &str| {
+            let target = normalize_text(header);
+            headers
+                .iter()
+                .position(|header| normalize_text(header) == target)
+                .map(|col| col as u16)
+        };
+
+        if let Some(col) = column(ANNOTATION_HEADERS[2]) {
+            let validation = DataValidation::new()
+                .allow_list_strings(&STATUS_OPTIONS)?
+                .set_error_style(DataValidationErrorStyle::Warning);
+            worksheet.add_data_validation(1, col, last_row, col, &validation)?;
+        }
+
+        let names = self.responsaveis();
+        if let Some(col) = column(ANNOTATION_HEADERS[1])
+            && !names.is_empty()
+        {
+            let validation = DataValidation::new()
+                .allow_list_strings(&names)?
+                .set_error_style(DataValidationErrorStyle::Information);
+            worksheet.add_data_validation(1, col, last_row, col, &validation)?;
+        }
+
+        Ok(())
+    }
+
+    /// Distinct "Responsável" names, as many as fit in a dropdown list.
+    fn responsaveis(&self) -> Vec<&str> {
+        let names: BTreeSet<&str> = self
+            .entries
+            .values()
+            .filter_map(|annotation| annotation.responsavel.as_deref())
+            .filter(|name| !name.contains(','))
+            .collect();
+
+        let mut length = 0;
+        names
+            .into_iter()
+            .take_while(|name| {
+                length += name.chars().count() + 1;
+                length <= MAX_LIST_LENGTH
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests_annotations {
+    use super::*;
+    use crate::{new_workbook, push_worksheets};
+
+    #[test]
+    fn annotations_survive_regeneration() -> MyResult<()> {
+        // 1. First run: an auditor annotates a workbook.
+        let mut first = vec![PerDcomp::sample("111", 0.0), PerDcomp::sample("222", 0.0)];
+        first[0].analise = Some("Crédito conferido".to_string());
+        first[0].status_interno = Some("Concluído".to_string());
+
+        let output = tempfile::Builder::new().suffix(".xlsx").tempfile()?;
+        let mut workbook = new_workbook()?;
+        push_worksheets(&mut workbook, &first, "PERDComp", &[])?;
+        workbook.save(output.path())?;
+
+        // 2. Second run: the sidecar is older than the workbook, and also
+        // keeps a PER/DCOMP missing from it.
+        let sidecar = tempfile::NamedTempFile::new()?;
+        fs::write(
+            sidecar.path(),
+            r#"{"111": {"analise": "Antiga", "responsavel": "Ana"}, "999": {"analise": "Arquivada"}}"#,
+        )?;
+
+        let annotations = Annotations::load(sidecar.path(), output.path())?;
+        let mut second = vec![
+            PerDcomp::sample("222", 0.0),
+            PerDcomp::sample("111", 0.0),
+            PerDcomp::sample("999", 0.0),
+        ];
+        assert_eq!(annotations.apply(&mut second), 2);
+
+        assert_eq!(second[1].analise.as_deref(), Some("Crédito conferido"));
+        assert_eq!(second[1].responsavel, None);
+        assert_eq!(second[1].status_interno.as_deref(), Some("Concluído"));
+        assert_eq!(second[2].analise.as_deref(), Some("Arquivada"));
+        assert!(Annotation::of(&second[0]).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+//! Data-quality pass over the parsed `PerDcomp` records.
+//!
+//! Each rule inspects the sorted dataset and reports its findings as [`Anomaly`]
+//! rows, which are written to the "Inconsistências" worksheet.
+
+use rust_xlsxwriter::{SerializeFieldOptions, XlsxSerialize};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    PERDCOMP_SHEET_NAME, SheetColumns,
+    excel::{MAX_NUMBER_OF_ROWS, format_sheet_name},
+    structures::{FORMAT, PerDcomp},
+};
+
+/// Name of the worksheet that receives the findings.
+pub const ANOMALY_SHEET_NAME: &str = "Inconsistências";
+
+/// How serious a finding is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Severity {
+    #[serde(rename = "Alta")]
+    High,
+    #[serde(rename = "Média")]
+    Medium,
+    #[serde(rename = "Baixa")]
+    Low,
+}
+
+/// A single finding of the data-quality pass.
+///
+/// Its workbook columns and value formats are those of [`Anomaly::sheet_columns`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Anomaly {
+    #[serde(rename = "Regra")]
+    pub rule_id: String,
+
+    #[serde(rename = "Severidade")]
+    pub severity: Severity,
+
+    #[serde(rename = "Descrição")]
+    pub description: String,
+
+    #[serde(rename = "PER/DCOMP")]
+    pub per_dcomp: Option<String>,
+
+    /// Excel rows of the affected records in the PERDComp worksheets. Rows
+    /// past the first worksheet carry its name, as in "PERDComp 2!5".
+    #[serde(rename = "Linhas")]
+    pub rows: String,
+}
+
+impl Anomaly {
+    /// The workbook columns, with their value formats.
+    pub fn sheet_columns() -> SheetColumns {
+        let fields = [
+            ("Regra", Some(FORMAT.bold_centered.clone())),
+            ("Severidade", Some(FORMAT.centered.clone())),
+            ("Descrição", None),
+            ("PER/DCOMP", Some(FORMAT.centered.clone())),
+            ("Linhas", None),
+        ];
+        SheetColumns::new("Anomaly", &FORMAT.header, &fields)
+    }
+
+    fn new(rule_id: &str, severity: Severity, description: String, indices: &[usize]) -> Self {
+        Self {
+            rule_id: rule_id.to_string(),
+            severity,
+            description,
+            per_dcomp: None,
+            rows: format_rows(indices),
+        }
+    }
+
+    fn with_per_dcomp(mut self, per_dcomp: Option<&String>) -> Self {
+        self.per_dcomp = per_dcomp.cloned();
+        self
+    }
+}
+
+impl XlsxSerialize for Anomaly {
+    fn to_serialize_field_options() -> SerializeFieldOptions {
+        Anomaly::sheet_columns().field_options().clone()
+    }
+}
+
+/// Runs every data-quality rule over `data` and returns the findings,
+/// ordered by severity and then by rule id.
+///
+/// `data` must be in the same order as the main worksheet, since the
+/// reported rows are positions in that sheet.
+pub fn detect_anomalies(data: &[PerDcomp]) -> Vec<Anomaly> {
+    let mut anomalies: Vec<Anomaly> = Vec::new();
+
+    anomalies.extend(duplicated_documents(data));
+    anomalies.extend(multiple_active_documents(data));
+    anomalies.extend(per_exceeds_credit(data));
+
+    anomalies.sort_by(|a, b| (a.severity, &a.rule_id).cmp(&(b.severity, &b.rule_id)));
+    anomalies
+}
+
+/// R01: same PER/DCOMP number with different values.
+///
+/// R02: same PER/DCOMP number repeated with identical values.
+///
+/// Only the document columns are compared, so the origin and the annotations
+/// of the repeated records may differ.
+fn duplicated_documents(data: &[PerDcomp]) -> Vec<Anomaly> {
+    let mut groups: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+
+    for (index, perdcomp) in data.iter().enumerate() {
+        if let Some(number) = non_empty(perdcomp.per_dcomp.as_ref()) {
+            groups.entry(number).or_default().push(index);
+        }
+    }
+
+    groups
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .map(|indices| {
+            let first = &data[indices[0]];
+            let cells = first.document_cells();
+            let conflicting = indices[1..]
+                .iter()
+                .any(|&i| data[i].document_cells() != cells);
+            let count = indices.len();
+
+            let anomaly = if conflicting {
+                Anomaly::new(
+                    "R01",
+                    Severity::High,
+                    format!("PER/DCOMP informado {count} vezes com valores divergentes"),
+                    &indices,
+                )
+            } else {
+                Anomaly::new(
+                    "R02",
+                    Severity::Low,
+                    format!("PER/DCOMP informado {count} vezes com valores idênticos"),
+                    &indices,
+                )
+            };
+
+            anomaly.with_per_dcomp(first.per_dcomp.as_ref())
+        })
+        .collect()
+}
+
+/// R03: several active documents with credit statement for the same credit and period.
+fn multiple_active_documents(data: &[PerDcomp]) -> Vec<Anomaly> {
+    type CreditKey<'a> = (&'a str, &'a str, &'a str, Option<u32>);
+
+    let mut groups: BTreeMap<CreditKey, Vec<usize>> = BTreeMap::new();
+
+    for (index, perdcomp) in data.iter().enumerate() {
+        if perdcomp.per_ativo_com_credito != Some(true) {
+            continue;
+        }
+
+        let holder = non_empty(perdcomp.cnpj_detentor_do_credito.as_ref())
+            .or_else(|| non_empty(perdcomp.cnpj_declarante.as_ref()))
+            .unwrap_or_default();
+        let credit = perdcomp
+            .codigo_do_credito
+            .as_deref()
+            .or(perdcomp.tipo_do_credito.as_deref())
+            .unwrap_or_default();
+        let period = perdcomp
+            .trimestre_de_apuracao
+            .as_deref()
+            .unwrap_or_default();
+
+        groups
+            .entry((holder, credit, period, perdcomp.ano))
+            .or_default()
+            .push(index);
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, indices)| indices.len() > 1)
+        .map(|((holder, credit, period, ano), indices)| {
+            let year = ano.map(|a| a.to_string()).unwrap_or_default();
+            Anomaly::new(
+                "R03",
+                Severity::Medium,
+                format!(
+                    "{} documentos ativos para o mesmo crédito: {holder} | {credit} | {period} {year}",
+                    indices.len()
+                ),
+                &indices,
+            )
+        })
+        .collect()
+}
+
+/// R04: amount requested greater than the total credit.
+fn per_exceeds_credit(data: &[PerDcomp]) -> Vec<Anomaly> {
+    data.iter()
+        .enumerate()
+        .filter(|(_, p)| p.valor_do_per > p.valor_total_do_credito)
+        .map(|(index, p)| {
+            Anomaly::new(
+                "R04",
+                Severity::High,
+                format!(
+                    "Valor do PER ({:.2}) maior que o Valor Total do Crédito ({:.2})",
+                    p.valor_do_per, p.valor_total_do_credito
+                ),
+                &[index],
+            )
+            .with_per_dcomp(p.per_dcomp.as_ref())
+        })
+        .collect()
+}
+
+/// Converts record positions to Excel rows (the header occupies row 1).
+///
+/// Each worksheet holds [`MAX_NUMBER_OF_ROWS`] records, so a position past
+/// the first one is written with the name of its worksheet.
+fn format_rows(indices: &[usize]) -> String {
+    indices
+        .iter()
+        .map(|index| {
+            let sheet = index / MAX_NUMBER_OF_ROWS + 1;
+            let row = index % MAX_NUMBER_OF_ROWS + 2;
+            match sheet {
+                1 => row.to_string(),
+                _ => format!("{}!{row}", format_sheet_name(PERDCOMP_SHEET_NAME, sheet)),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn non_empty(value: Option<&String>) -> Option<&str> {
+    value.map(|s| s.trim()).filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests_anomalies {
+    use super::*;
+    use crate::Origem;
+
+    #[test]
+    fn detects_conflicting_and_identical_duplicates() {
+        let data = vec![
+            PerDcomp::sample("111", 50.0).with_credito(100.0),
+            PerDcomp::sample("111", 60.0).with_credito(100.0),
+            PerDcomp::sample("222", 50.0).with_credito(100.0),
+            PerDcomp::sample("222", 50.0).with_credito(100.0),
+        ];
+
+        let anomalies = detect_anomalies(&data);
+
+        assert_eq!(anomalies.len(), 2);
+        assert_eq!(anomalies[0].rule_id, "R01");
+        assert_eq!(anomalies[0].rows, "2, 3");
+        assert_eq!(anomalies[1].rule_id, "R02");
+        assert_eq!(anomalies[1].severity, Severity::Low);
+        assert_eq!(anomalies[1].rows, "4, 5");
+    }
+
+    #[test]
+    fn duplicates_differing_only_in_origin_are_identical() {
+        let mut a = PerDcomp::sample("111", 50.0).with_credito(100.0);
+        let mut b = PerDcomp::sample("111", 50.0).with_credito(100.0);
+        a.origem = Some(Origem {
+            file: "in.csv".into(),
+            line: 2,
+        });
+        b.origem = Some(Origem {
+            file: "in.csv".into(),
+            line: 3,
+        });
+        b.analise = Some("Conferido".to_string());
+
+        let anomalies = detect_anomalies(&[a, b]);
+
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].rule_id, "R02");
+    }
+
+    #[test]
+    fn detects_multiple_active_documents() {
+        let mut a = PerDcomp::sample("111", 50.0).with_credito(100.0);
+        let mut b = PerDcomp::sample("222", 50.0).with_credito(100.0);
+        let mut c = PerDcomp::sample("333", 50.0).with_credito(100.0);
+        a.per_ativo_com_credito = Some(true);
+        b.per_ativo_com_credito = Some(true);
+        c.per_ativo_com_credito = Some(false);
+
+        let anomalies = detect_anomalies(&[a, b, c]);
+
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].rule_id, "R03");
+        assert_eq!(anomalies[0].rows, "2, 3");
+    }
+
+    #[test]
+    fn detects_per_greater_than_credit() {
+        let data = vec![
+            PerDcomp::sample("111", 100.0).with_credito(100.0),
+            PerDcomp::sample("222", 100.01).with_credito(100.0),
+        ];
+
+        let anomalies = detect_anomalies(&data);
+
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].rule_id, "R04");
+        assert_eq!(anomalies[0].per_dcomp, Some("222".to_string()));
+        assert_eq!(anomalies[0].rows, "3");
+    }
+
+    #[test]
+    fn rows_past_the_first_sheet_name_their_sheet() {
+        let rows = format_rows(&[0, MAX_NUMBER_OF_ROWS - 1, MAX_NUMBER_OF_ROWS + 3]);
+        assert_eq!(rows, format!("2, {}, PERDComp 2!5", MAX_NUMBER_OF_ROWS + 1));
+    }
+}
@@ -1,12 +1,14 @@
-use crate::MyResult;
+use crate::{Config, FilterRule, LogFormat, MyResult, OutputFormat, TextEncoding};
 use clap::{
-    ArgAction, Parser,
+    ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand,
     builder::{
         Styles,
         styling::{AnsiColor, Effects},
     },
+    error::ErrorKind,
 };
-use std::path::PathBuf;
+use log::LevelFilter;
+use std::{collections::BTreeMap, path::PathBuf};
 
 /// Custom Clap styling to mimic a beautiful colored help menu.
 fn get_styles() -> Styles {
@@ -22,10 +24,9 @@ fn get_styles() -> Styles {
 }
 
 /*
-https://www.perrygeo.com/getting-started-with-application-configuration-in-rust.html
-https://stackoverflow.com/questions/55133351/is-there-a-way-to-get-clap-to-use-default-values-from-a-file
-https://rust-cli.github.io/book/in-depth/config-files.html
-https://docs.rs/confy/latest/confy/index.html
+Persistent defaults come from TOML files, see the `config` module:
+the user file, then `perdcomp.toml` (or `--config`), then the
+environment variables, then the command line.
 
 How to Set Environment Variables in Linux:
 export DELIMITER_CSV=';'
@@ -48,7 +49,7 @@ const APPLET_TEMPLATE: &str = "\
 {all-args}
 {after-help}";
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone, Default)]
 #[command(
     // Read from `Cargo.toml`
     author, version, about,
@@ -58,6 +59,35 @@ const APPLET_TEMPLATE: &str = "\
     styles=get_styles(),
 )]
 pub struct Arguments {
+    #[command(flatten, next_help_heading = "Input")]
+    pub input: InputArgs,
+
+    #[command(flatten, next_help_heading = "Conversion")]
+    pub convert: ConvertArgs,
+
+    #[command(flatten, next_help_heading = "Logging")]
+    pub log: LogArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Options shared by every subcommand: the input files and how to read them.
+#[derive(Args, Debug, Clone)]
+pub struct InputArgs {
+    /// Set the TOML configuration file, instead of `perdcomp.toml`.
+    ///
+    /// Its settings replace the ones of the user configuration file and are
+    /// replaced by the environment variables and the command line.
+    /// See `config show`.
+    #[arg(
+        long("config"),
+        env("PERDCOMP_CONFIG"),
+        required = false,
+        global = true
+    )]
+    pub config: Option<PathBuf>,
+
     /// Set the field delimiter to use when parsing CSV.
     ///
     /// The default is b','.
@@ -66,10 +96,116 @@ pub struct Arguments {
         long,
         env("DELIMITER_CSV"),
         required = false,
-        default_value_t = ','
+        default_value_t = ',',
+        global = true
     )]
     pub delimiter: char,
 
+    /// Set the text encoding of the CSV inputs.
+    ///
+    /// `auto` reads each line as UTF-8 or, failing that, as WINDOWS-1252.
+    #[arg(long("encoding"), value_enum, default_value_t = TextEncoding::Auto, global = true)]
+    pub encoding: TextEncoding,
+
+    /// Convert any CSV file, without the PER/DCOMP columns.
+    ///
+    /// Each file is written to `<name>.xlsx` next to it, or to the output
+    /// file name (suffixed with `_<name>` when there are several files).
+    /// Its columns keep the file order.
+    /// The type of each column (integer, number, date, Sim/Não or text) is
+    /// inferred from all of its values.
+    #[arg(short('g'), long("generic"), default_value_t = false, action=ArgAction::SetTrue, global = true)]
+    pub generic: bool,
+
+    /// Extra CSV headers and their PER/DCOMP columns, set in the configuration file only.
+    #[arg(skip)]
+    pub mapping: BTreeMap<String, String>,
+
+    /// Set the csv file paths.
+    ///
+    /// Several files are merged into one dataset, e.g. `-p 2022.csv 2023.csv`.
+    /// An `.xlsx` file is read back from its worksheets whose headers match
+    /// the PER/DCOMP columns, such as a workbook written by this tool.
+    /// Required, unless set in the configuration file.
+    #[arg(short('p'), long("path"), num_args = 1.., global = true)]
+    pub path: Vec<PathBuf>,
+}
+
+/// Options of the conversion pipeline: companion files, filters and outputs.
+#[derive(Args, Debug, Clone)]
+pub struct ConvertArgs {
+    /// Set the JSON sidecar file with the auditor annotations.
+    ///
+    /// The "Análise", "Responsável" and "Status interno" columns of the
+    /// previous `perdcomp.xlsx` and of this file are merged into the new
+    /// workbook, by PER/DCOMP, and the file is updated.
+    /// The default is `perdcomp.annotations.json`.
+    #[arg(long("annotations"), required = false, global = true)]
+    pub annotations: Option<PathBuf>,
+
+    /// Add a "Gráficos" worksheet with summary charts.
+    ///
+    /// Credit and PER totals per quarter, documents by situação and the
+    /// cumulative PER amount by transmission month.
+    #[arg(long("charts"), default_value_t = false, action=ArgAction::SetTrue, global = true)]
+    pub charts: bool,
+
+    /// Set the JSON file with per-column width overrides.
+    ///
+    /// Each entry gives a column header, optional `min`, `max` or preset
+    /// `width` (in digit widths) and whether its values are `bold`. Replaces
+    /// the embedded overrides.
+    #[arg(long("column-widths"), required = false, global = true)]
+    pub column_widths: Option<PathBuf>,
+
+    /// Set the JSON file with the catalogue of canonical credit types.
+    ///
+    /// Replaces the embedded catalogue, which maps the `Tipo de Crédito`
+    /// wordings to codes such as `COFINS_NC_EXP`.
+    #[arg(long("credit-catalog"), required = false, global = true)]
+    pub credit_catalog: Option<PathBuf>,
+
+    /// Set the CSV file path with the debts compensated by each DCOMP.
+    ///
+    /// Adds a "Débitos" worksheet and the total compensated debt per DCOMP
+    /// to the main worksheet.
+    #[arg(long("debitos"), required = false, global = true)]
+    pub debitos: Option<PathBuf>,
+
+    /// Set the EFD-Contribuições credit ledger CSV file path.
+    ///
+    /// Columns: CNPJ, Período, Tipo de Crédito and Valor do Crédito.
+    /// Adds a worksheet comparing the credits declared in EFD with the
+    /// amounts claimed in PER/DCOMP.
+    #[arg(long("efd"), required = false, global = true)]
+    pub efd: Option<PathBuf>,
+
+    /// Row filters, set in the configuration file only.
+    #[arg(skip)]
+    pub filters: Vec<FilterRule>,
+
+    /// Set the output formats, separated by commas.
+    ///
+    /// Each format is written to `perdcomp.<extension>`.
+    /// Example: `--format xlsx,csv,ndjson,parquet`
+    #[arg(
+        short('f'),
+        long("format"),
+        value_enum,
+        value_delimiter = ',',
+        default_value = "xlsx",
+        global = true
+    )]
+    pub format: Vec<OutputFormat>,
+
+    /// Set the JSON file with the conditional formatting rules.
+    ///
+    /// Replaces the embedded rules, which highlight the situação, the
+    /// documents pending action and the PER amounts. Each rule maps a column
+    /// header, an operator and a value to a format name (e.g. `alert`).
+    #[arg(long("format-rules"), required = false, global = true)]
+    pub format_rules: Option<PathBuf>,
+
     /// Prevent the temporary file from being deleted.
     ///
     /// And then, rename the temporary file to “temporary.csv”.
@@ -77,32 +213,235 @@ pub struct Arguments {
     /// <https://docs.rs/tempfile/latest/tempfile/struct.TempPath.html#method.keep>
     ///
     /// <https://docs.rs/clap/latest/clap/enum.ArgAction.html>
-    #[arg(short('k'), long("keep"), default_value_t = false, action=ArgAction::SetTrue)]
+    #[arg(short('k'), long("keep"), default_value_t = false, action=ArgAction::SetTrue, global = true)]
     pub keep: bool,
 
-    /// Set the csv file path.
-    #[arg(short('p'), long("path"), required = true)]
-    pub path: PathBuf,
+    /// Write the "Origem" column with the source file and line of each record.
+    ///
+    /// Without this flag, the column is left out of every output format.
+    #[arg(long("origin"), default_value_t = false, action=ArgAction::SetTrue, global = true)]
+    pub origin: bool,
+
+    /// Output path template, set in the configuration file only.
+    #[arg(skip)]
+    pub output: Option<String>,
 
     /// Remove columns that are empty in all rows.
-    #[arg(short('r'), long("remove-empty"), default_value_t = false, action=ArgAction::SetTrue)]
+    #[arg(short('r'), long("remove-empty"), default_value_t = false, action=ArgAction::SetTrue, global = true)]
     pub remove_empty: bool,
 
+    /// Sort keys, set in the configuration file only.
+    #[arg(skip)]
+    pub sort: Vec<String>,
+
+    /// Write the workbook while the CSV is parsed, with bounded memory.
+    ///
+    /// Rows go straight to constant memory worksheets, in file order (no
+    /// sorting). Column widths come from the first 10,000 records and the
+    /// preset widths of `--column-widths`. Only the PERDComp worksheet is
+    /// written: options that need the whole dataset are ignored.
+    #[arg(short('s'), long("stream"), default_value_t = false, action=ArgAction::SetTrue, global = true)]
+    pub stream: bool,
+
+    /// Excel table style, set in the configuration file only.
+    #[arg(skip)]
+    pub theme: Option<String>,
+
+    /// Add a total row to the PERDComp table.
+    ///
+    /// Shows the number of documents and the sum of each money column,
+    /// updated by Excel when the table is filtered.
+    #[arg(long("totals"), default_value_t = false, action=ArgAction::SetTrue, global = true)]
+    pub totals: bool,
+}
+
+/// Options of the diagnostics on stderr.
+#[derive(Args, Debug, Clone, Default)]
+pub struct LogArgs {
+    /// Set the layout of the log lines on stderr.
+    ///
+    /// `json` writes one object per line, for batch schedulers.
+    #[arg(long("log-format"), value_enum, default_value_t = LogFormat::Text, global = true)]
+    pub log_format: LogFormat,
+
+    /// Only log errors.
+    #[arg(short('q'), long("quiet"), default_value_t = false, action=ArgAction::SetTrue, conflicts_with = "verbose", global = true)]
+    pub quiet: bool,
+
     /// Show total execution time.
-    #[arg(short('t'), long("time"), default_value_t = false)]
+    #[arg(short('t'), long("time"), default_value_t = false, global = true)]
     pub time: bool,
 
-    /// Show intermediate runtime messages.
+    /// Log more details: `-v` adds the debug messages (column names,
+    /// inferred types, column profile), `-vv` also traces the records,
+    /// such as the first 50 lines.
+    #[arg(short('v'), long("verbose"), action=ArgAction::Count, global = true)]
+    pub verbose: u8,
+}
+
+/// Actions over the inputs; `convert` when none is given.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Convert the inputs to the output formats (the default).
+    Convert,
+
+    /// Parse every record and list the invalid ones, without writing any file.
+    ///
+    /// Exits with an error when a record is invalid.
+    Validate,
+
+    /// List the headers of each input and the PER/DCOMP column each one fills.
+    ///
+    /// Also lists the columns missing from each input.
+    InspectHeaders,
+
+    /// Print the number of records, the totals and the documents by situação
+    /// and by quarter, after the filters.
+    Summary,
+
+    /// Compare the inputs with a previous export, by PER/DCOMP number.
+    ///
+    /// Example: perdcomp_csv_to_xlsx diff perdcomp.xlsx -p 2024-06.csv
+    Diff(DiffArgs),
+
+    /// Run a SQL statement over the parsed records.
+    ///
+    /// The records are loaded into an in-memory table named `perdcomp`,
+    /// with one column per field (e.g. `cnpj_declarante`, `valor_do_per`).
     ///
-    /// Display up to the first 50 lines.
-    #[arg(short('v'), long("verbose"), default_value_t = false)]
-    pub verbose: bool,
+    /// Example: perdcomp_csv_to_xlsx query "SELECT situacao, COUNT(*) FROM perdcomp GROUP BY situacao" -p file.csv
+    Query(QueryArgs),
+
+    /// Convert each CSV file dropped into a folder to a workbook.
+    ///
+    /// A file is converted once its size has been stable for the debounce
+    /// interval, to `<name>.xlsx` next to it or in the output folder.
+    /// A failure is written to `<name>.error.txt`. Files whose content was
+    /// already converted are skipped, even after a restart.
+    ///
+    /// Example: perdcomp_csv_to_xlsx watch ~/SCC --output-dir ~/SCC/xlsx
+    Watch(WatchArgs),
+
+    /// Inspect the configuration files.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+/// Actions of the `config` subcommand.
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Print the effective settings, merged from the configuration files,
+    /// the environment variables and the command line.
+    Show,
+}
+
+/// Arguments of the `diff` subcommand.
+#[derive(Args, Debug, Clone)]
+pub struct DiffArgs {
+    /// The previous CSV file or workbook.
+    pub previous: PathBuf,
+}
+
+/// Arguments of the `watch` subcommand.
+#[derive(Args, Debug, Clone)]
+pub struct WatchArgs {
+    /// The folder to watch.
+    pub dir: PathBuf,
+
+    /// Seconds a file size must stay the same before the file is converted.
+    #[arg(long("debounce"), default_value_t = 5, value_name = "SECONDS")]
+    pub debounce: u64,
+
+    /// Write the workbooks to this folder instead of next to each file.
+    #[arg(long("output-dir"), value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+}
+
+/// Arguments of the `query` subcommand.
+#[derive(Args, Debug, Clone)]
+pub struct QueryArgs {
+    /// The SQL statement.
+    pub sql: String,
+
+    /// Write the result to an `.xlsx` or `.csv` file instead of printing it.
+    #[arg(short('o'), long("output"), required = false)]
+    pub output: Option<PathBuf>,
+}
+
+impl Default for InputArgs {
+    /// The defaults of the command line, without input files.
+    fn default() -> Self {
+        InputArgs {
+            config: None,
+            delimiter: ',',
+            encoding: TextEncoding::Auto,
+            generic: false,
+            mapping: BTreeMap::new(),
+            path: Vec::new(),
+        }
+    }
+}
+
+impl Default for ConvertArgs {
+    /// The defaults of the command line: an `.xlsx` output, sorted by year and quarter.
+    fn default() -> Self {
+        ConvertArgs {
+            annotations: None,
+            charts: false,
+            column_widths: None,
+            credit_catalog: None,
+            debitos: None,
+            efd: None,
+            filters: Vec::new(),
+            format: vec![OutputFormat::Xlsx],
+            format_rules: None,
+            keep: false,
+            origin: false,
+            output: None,
+            remove_empty: false,
+            sort: Vec::new(),
+            stream: false,
+            theme: None,
+            totals: false,
+        }
+    }
 }
 
 impl Arguments {
+    /// The most detailed log level to show: warnings and information by default.
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.log.quiet, self.log.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+
+    /// Whether the files are converted: without a subcommand or with `convert`.
+    pub fn is_conversion(&self) -> bool {
+        matches!(self.command, None | Some(Command::Convert))
+    }
+
     /// Build Arguments struct
+    ///
+    /// The options not given on the command line or by an environment
+    /// variable come from the configuration files.
     pub fn build() -> MyResult<Arguments> {
-        let args: Arguments = Arguments::parse();
+        let matches = Arguments::command().get_matches();
+        let mut args = Arguments::from_arg_matches(&matches)?;
+        Config::discover(args.input.config.as_deref())?.apply(&mut args, &matches);
+
+        let needs_path = !matches!(args.command, Some(Command::Config(_) | Command::Watch(_)));
+        if args.input.path.is_empty() && needs_path {
+            Arguments::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "the following required arguments were not provided:\n  --path <PATH>...",
+                )
+                .exit();
+        }
+
         Ok(args)
     }
 }
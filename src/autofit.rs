@@ -0,0 +1,379 @@
+//! Column auto-fit based on Calibri font metrics.
+//!
+//! Widths are measured with the advance widths of Calibri and Calibri Bold,
+//! in font design units (2048 per em), and expressed in Excel's column width
+//! unit: the width of one digit of the default font ([`FONT_SIZE`]).
+//! Headers are fitted to the lines that the 62pt header row can hold.
+//!
+//! Per-column overrides (minimum, maximum, preset width and bold values) come
+//! from a JSON file with the same layout as `src/data/column_widths.json`.
+//! The bold and date columns of a worksheet are otherwise known from its
+//! value formats (see [`SheetColumns`](crate::SheetColumns)).
+
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::{
+    MyResult, WithPath, normalize_text,
+    structures::{FONT_SIZE, HEADER_FONT_SIZE},
+};
+
+/// Overrides embedded in the binary, used when no file is given.
+const EMBEDDED_WIDTHS: &str = include_str!("data/column_widths.json");
+
+/// Height of the header row, in points.
+pub const HEADER_ROW_HEIGHT: f64 = 62.0;
+
+/// Calibri line height, as a multiple of the font size.
+const LINE_SPACING: f64 = 1.22;
+
+/// Advance width of the Calibri digits, in font units. Digits have the same
+/// width in the regular and bold faces.
+const DIGIT_UNITS: f64 = 1038.0;
+
+/// Horizontal cell margins, in digit widths.
+const CELL_PADDING: f64 = 1.5;
+
+/// Default bounds, in digit widths.
+const MIN_WIDTH: f64 = 8.0;
+const MAX_WIDTH: f64 = 100.0;
+
+/// Width of a checkbox cell, in digit widths.
+pub const CHECKBOX_WIDTH: f64 = 2.0;
+
+/// Returns the advance width of `c` in Calibri font units.
+///
+/// Accented letters take the width of their base letter. Other characters
+/// outside the tables get the average lowercase width.
+fn glyph_units(c: char, bold: bool) -> f64 {
+    let c = base_letter(c);
+    let units = match (c, bold) {
+        ('0'..='9', _) => 1038,
+        (' ', _) => 463,
+        ('!', _) => 544,
+        ('"', _) => 821,
+        ('#' | '$' | '*' | '+' | '<' | '=' | '>' | '^' | '_' | '~', _) => 1038,
+        ('%', _) => 1470,
+        ('&', _) => 1397,
+        ('\'', _) => 452,
+        ('(' | ')', _) => 621,
+        (',', false) => 511,
+        (',', true) => 524,
+        ('-', _) => 627,
+        ('.', false) => 517,
+        ('.', true) => 546,
+        ('/' | '\\', _) => 791,
+        (':' | ';', _) => 548,
+        ('?', _) => 950,
+        ('@', _) => 1823,
+        ('[' | ']', _) => 627,
+        ('`', _) => 596,
+        ('{' | '}', _) => 653,
+        ('|', _) => 943,
+        ('º' | 'ª' | '°', _) => 700,
+
+        ('a', false) => 981,
+        ('b' | 'd' | 'h' | 'n' | 'p' | 'q' | 'u', false) => 1076,
+        ('c', false) => 866,
+        ('e', false) => 1019,
+        ('f', false) => 625,
+        ('g', false) => 964,
+        ('i' | 'l', false) => 470,
+        ('j', false) => 490,
+        ('k', false) => 931,
+        ('m', false) => 1636,
+        ('o', false) => 1080,
+        ('r', false) => 714,
+        ('s', false) => 801,
+        ('t', false) => 686,
+        ('v', false) => 925,
+        ('w', false) => 1464,
+        ('x', false) => 887,
+        ('y', false) => 927,
+        ('z', false) => 809,
+
+        ('a', true) => 1011,
+        ('b' | 'd' | 'h' | 'n' | 'p' | 'q' | 'u', true) => 1096,
+        ('c', true) => 857,
+        ('e', true) => 1032,
+        ('f', true) => 648,
+        ('g', true) => 964,
+        ('i' | 'l', true) => 503,
+        ('j', true) => 548,
+        ('k', true) => 986,
+        ('m', true) => 1664,
+        ('o', true) => 1100,
+        ('r', true) => 728,
+        ('s', true) => 817,
+        ('t', true) => 711,
+        ('v', true) => 979,
+        ('w', true) => 1526,
+        ('x', true) => 934,
+        ('y', true) => 981,
+        ('z', true) => 815,
+
+        ('A', false) => 1185,
+        ('B', false) => 1114,
+        ('C', false) => 1092,
+        ('D', false) => 1260,
+        ('E', false) => 1000,
+        ('F', false) => 941,
+        ('G', false) => 1292,
+        ('H', false) => 1276,
+        ('I', false) => 516,
+        ('J', false) => 653,
+        ('K', false) => 1064,
+        ('L', false) => 861,
+        ('M', false) => 1751,
+        ('N', false) => 1322,
+        ('O', false) => 1356,
+        ('P', false) => 1058,
+        ('Q', false) => 1378,
+        ('R', false) => 1112,
+        ('S', false) => 941,
+        ('T', false) => 998,
+        ('U', false) => 1314,
+        ('V', false) => 1162,
+        ('W', false) => 1822,
+        ('X', false) => 1063,
+        ('Y', false) => 998,
+        ('Z', false) => 959,
+
+        ('A', true) => 1225,
+        ('B', true) => 1149,
+        ('C', true) => 1093,
+        ('D', true) => 1290,
+        ('E', true) => 1007,
+        ('F', true) => 944,
+        ('G', true) => 1296,
+        ('H', true) => 1296,
+        ('I', true) => 546,
+        ('J', true) => 692,
+        ('K', true) => 1097,
+        ('L', true) => 861,
+        ('M', true) => 1751,
+        ('N', true) => 1326,
+        ('O', true) => 1373,
+        ('P', true) => 1082,
+        ('Q', true) => 1391,
+        ('R', true) => 1142,
+        ('S', true) => 941,
+        ('T', true) => 1008,
+        ('U', true) => 1327,
+        ('V', true) => 1212,
+        ('W', true) => 1843,
+        ('X', true) => 1114,
+        ('Y', true) => 1051,
+        ('Z', true) => 968,
+
+        _ => 1000,
+    };
+    units as f64
+}
+
+/// Maps the accented letters used in Portuguese to their base letter.
+fn base_letter(c: char) -> char {
+    match c {
+        'á' | 'à' | 'â' | 'ã' | 'ä' => 'a',
+        'é' | 'ê' | 'è' | 'ë' => 'e',
+        'í' | 'ì' | 'î' | 'ï' => 'i',
+        'ó' | 'ò' | 'ô' | 'õ' | 'ö' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' => 'u',
+        'ç' => 'c',
+        'Á' | 'À' | 'Â' | 'Ã' | 'Ä' => 'A',
+        'É' | 'Ê' | 'È' | 'Ë' => 'E',
+        'Í' | 'Ì' | 'Î' | 'Ï' => 'I',
+        'Ó' | 'Ò' | 'Ô' | 'Õ' | 'Ö' => 'O',
+        'Ú' | 'Ù' | 'Û' | 'Ü' => 'U',
+        'Ç' => 'C',
+        _ => c,
+    }
+}
+
+/// Width of `text` written in Calibri at `font_size`, in digit widths of the
+/// default font.
+///
+/// ```
+/// use perdcomp_csv_to_xlsx::text_width;
+///
+/// assert_eq!(text_width("0000", 14.0, false), 4.0);
+/// assert!(text_width("Motivo", 14.0, true) > text_width("Motivo", 14.0, false));
+/// ```
+pub fn text_width(text: &str, font_size: f64, bold: bool) -> f64 {
+    let units: f64 = text.chars().map(|c| glyph_units(c, bold)).sum();
+    units / DIGIT_UNITS * font_size / FONT_SIZE
+}
+
+/// Renders a number as Excel shows it with the `#,##0.00` format in pt-BR.
+///
+/// ```
+/// use perdcomp_csv_to_xlsx::money_text;
+///
+/// assert_eq!(money_text(-1234567.891), "-1.234.567,89");
+/// assert_eq!(money_text(0.5), "0,50");
+/// ```
+pub fn money_text(value: f64) -> String {
+    let text = format!("{:.2}", value.abs());
+    let (integer, decimals) = text.split_once('.').unwrap_or((&text, "00"));
+
+    let mut grouped = String::new();
+    for (index, digit) in integer.chars().enumerate() {
+        if index > 0 && (integer.len() - index) % 3 == 0 {
+            grouped.push('.');
+        }
+        grouped.push(digit);
+    }
+
+    let sign = if value < 0.0 && text != "0.00" {
+        "-"
+    } else {
+        ""
+    };
+    format!("{sign}{grouped},{decimals}")
+}
+
+/// Smallest width at which the bold header wraps into the header row.
+///
+/// The header is wrapped at spaces, greedily, into as many lines as fit in
+/// [`HEADER_ROW_HEIGHT`]. The width is never below the longest word.
+pub fn header_width(header: &str) -> f64 {
+    let max_lines = (HEADER_ROW_HEIGHT / (HEADER_FONT_SIZE * LINE_SPACING)).floor() as usize;
+    let words: Vec<f64> = header
+        .split_whitespace()
+        .map(|word| text_width(word, HEADER_FONT_SIZE, true))
+        .collect();
+    let space = text_width(" ", HEADER_FONT_SIZE, true);
+
+    let longest = words.iter().copied().fold(0.0, f64::max);
+    let total = words.iter().sum::<f64>() + space * words.len().saturating_sub(1) as f64;
+
+    let mut width = longest.max(total / max_lines.max(1) as f64);
+    while width < total && wrapped_lines(&words, space, width) > max_lines {
+        width += 0.25;
+    }
+    width.min(total)
+}
+
+fn wrapped_lines(words: &[f64], space: f64, width: f64) -> usize {
+    let mut lines = 0;
+    let mut current: Option<f64> = None;
+
+    for &word in words {
+        current = match current {
+            Some(line) if line + space + word <= width => Some(line + space + word),
+            Some(_) => {
+                lines += 1;
+                Some(word)
+            }
+            None => Some(word),
+        };
+    }
+
+    lines + usize::from(current.is_some())
+}
+
+/// Width overrides for one column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnWidth {
+    /// Column header, compared with [`normalize_text`].
+    pub column: String,
+    /// Minimum width, in digit widths.
+    #[serde(default)]
+    pub min: Option<f64>,
+    /// Maximum width, in digit widths. Longer values wrap or are cut.
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Values are written in bold.
+    #[serde(default)]
+    pub bold: bool,
+    /// Preset width, in digit widths, used instead of auto-fit.
+    #[serde(default)]
+    pub width: Option<f64>,
+}
+
+/// Per-column width overrides.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnWidths {
+    pub columns: Vec<ColumnWidth>,
+}
+
+impl ColumnWidths {
+    /// Loads the overrides from a JSON file, or the embedded ones if `path` is `None`.
+    pub fn load(path: Option<&Path>) -> MyResult<Self> {
+        let columns: Vec<ColumnWidth> = match path {
+            Some(path) => {
+                let json = fs::read_to_string(path).with_path(path)?;
+                serde_json::from_str(&json).with_path(path)?
+            }
+            None => serde_json::from_str(EMBEDDED_WIDTHS)?,
+        };
+
+        Ok(Self { columns })
+    }
+
+    /// Returns the overrides of the column with this header.
+    pub fn get(&self, header: &str) -> Option<&ColumnWidth> {
+        let target = normalize_text(header);
+        self.columns
+            .iter()
+            .find(|column| normalize_text(&column.column) == target)
+    }
+
+    /// Values of the column with this header are written in bold, by an override.
+    pub fn is_bold(&self, header: &str) -> bool {
+        self.get(header).is_some_and(|column| column.bold)
+    }
+
+    /// Final column width from the header and the widest value.
+    ///
+    /// A preset `width` wins over the measured values.
+    pub fn fit(&self, header: &str, max_value_width: f64) -> f64 {
+        if let Some(width) = self.get(header).and_then(|column| column.width) {
+            return width;
+        }
+
+        let (min, max) = match self.get(header) {
+            Some(column) => (
+                column.min.unwrap_or(MIN_WIDTH),
+                column.max.unwrap_or(MAX_WIDTH),
+            ),
+            None => (MIN_WIDTH, MAX_WIDTH),
+        };
+
+        let width = header_width(header).max(max_value_width) + CELL_PADDING;
+        (width.clamp(min, max.max(min)) * 4.0).ceil() / 4.0
+    }
+}
+
+impl Default for ColumnWidths {
+    fn default() -> Self {
+        Self::load(None).expect("embedded column widths must be valid")
+    }
+}
+
+#[cfg(test)]
+mod tests_autofit {
+    use super::*;
+
+    #[test]
+    fn headers_wrap_into_header_row() {
+        let header = "Valor Total do Pedido de Resssarcimento (PER)";
+        let width = header_width(header);
+
+        assert!(width < text_width(header, HEADER_FONT_SIZE, true));
+        assert!(width >= text_width("Resssarcimento", HEADER_FONT_SIZE, true));
+    }
+
+    #[test]
+    fn overrides_bound_the_width() {
+        let widths = ColumnWidths::default();
+        let long_text = "x".repeat(500);
+
+        let motivo = widths.fit("Motivo", text_width(&long_text, FONT_SIZE, false));
+        assert_eq!(motivo, 60.0);
+        assert_eq!(widths.fit("motivo", 0.0), 20.0);
+
+        assert!(!widths.is_bold("Motivo"));
+        assert_eq!(widths.fit("Ano", 4.0), MIN_WIDTH);
+    }
+}
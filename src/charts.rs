@@ -0,0 +1,249 @@
+//! Optional "Gráficos" worksheet with management charts.
+//!
+//! The charts read from small aggregated tables written on the same worksheet
+//! (the helper ranges), so they stay editable in Excel and never reference the
+//! full data sheets:
+//! - credit and PER totals per year and quarter (stacked columns);
+//! - documents by situação (pie);
+//! - cumulative PER amount by transmission month (line).
+
+use log::debug;
+use rust_xlsxwriter::{Chart, ChartDataLabel, ChartType, Workbook, Worksheet, XlsxError};
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use crate::{HEADER_ROW_HEIGHT, PerDcomp, excel::write_headers, structures::FORMAT};
+
+/// Name of the worksheet that receives the charts.
+pub const CHARTS_SHEET_NAME: &str = "Gráficos";
+
+/// Label used for documents without situação.
+const NO_SITUACAO: &str = "Sem Situação";
+
+/// Credit and PER totals of one quarter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarterTotals {
+    /// Category label, such as "2021 T1".
+    pub label: String,
+    pub credito: f64,
+    pub per: f64,
+}
+
+/// Sums `valor_total_do_credito` and `valor_do_per` per year and quarter.
+///
+/// Records whose period cannot be parsed are left out.
+pub fn quarter_totals(perdcomps: &[PerDcomp]) -> Vec<QuarterTotals> {
+    let mut groups: BTreeMap<(u32, u32), (f64, f64)> = BTreeMap::new();
+
+    for perdcomp in perdcomps {
+        if let Some(key) = perdcomp.quarter() {
+            let totals = groups.entry(key).or_default();
+            totals.0 += perdcomp.valor_total_do_credito;
+            totals.1 += perdcomp.valor_do_per;
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|((ano, trimestre), (credito, per))| QuarterTotals {
+            label: format!("{ano} T{trimestre}"),
+            credito,
+            per,
+        })
+        .collect()
+}
+
+/// Counts the documents of each situação, most frequent first.
+pub fn documents_by_situacao(perdcomps: &[PerDcomp]) -> Vec<(String, u32)> {
+    let mut counts: BTreeMap<String, u32> = BTreeMap::new();
+
+    for perdcomp in perdcomps {
+        let situacao = perdcomp
+            .situacao
+            .as_ref()
+            .map_or(NO_SITUACAO.to_string(), |situacao| situacao.to_string());
+        *counts.entry(situacao).or_default() += 1;
+    }
+
+    let mut counts: Vec<(String, u32)> = counts.into_iter().collect();
+    counts.sort_by_key(|(_, count)| Reverse(*count));
+    counts
+}
+
+/// Accumulates `valor_do_per` by transmission month ("AAAA-MM").
+///
+/// Records without transmission date are left out.
+pub fn cumulative_utilization(perdcomps: &[PerDcomp]) -> Vec<(String, f64)> {
+    let mut months: BTreeMap<String, f64> = BTreeMap::new();
+
+    for perdcomp in perdcomps {
+        if let Some(date) = perdcomp.data_da_transmissao {
+            *months.entry(date.format("%Y-%m").to_string()).or_default() += perdcomp.valor_do_per;
+        }
+    }
+
+    let mut total = 0.0;
+    months
+        .into_iter()
+        .map(|(month, value)| {
+            total += value;
+            (month, total)
+        })
+        .collect()
+}
+
+/// Appends the "Gráficos" worksheet to `workbook`.
+///
+/// Helper ranges occupy columns A to I; the charts are placed from column K.
+/// An empty dataset adds no worksheet.
+pub fn push_charts_worksheet(
+    workbook: &mut Workbook,
+    perdcomps: &[PerDcomp],
+) -> Result<(), XlsxError> {
+    if perdcomps.is_empty() {
+        return Ok(());
+    }
+
+    debug!("Populating worksheet '{CHARTS_SHEET_NAME}' with charts.");
+
+    let mut worksheet = Worksheet::new();
+    worksheet.set_name(CHARTS_SHEET_NAME)?;
+
+    // 1. Helper range: totals per quarter (columns A:C).
+    let quarters = quarter_totals(perdcomps);
+    write_headers(
+        &mut worksheet,
+        0,
+        0,
+        &["Trimestre", "Valor Total do Crédito", "Valor do PER"],
+    )?;
+    for (index, quarter) in quarters.iter().enumerate() {
+        let row = (index + 1) as u32;
+        worksheet.write_string_with_format(row, 0, &quarter.label, &FORMAT.centered)?;
+        worksheet.write_number_with_format(row, 1, quarter.credito, &FORMAT.currency)?;
+        worksheet.write_number_with_format(row, 2, quarter.per, &FORMAT.currency)?;
+    }
+
+    // 2. Helper range: documents by situação (columns E:F).
+    let situacoes = documents_by_situacao(perdcomps);
+    write_headers(&mut worksheet, 0, 4, &["Situação", "Documentos"])?;
+    for (index, (situacao, count)) in situacoes.iter().enumerate() {
+        let row = (index + 1) as u32;
+        worksheet.write_string(row, 4, situacao)?;
+        worksheet.write_number_with_format(row, 5, *count, &FORMAT.centered)?;
+    }
+
+    // 3. Helper range: cumulative utilization (columns H:I).
+    let months = cumulative_utilization(perdcomps);
+    write_headers(
+        &mut worksheet,
+        0,
+        7,
+        &["Mês de Transmissão", "Valor Acumulado"],
+    )?;
+    for (index, (month, total)) in months.iter().enumerate() {
+        let row = (index + 1) as u32;
+        worksheet.write_string_with_format(row, 7, month, &FORMAT.centered)?;
+        worksheet.write_number_with_format(row, 8, *total, &FORMAT.currency)?;
+    }
+
+    worksheet.set_row_height(0, HEADER_ROW_HEIGHT)?;
+    for (col, width) in [
+        (0, 14),
+        (1, 22),
+        (2, 22),
+        (4, 26),
+        (5, 14),
+        (7, 16),
+        (8, 22),
+    ] {
+        worksheet.set_column_width(col, width)?;
+    }
+
+    // 4. Charts, stacked vertically to the right of the helper ranges.
+    if !quarters.is_empty() {
+        let last_row = quarters.len() as u32;
+        let mut chart = Chart::new(ChartType::ColumnStacked);
+        for col in [1, 2] {
+            chart
+                .add_series()
+                .set_name((CHARTS_SHEET_NAME, 0, col))
+                .set_categories((CHARTS_SHEET_NAME, 1, 0, last_row, 0))
+                .set_values((CHARTS_SHEET_NAME, 1, col, last_row, col));
+        }
+        chart.title().set_name("Crédito e PER por Trimestre");
+        chart.y_axis().set_num_format("#,##0.00");
+        chart.set_width(900).set_height(420);
+        worksheet.insert_chart(1, 10, &chart)?;
+    }
+
+    if !situacoes.is_empty() {
+        let last_row = situacoes.len() as u32;
+        let mut chart = Chart::new(ChartType::Pie);
+        chart
+            .add_series()
+            .set_name((CHARTS_SHEET_NAME, 0, 5))
+            .set_categories((CHARTS_SHEET_NAME, 1, 4, last_row, 4))
+            .set_values((CHARTS_SHEET_NAME, 1, 5, last_row, 5))
+            .set_data_label(ChartDataLabel::new().show_percentage());
+        chart.title().set_name("Documentos por Situação");
+        chart.set_width(900).set_height(420);
+        worksheet.insert_chart(23, 10, &chart)?;
+    }
+
+    if !months.is_empty() {
+        let last_row = months.len() as u32;
+        let mut chart = Chart::new(ChartType::Line);
+        chart
+            .add_series()
+            .set_name((CHARTS_SHEET_NAME, 0, 8))
+            .set_categories((CHARTS_SHEET_NAME, 1, 7, last_row, 7))
+            .set_values((CHARTS_SHEET_NAME, 1, 8, last_row, 8));
+        chart.title().set_name("Utilização Acumulada");
+        chart.y_axis().set_num_format("#,##0.00");
+        chart.set_width(900).set_height(420);
+        worksheet.insert_chart(45, 10, &chart)?;
+    }
+
+    workbook.push_worksheet(worksheet);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests_charts {
+    use super::*;
+    use crate::Situacao;
+
+    #[test]
+    fn aggregates_helper_ranges() {
+        let mut perdcomps = vec![
+            PerDcomp::sample("1", 5.0)
+                .with_credito(5.0 * 2.0)
+                .with_period("2º TRIMESTRE", 2022)
+                .with_transmission(2022, 8, 10),
+            PerDcomp::sample("1", 10.0)
+                .with_credito(10.0 * 2.0)
+                .with_period("1º TRIMESTRE", 2022)
+                .with_transmission(2022, 5, 2),
+            PerDcomp::sample("1", 1.0)
+                .with_credito(1.0 * 2.0)
+                .with_period("1º TRIMESTRE", 2022)
+                .with_transmission(2022, 5, 20),
+        ];
+        perdcomps[0].situacao = Some(Situacao::Deferido);
+
+        let quarters = quarter_totals(&perdcomps);
+        assert_eq!(quarters.len(), 2);
+        assert_eq!(quarters[0].label, "2022 T1");
+        assert_eq!((quarters[0].credito, quarters[0].per), (22.0, 11.0));
+
+        assert_eq!(
+            documents_by_situacao(&perdcomps),
+            vec![(NO_SITUACAO.to_string(), 2), ("Deferido".to_string(), 1)]
+        );
+
+        assert_eq!(
+            cumulative_utilization(&perdcomps),
+            vec![("2022-05".to_string(), 11.0), ("2022-08".to_string(), 16.0)]
+        );
+    }
+}
@@ -0,0 +1,680 @@
+//! Per-column statistics gathered in a single pass over the records.
+//!
+//! [`collect_column_stats`] walks every record once with a dedicated
+//! `serde::Serializer`, in parallel with rayon, and records for each column
+//! its display width, emptiness, null count and min/max values. Width
+//! calculation, empty-column hiding and the verbose profile all read from
+//! the resulting [`TableStats`], instead of converting every row to a
+//! `serde_json::Value` again.
+
+use log::debug;
+use rayon::prelude::*;
+use serde::{
+    Serialize, Serializer,
+    ser::{self, Impossible, SerializeStruct},
+};
+use std::fmt;
+
+use crate::{
+    CHECKBOX_WIDTH, ColumnWidths, SheetColumns, money_text, progress_bar, structures::FONT_SIZE,
+    text_width,
+};
+
+/// Width sample of the `dd/mm/aaaa` date layout.
+const DATE_TEXT: &str = "00/00/0000";
+
+/// Records per rayon work unit.
+const CHUNK_SIZE: usize = 1024;
+
+/// Smallest or largest value seen in a column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatValue {
+    Number(f64),
+    Text(String),
+}
+
+impl fmt::Display for StatValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatValue::Number(value) => write!(f, "{value}"),
+            StatValue::Text(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+/// Statistics of one column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    /// Serialized field name, the same header written to the workbook.
+    pub header: String,
+    /// Widest value, in digit widths (see [`text_width`]).
+    pub max_width: f64,
+    /// Values that are neither null nor blank text.
+    pub filled: usize,
+    /// `None` values.
+    pub nulls: usize,
+    pub min: Option<StatValue>,
+    pub max: Option<StatValue>,
+    bold: bool,
+    date: bool,
+}
+
+impl ColumnStats {
+    fn new(header: &str, column_widths: &ColumnWidths, columns: Option<&SheetColumns>) -> Self {
+        Self {
+            header: header.to_string(),
+            max_width: 0.0,
+            filled: 0,
+            nulls: 0,
+            min: None,
+            max: None,
+            bold: column_widths.is_bold(header)
+                || columns.is_some_and(|columns| columns.is_bold(header)),
+            date: columns.is_some_and(|columns| columns.is_date(header)),
+        }
+    }
+
+    /// A number as Excel displays it: `dd/mm/aaaa` for the date serials, `#,##0.00` otherwise.
+    fn number_text(&self, value: f64) -> String {
+        match self.date {
+            // Digits share one width, so every date measures the same
+            true => DATE_TEXT.to_string(),
+            false => money_text(value),
+        }
+    }
+
+    /// No row has content in this column.
+    pub fn is_empty(&self) -> bool {
+        self.filled == 0
+    }
+
+    fn observe_number(&mut self, value: f64, text: &str) {
+        self.filled += 1;
+        self.max_width = self.max_width.max(text_width(text, FONT_SIZE, self.bold));
+        self.update_range(StatValue::Number(value));
+    }
+
+    fn observe_text(&mut self, text: &str) {
+        if text.trim().is_empty() {
+            return;
+        }
+        self.filled += 1;
+        self.max_width = self.max_width.max(text_width(text, FONT_SIZE, self.bold));
+
+        let below = match &self.min {
+            Some(StatValue::Text(min)) => text < min.as_str(),
+            _ => self.min.is_none(),
+        };
+        if below {
+            self.min = Some(StatValue::Text(text.to_string()));
+        }
+
+        let above = match &self.max {
+            Some(StatValue::Text(max)) => text > max.as_str(),
+            _ => self.max.is_none(),
+        };
+        if above {
+            self.max = Some(StatValue::Text(text.to_string()));
+        }
+    }
+
+    fn update_range(&mut self, value: StatValue) {
+        if self.min.as_ref().is_none_or(|min| less_than(&value, min)) {
+            self.min = Some(value.clone());
+        }
+        if self.max.as_ref().is_none_or(|max| less_than(max, &value)) {
+            self.max = Some(value);
+        }
+    }
+
+    fn merge(&mut self, other: ColumnStats) {
+        self.max_width = self.max_width.max(other.max_width);
+        self.filled += other.filled;
+        self.nulls += other.nulls;
+        if let Some(min) = other.min {
+            self.update_range(min);
+        }
+        if let Some(max) = other.max {
+            self.update_range(max);
+        }
+    }
+}
+
+/// Numbers sort before text; values of the same kind compare naturally.
+fn less_than(a: &StatValue, b: &StatValue) -> bool {
+    match (a, b) {
+        (StatValue::Number(a), StatValue::Number(b)) => a < b,
+        (StatValue::Text(a), StatValue::Text(b)) => a < b,
+        (StatValue::Number(_), StatValue::Text(_)) => true,
+        (StatValue::Text(_), StatValue::Number(_)) => false,
+    }
+}
+
+/// Statistics of every column of a dataset.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableStats {
+    pub rows: usize,
+    pub columns: Vec<ColumnStats>,
+}
+
+impl TableStats {
+    fn merge(mut self, other: TableStats) -> TableStats {
+        if self.columns.is_empty() {
+            return TableStats {
+                rows: self.rows + other.rows,
+                columns: other.columns,
+            };
+        }
+        self.rows += other.rows;
+        for (column, other) in self.columns.iter_mut().zip(other.columns) {
+            column.merge(other);
+        }
+        self
+    }
+
+    /// Auto-fit width of each column (see [`ColumnWidths::fit`]).
+    pub fn column_widths(&self, column_widths: &ColumnWidths) -> Vec<f64> {
+        self.columns
+            .iter()
+            .map(|column| column_widths.fit(&column.header, column.max_width))
+            .collect()
+    }
+
+    /// Indices of the columns without content in any row.
+    pub fn empty_column_indices(&self) -> Vec<u16> {
+        self.columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| column.is_empty())
+            .map(|(index, _)| index as u16)
+            .collect()
+    }
+
+    /// Logs one debug line per column with its counts and range.
+    pub fn print_profile(&self) {
+        debug!("Column profile ({} rows):", self.rows);
+        for column in &self.columns {
+            let range = match (&column.min, &column.max) {
+                (Some(min), Some(max)) => format!("{min} .. {max}"),
+                _ => String::from("-"),
+            };
+            debug!(
+                "  {:<48} filled: {:>8}  nulls: {:>8}  width: {:>6.2}  range: {range}",
+                column.header, column.filled, column.nulls, column.max_width
+            );
+        }
+    }
+}
+
+/// Collects the statistics of `data` in one parallel pass.
+///
+/// Widths are measured in bold for the columns with a bold value format in
+/// `columns` or marked as such in `column_widths`, and date serials with the
+/// date layout (see [`SheetColumns::is_date`]).
+pub fn collect_column_stats<T>(
+    data: &[T],
+    column_widths: &ColumnWidths,
+    columns: Option<&SheetColumns>,
+) -> TableStats
+where
+    T: Serialize + Sync,
+{
+    let progress = progress_bar(data.len() as u64, "Measuring columns");
+    let stats = data
+        .par_chunks(CHUNK_SIZE)
+        .map(|chunk| {
+            let mut stats = TableStats::default();
+            for item in chunk {
+                // Records that fail to serialize (e.g. non-struct types) are not counted.
+                if item
+                    .serialize(RecordSerializer {
+                        stats: &mut stats.columns,
+                        column_widths,
+                        columns,
+                    })
+                    .is_ok()
+                {
+                    stats.rows += 1;
+                }
+            }
+            progress.inc(chunk.len() as u64);
+            stats
+        })
+        .reduce(TableStats::default, TableStats::merge);
+    progress.finish_and_clear();
+    stats
+}
+
+/// Error raised for values that cannot be laid out as a single cell.
+#[derive(Debug)]
+pub struct StatsError(String);
+
+impl fmt::Display for StatsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column statistics: {}", self.0)
+    }
+}
+
+impl std::error::Error for StatsError {}
+
+impl ser::Error for StatsError {
+    fn custom<M: fmt::Display>(msg: M) -> Self {
+        StatsError(msg.to_string())
+    }
+}
+
+fn unsupported(kind: &str) -> StatsError {
+    StatsError(format!("{kind} values are not supported"))
+}
+
+/// Serializes a record: accepts only structs, one column per field.
+struct RecordSerializer<'a> {
+    stats: &'a mut Vec<ColumnStats>,
+    column_widths: &'a ColumnWidths,
+    columns: Option<&'a SheetColumns>,
+}
+
+/// Field visitor returned by [`RecordSerializer::serialize_struct`].
+struct FieldSerializer<'a> {
+    stats: &'a mut Vec<ColumnStats>,
+    column_widths: &'a ColumnWidths,
+    columns: Option<&'a SheetColumns>,
+    index: usize,
+}
+
+impl SerializeStruct for FieldSerializer<'_> {
+    type Ok = ();
+    type Error = StatsError;
+
+    fn serialize_field<V>(&mut self, key: &'static str, value: &V) -> Result<(), StatsError>
+    where
+        V: ?Sized + Serialize,
+    {
+        if self.index == self.stats.len() {
+            self.stats
+                .push(ColumnStats::new(key, self.column_widths, self.columns));
+        }
+        let column = &mut self.stats[self.index];
+        self.index += 1;
+
+        // A nested value that is not a cell leaves the column untouched.
+        let _ = value.serialize(CellSerializer { column });
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), StatsError> {
+        Ok(())
+    }
+}
+
+macro_rules! reject {
+    ($($method:ident($($arg:ty),*) -> $ok:ty;)*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<$ok, StatsError> {
+                Err(unsupported(stringify!($method)))
+            }
+        )*
+    };
+}
+
+impl<'a> Serializer for RecordSerializer<'a> {
+    type Ok = ();
+    type Error = StatsError;
+    type SerializeSeq = Impossible<(), StatsError>;
+    type SerializeTuple = Impossible<(), StatsError>;
+    type SerializeTupleStruct = Impossible<(), StatsError>;
+    type SerializeTupleVariant = Impossible<(), StatsError>;
+    type SerializeMap = Impossible<(), StatsError>;
+    type SerializeStruct = FieldSerializer<'a>;
+    type SerializeStructVariant = Impossible<(), StatsError>;
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<FieldSerializer<'a>, StatsError> {
+        Ok(FieldSerializer {
+            stats: self.stats,
+            column_widths: self.column_widths,
+            columns: self.columns,
+            index: 0,
+        })
+    }
+
+    fn serialize_newtype_struct<V>(self, _name: &'static str, value: &V) -> Result<(), StatsError>
+    where
+        V: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_some<V>(self, value: &V) -> Result<(), StatsError>
+    where
+        V: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<V>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &V,
+    ) -> Result<(), StatsError>
+    where
+        V: ?Sized + Serialize,
+    {
+        Err(unsupported("enum"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+    ) -> Result<(), StatsError> {
+        Err(unsupported("enum"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), StatsError> {
+        Err(unsupported("unit struct"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, StatsError> {
+        Err(unsupported("tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, StatsError> {
+        Err(unsupported("enum"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, StatsError> {
+        Err(unsupported("enum"))
+    }
+
+    reject! {
+        serialize_bool(bool) -> ();
+        serialize_i8(i8) -> ();
+        serialize_i16(i16) -> ();
+        serialize_i32(i32) -> ();
+        serialize_i64(i64) -> ();
+        serialize_u8(u8) -> ();
+        serialize_u16(u16) -> ();
+        serialize_u32(u32) -> ();
+        serialize_u64(u64) -> ();
+        serialize_f32(f32) -> ();
+        serialize_f64(f64) -> ();
+        serialize_char(char) -> ();
+        serialize_str(&str) -> ();
+        serialize_bytes(&[u8]) -> ();
+        serialize_none() -> ();
+        serialize_unit() -> ();
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+    }
+}
+
+/// Serializes one field value into the statistics of its column.
+///
+/// Values are measured as Excel displays them: floats as `#,##0.00` money,
+/// integers as plain digits, booleans as checkboxes and enum variants by name.
+struct CellSerializer<'a> {
+    column: &'a mut ColumnStats,
+}
+
+impl CellSerializer<'_> {
+    fn integer(self, value: impl Into<i128>) -> Result<(), StatsError> {
+        let value: i128 = value.into();
+        self.column.observe_number(value as f64, &value.to_string());
+        Ok(())
+    }
+}
+
+impl Serializer for CellSerializer<'_> {
+    type Ok = ();
+    type Error = StatsError;
+    type SerializeSeq = Impossible<(), StatsError>;
+    type SerializeTuple = Impossible<(), StatsError>;
+    type SerializeTupleStruct = Impossible<(), StatsError>;
+    type SerializeTupleVariant = Impossible<(), StatsError>;
+    type SerializeMap = Impossible<(), StatsError>;
+    type SerializeStruct = Impossible<(), StatsError>;
+    type SerializeStructVariant = Impossible<(), StatsError>;
+
+    fn serialize_bool(self, _value: bool) -> Result<(), StatsError> {
+        self.column.filled += 1;
+        self.column.max_width = self.column.max_width.max(CHECKBOX_WIDTH);
+        Ok(())
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<(), StatsError> {
+        self.integer(value)
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<(), StatsError> {
+        self.integer(value)
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<(), StatsError> {
+        self.integer(value)
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<(), StatsError> {
+        self.integer(value)
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<(), StatsError> {
+        self.integer(value)
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<(), StatsError> {
+        self.integer(value)
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<(), StatsError> {
+        self.integer(value)
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<(), StatsError> {
+        self.integer(value)
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<(), StatsError> {
+        self.serialize_f64(f64::from(value))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<(), StatsError> {
+        if value.is_finite() {
+            let text = self.column.number_text(value);
+            self.column.observe_number(value, &text);
+        } else {
+            self.column.nulls += 1;
+        }
+        Ok(())
+    }
+
+    fn serialize_char(self, value: char) -> Result<(), StatsError> {
+        self.column.observe_text(value.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+
+    fn serialize_str(self, value: &str) -> Result<(), StatsError> {
+        self.column.observe_text(value);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<(), StatsError> {
+        self.column.observe_text(&String::from_utf8_lossy(value));
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), StatsError> {
+        self.column.nulls += 1;
+        Ok(())
+    }
+
+    fn serialize_some<V>(self, value: &V) -> Result<(), StatsError>
+    where
+        V: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), StatsError> {
+        self.serialize_none()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), StatsError> {
+        self.serialize_none()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), StatsError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<V>(self, _name: &'static str, value: &V) -> Result<(), StatsError>
+    where
+        V: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<V>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        value: &V,
+    ) -> Result<(), StatsError>
+    where
+        V: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, StatsError> {
+        Err(unsupported("sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, StatsError> {
+        Err(unsupported("tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, StatsError> {
+        Err(unsupported("tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, StatsError> {
+        Err(unsupported("enum"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, StatsError> {
+        Err(unsupported("map"))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, StatsError> {
+        Err(unsupported("struct"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, StatsError> {
+        Err(unsupported("enum"))
+    }
+}
+
+#[cfg(test)]
+mod tests_column_stats {
+    use super::*;
+    use crate::{PerDcomp, Situacao};
+
+    #[test]
+    fn collects_statistics_in_one_pass() {
+        let mut perdcomps: Vec<PerDcomp> = (0..3000)
+            .map(|index| PerDcomp {
+                per_dcomp: Some(format!("{index:05}")),
+                valor_do_per: index as f64,
+                ..Default::default()
+            })
+            .collect();
+        perdcomps[10].situacao = Some(Situacao::NaoHomologada);
+        perdcomps[20].motivo = Some("   ".to_string());
+        perdcomps[30].data_da_transmissao = chrono::NaiveDate::from_ymd_opt(2023, 3, 15);
+
+        let columns = PerDcomp::sheet_columns(true);
+        let stats = collect_column_stats(&perdcomps, &ColumnWidths::default(), Some(&columns));
+        let column = |header: &str| {
+            stats
+                .columns
+                .iter()
+                .find(|column| column.header == header)
+                .unwrap()
+        };
+
+        assert_eq!(stats.rows, 3000);
+        assert_eq!(stats.columns.len(), 30);
+
+        let per = column("Valor Total do Pedido de Resssarcimento (PER)");
+        assert_eq!(per.min, Some(StatValue::Number(0.0)));
+        assert_eq!(per.max, Some(StatValue::Number(2999.0)));
+        assert_eq!(per.max_width, text_width("2.999,00", FONT_SIZE, true));
+
+        let situacao = column("Situação");
+        assert_eq!(situacao.filled, 1);
+        assert_eq!(situacao.nulls, 2999);
+        assert_eq!(
+            situacao.max,
+            Some(StatValue::Text("Não Homologada".to_string()))
+        );
+
+        // Date serials are measured as dd/mm/aaaa, not as money
+        let data = column("Data da Transmissão");
+        assert_eq!(data.filled, 1);
+        assert_eq!(data.max_width, text_width("15/03/2023", FONT_SIZE, false));
+
+        let empty = stats.empty_column_indices();
+        assert!(empty.contains(&25)); // Motivo: only blank text
+        assert!(!empty.contains(&0));
+    }
+}
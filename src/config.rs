@@ -0,0 +1,592 @@
+//! Persistent defaults read from TOML files.
+//!
+//! Settings are merged from, in increasing precedence:
+//!
+//! 1. the user file, `config.toml` in the `perdcomp_csv_to_xlsx` directory
+//!    of the user configuration directory (e.g. `~/.config` on Linux);
+//! 2. the project file, `perdcomp.toml` in the working directory, or the
+//!    file given by `--config`;
+//! 3. the environment variables, such as `DELIMITER_CSV`;
+//! 4. the command line.
+//!
+//! Keys are the long option names (`remove-empty = true`, `format = ["xlsx",
+//! "csv"]`), and relative paths are relative to the file that sets them.
+//! Some settings exist only in the files:
+//!
+//! ```toml
+//! output = "relatorios/perdcomp_{date}"   # also {input}, the first input file name
+//! sort = ["cnpj_declarante", "-valor_do_per"]   # "-" sorts in decreasing order
+//! theme = "Medium2"                        # Excel table style
+//!
+//! [mapping]                                # CSV header = PER/DCOMP column
+//! "Valor Crédito" = "valor_total_do_credito"
+//!
+//! [[filters]]
+//! column = "situacao"
+//! operator = "not_equal_to"
+//! value = "Cancelado"
+//! ```
+//!
+//! `perdcomp_csv_to_xlsx config show` prints the merged settings.
+
+use chrono::Local;
+use clap::{ArgMatches, parser::ValueSource};
+use csv::StringRecord;
+use log::debug;
+use rust_xlsxwriter::TableStyle;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Arguments, Cell, Column, LogFormat, MyResult, OutputFormat, PerDcomp, PerDcompError,
+    RuleOperator, Tabular, TextEncoding, WithPath, normalize_text, parse_date,
+};
+
+/// Name of the project configuration file, looked up in the working directory.
+pub const CONFIG_FILE: &str = "perdcomp.toml";
+
+/// Directory and name of the user configuration file.
+const USER_CONFIG: [&str; 2] = ["perdcomp_csv_to_xlsx", "config.toml"];
+
+/// Settings of a configuration file; `None` leaves the option unset.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub annotations: Option<PathBuf>,
+    pub charts: Option<bool>,
+    pub column_widths: Option<PathBuf>,
+    pub credit_catalog: Option<PathBuf>,
+    pub debitos: Option<PathBuf>,
+    pub delimiter: Option<char>,
+    pub efd: Option<PathBuf>,
+    pub encoding: Option<TextEncoding>,
+    pub format: Option<Vec<OutputFormat>>,
+    pub format_rules: Option<PathBuf>,
+    pub generic: Option<bool>,
+    pub keep: Option<bool>,
+    pub log_format: Option<LogFormat>,
+    pub origin: Option<bool>,
+    pub path: Option<Vec<PathBuf>>,
+    pub quiet: Option<bool>,
+    pub remove_empty: Option<bool>,
+    pub stream: Option<bool>,
+    pub time: Option<bool>,
+    pub totals: Option<bool>,
+    pub verbose: Option<u8>,
+    /// Output path without extension, see [`output_stem`].
+    pub output: Option<String>,
+    /// Sort keys, see [`sort_records`].
+    pub sort: Option<Vec<String>>,
+    /// Excel table style of the PERDComp worksheet, see [`table_style`].
+    pub theme: Option<String>,
+    /// Extra CSV headers, each mapped to a PER/DCOMP column name or header.
+    pub mapping: Option<BTreeMap<String, String>>,
+    /// Keeps only the records matching every rule.
+    pub filters: Option<Vec<FilterRule>>,
+}
+
+/// Keeps the records whose `column` compares with `value` as `operator` says.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterRule {
+    /// Column name or header, compared with [`normalize_text`].
+    pub column: String,
+    /// Any [`RuleOperator`] but `data_bar`.
+    pub operator: RuleOperator,
+    /// Text, number, date text or boolean.
+    pub value: Value,
+}
+
+/// Calls `$macro` with the options of the command line, by [`Arguments`] group.
+macro_rules! config_fields {
+    ($macro:ident) => {
+        $macro!(
+            input: [delimiter, encoding, generic, path],
+            convert: [
+                annotations,
+                charts,
+                column_widths,
+                credit_catalog,
+                debitos,
+                efd,
+                format,
+                format_rules,
+                keep,
+                origin,
+                remove_empty,
+                stream,
+                totals
+            ],
+            log: [log_format, quiet, time, verbose]
+        )
+    };
+}
+
+impl Config {
+    /// Reads a configuration file and checks its settings.
+    pub fn load(path: &Path) -> MyResult<Self> {
+        let text = fs::read_to_string(path).with_path(path)?;
+        let config: Config = toml::from_str(&text).with_path(path)?;
+        config.validate().with_path(path)?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Ok(config.relative_to(dir))
+    }
+
+    /// Merges the user file and the project file (or `explicit`).
+    ///
+    /// Missing default files are skipped; a missing `explicit` file is an error.
+    pub fn discover(explicit: Option<&Path>) -> MyResult<Self> {
+        let user = dirs::config_dir().map(|dir| USER_CONFIG.iter().fold(dir, |dir, p| dir.join(p)));
+        let project = match explicit {
+            Some(path) => Some(path.to_path_buf()),
+            None => Some(PathBuf::from(CONFIG_FILE)).filter(|path| path.is_file()),
+        };
+
+        let mut config = Config::default();
+        for path in [user.filter(|path| path.is_file()), project]
+            .into_iter()
+            .flatten()
+        {
+            debug!("Configuration file: {path:?}");
+            config = config.merge(Config::load(&path)?);
+        }
+        Ok(config)
+    }
+
+    /// The settings of `self`, replaced by the ones set in `other`.
+    ///
+    /// `quiet` and `verbose` conflict, so `other` setting either one replaces both.
+    pub fn merge(self, other: Config) -> Self {
+        let (quiet, verbose) = match other.quiet.is_some() || other.verbose.is_some() {
+            true => (other.quiet, other.verbose),
+            false => (self.quiet, self.verbose),
+        };
+
+        macro_rules! merge {
+            ($($group:ident: [$($field:ident),*]),*) => {
+                Config {
+                    $($($field: other.$field.or(self.$field),)*)*
+                    output: other.output.or(self.output),
+                    sort: other.sort.or(self.sort),
+                    theme: other.theme.or(self.theme),
+                    mapping: other.mapping.or(self.mapping),
+                    filters: other.filters.or(self.filters),
+                }
+            };
+        }
+        Config {
+            quiet,
+            verbose,
+            ..config_fields!(merge)
+        }
+    }
+
+    /// Sets the options of `args` that were given neither on the command
+    /// line nor by an environment variable.
+    ///
+    /// `quiet` and `verbose` conflict, so giving either one skips both.
+    pub fn apply(self, args: &mut Arguments, matches: &ArgMatches) {
+        let given = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        let explicit = |id: &str| match id {
+            "quiet" | "verbose" => given("quiet") || given("verbose"),
+            _ => given(id),
+        };
+
+        macro_rules! apply {
+            ($($group:ident: [$($field:ident),*]),*) => {
+                $($(
+                    if let Some(value) = self.$field
+                        && !explicit(stringify!($field))
+                    {
+                        args.$group.$field = value.into();
+                    }
+                )*)*
+            };
+        }
+        config_fields!(apply);
+
+        let (input, convert) = (&mut args.input, &mut args.convert);
+        input.mapping = self.mapping.unwrap_or(std::mem::take(&mut input.mapping));
+        convert.filters = self.filters.unwrap_or(std::mem::take(&mut convert.filters));
+        convert.output = self.output.or(convert.output.take());
+        convert.sort = self.sort.unwrap_or(std::mem::take(&mut convert.sort));
+        convert.theme = self.theme.or(convert.theme.take());
+    }
+
+    /// The effective settings of `args`, as printed by `config show`.
+    pub fn from_arguments(args: &Arguments) -> Self {
+        let (input, convert, log) = (&args.input, &args.convert, &args.log);
+        Config {
+            annotations: convert.annotations.clone(),
+            charts: Some(convert.charts),
+            column_widths: convert.column_widths.clone(),
+            credit_catalog: convert.credit_catalog.clone(),
+            debitos: convert.debitos.clone(),
+            delimiter: Some(input.delimiter),
+            efd: convert.efd.clone(),
+            encoding: Some(input.encoding),
+            format: Some(convert.format.clone()),
+            format_rules: convert.format_rules.clone(),
+            generic: Some(input.generic),
+            keep: Some(convert.keep),
+            log_format: Some(log.log_format),
+            origin: Some(convert.origin),
+            path: Some(input.path.clone()).filter(|path| !path.is_empty()),
+            quiet: Some(log.quiet),
+            remove_empty: Some(convert.remove_empty),
+            stream: Some(convert.stream),
+            time: Some(log.time),
+            totals: Some(convert.totals),
+            verbose: Some(log.verbose),
+            output: convert.output.clone(),
+            sort: Some(convert.sort.clone()).filter(|sort| !sort.is_empty()),
+            theme: convert.theme.clone(),
+            mapping: Some(input.mapping.clone()).filter(|mapping| !mapping.is_empty()),
+            filters: Some(convert.filters.clone()).filter(|filters| !filters.is_empty()),
+        }
+    }
+
+    /// The settings as a TOML document.
+    pub fn to_toml(&self) -> MyResult<String> {
+        toml::to_string(self)
+            .map_err(|error| config_error(format!("falha ao gravar as configurações: {error}")))
+    }
+
+    /// Checks the columns, operators and theme of the file-only settings.
+    fn validate(&self) -> MyResult<()> {
+        let mut columns: Vec<&str> = Vec::new();
+        columns.extend(
+            self.mapping
+                .iter()
+                .flatten()
+                .map(|(_, column)| column.as_str()),
+        );
+        columns.extend(
+            self.filters
+                .iter()
+                .flatten()
+                .map(|rule| rule.column.as_str()),
+        );
+        columns.extend(
+            self.sort
+                .iter()
+                .flatten()
+                .map(|key| key.strip_prefix('-').unwrap_or(key)),
+        );
+        for column in columns {
+            if find_column(column).is_none() {
+                return Err(config_error(format!("coluna desconhecida {column:?}")));
+            }
+        }
+
+        for rule in self.filters.iter().flatten() {
+            if rule.operator == RuleOperator::DataBar {
+                return Err(config_error(format!(
+                    "o filtro da coluna {:?} não aceita data_bar",
+                    rule.column
+                )));
+            }
+        }
+
+        if let Some(theme) = &self.theme
+            && table_style(theme).is_none()
+        {
+            return Err(config_error(format!("tema desconhecido {theme:?}")));
+        }
+
+        Ok(())
+    }
+
+    /// Makes the relative paths relative to `dir` instead of the working directory.
+    fn relative_to(mut self, dir: &Path) -> Self {
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = dir.join(&*path);
+            }
+        };
+
+        for path in [
+            &mut self.annotations,
+            &mut self.column_widths,
+            &mut self.credit_catalog,
+            &mut self.debitos,
+            &mut self.efd,
+            &mut self.format_rules,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            resolve(path);
+        }
+        self.path.iter_mut().flatten().for_each(resolve);
+        self
+    }
+}
+
+fn config_error(message: String) -> PerDcompError {
+    PerDcompError::Config {
+        path: None,
+        message,
+    }
+}
+
+impl FilterRule {
+    /// Whether `perdcomp` is kept. Empty cells only match `not_equal_to`.
+    pub fn matches(&self, perdcomp: &PerDcomp) -> bool {
+        let Some((index, _)) = find_column(&self.column) else {
+            return false;
+        };
+        let cell = &perdcomp.cells()[index];
+
+        if let (RuleOperator::Contains, Cell::Text(text), Value::String(value)) =
+            (self.operator, cell, &self.value)
+        {
+            return normalize_text(text).contains(&normalize_text(value));
+        }
+
+        let ordering = compare_with_value(cell, &self.value);
+        match self.operator {
+            RuleOperator::EqualTo => ordering == Some(Ordering::Equal),
+            RuleOperator::NotEqualTo => ordering != Some(Ordering::Equal),
+            RuleOperator::GreaterThan => ordering == Some(Ordering::Greater),
+            RuleOperator::GreaterThanOrEqualTo => ordering.is_some_and(Ordering::is_ge),
+            RuleOperator::LessThan => ordering == Some(Ordering::Less),
+            RuleOperator::LessThanOrEqualTo => ordering.is_some_and(Ordering::is_le),
+            RuleOperator::Contains | RuleOperator::DataBar => false,
+        }
+    }
+}
+
+impl fmt::Display for FilterRule {
+    /// `situacao not_equal_to "Cancelado"`, as recorded in the "Metadados" worksheet.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = serde_json::to_value(self.operator).unwrap_or_default();
+        let operator = operator.as_str().unwrap_or_default();
+        write!(f, "{} {operator} {}", self.column, self.value)
+    }
+}
+
+/// Compares a cell with a configured value of the same type.
+fn compare_with_value(cell: &Cell, value: &Value) -> Option<Ordering> {
+    match (cell, value) {
+        (Cell::Text(text), Value::String(value)) => {
+            Some(normalize_text(text).cmp(&normalize_text(value)))
+        }
+        (Cell::Money(number), value) => number.partial_cmp(&value.as_f64()?),
+        (Cell::Integer(number), value) => (*number as f64).partial_cmp(&value.as_f64()?),
+        (Cell::Date(date), Value::String(value)) => Some(date.cmp(&parse_date(value)?)),
+        (Cell::Bool(flag), Value::Bool(value)) => Some(flag.cmp(value)),
+        _ => None,
+    }
+}
+
+/// The PER/DCOMP column with this name or header, and its index.
+pub fn find_column(name: &str) -> Option<(usize, Column)> {
+    let target = normalize_text(name);
+    PerDcomp::columns()
+        .into_iter()
+        .enumerate()
+        .find(|(_, column)| {
+            normalize_text(column.name) == target || normalize_text(column.header) == target
+        })
+}
+
+/// Sorts the records by `keys`, column names or headers, decreasing when prefixed with `-`.
+///
+/// Empty cells come last. Unknown columns are ignored.
+pub fn sort_records(perdcomps: &mut Vec<PerDcomp>, keys: &[String]) {
+    let keys: Vec<(usize, bool)> = keys
+        .iter()
+        .filter_map(|key| {
+            let (name, decreasing) = match key.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (key.as_str(), false),
+            };
+            find_column(name).map(|(index, _)| (index, decreasing))
+        })
+        .collect();
+
+    let mut keyed: Vec<(Vec<Cell>, PerDcomp)> = perdcomps
+        .drain(..)
+        .map(|perdcomp| {
+            let cells = perdcomp.cells();
+            let key = keys
+                .iter()
+                .map(|&(index, _)| cells[index].clone())
+                .collect();
+            (key, perdcomp)
+        })
+        .collect();
+
+    keyed.sort_by(|(a, _), (b, _)| {
+        a.iter()
+            .zip(b)
+            .zip(&keys)
+            .map(|((a, b), &(_, decreasing))| match (a, b) {
+                (Cell::Null, Cell::Null) => Ordering::Equal,
+                (Cell::Null, _) => Ordering::Greater,
+                (_, Cell::Null) => Ordering::Less,
+                _ if decreasing => compare_cells(b, a),
+                _ => compare_cells(a, b),
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+
+    perdcomps.extend(keyed.into_iter().map(|(_, perdcomp)| perdcomp));
+}
+
+fn compare_cells(a: &Cell, b: &Cell) -> Ordering {
+    match (a, b) {
+        (Cell::Text(a), Cell::Text(b)) => a.cmp(b),
+        (Cell::Money(a), Cell::Money(b)) => a.total_cmp(b),
+        (Cell::Integer(a), Cell::Integer(b)) => a.cmp(b),
+        (Cell::Date(a), Cell::Date(b)) => a.cmp(b),
+        (Cell::Bool(a), Cell::Bool(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+/// Renames the CSV headers found in `mapping` to the canonical header of their column.
+pub(crate) fn map_headers(
+    headers: &StringRecord,
+    mapping: &BTreeMap<String, String>,
+) -> StringRecord {
+    if mapping.is_empty() {
+        return headers.clone();
+    }
+
+    let mapping: BTreeMap<String, &str> = mapping
+        .iter()
+        .filter_map(|(header, column)| {
+            find_column(column).map(|(_, column)| (normalize_text(header), column.header))
+        })
+        .collect();
+
+    headers
+        .iter()
+        .map(|header| {
+            mapping
+                .get(&normalize_text(header))
+                .copied()
+                .unwrap_or(header)
+        })
+        .collect()
+}
+
+/// Output path of an output template: `{date}` becomes today's date
+/// (`2024-05-31`) and `{input}` the name of the first input file, without extension.
+pub fn output_stem(template: &str, args: &Arguments) -> PathBuf {
+    let input = args
+        .input
+        .path
+        .first()
+        .and_then(|path| path.file_stem())
+        .map_or("perdcomp".into(), |stem| stem.to_string_lossy());
+
+    template
+        .replace("{date}", &Local::now().format("%Y-%m-%d").to_string())
+        .replace("{input}", &input)
+        .into()
+}
+
+/// The Excel table style named `name`, such as `"Medium9"` or `"TableStyleLight1"`.
+pub fn table_style(name: &str) -> Option<TableStyle> {
+    use TableStyle::*;
+    #[rustfmt::skip]
+    let styles = [
+        None,
+        Light1, Light2, Light3, Light4, Light5, Light6, Light7, Light8, Light9, Light10, Light11,
+        Light12, Light13, Light14, Light15, Light16, Light17, Light18, Light19, Light20, Light21,
+        Medium1, Medium2, Medium3, Medium4, Medium5, Medium6, Medium7, Medium8, Medium9, Medium10,
+        Medium11, Medium12, Medium13, Medium14, Medium15, Medium16, Medium17, Medium18, Medium19,
+        Medium20, Medium21, Medium22, Medium23, Medium24, Medium25, Medium26, Medium27, Medium28,
+        Dark1, Dark2, Dark3, Dark4, Dark5, Dark6, Dark7, Dark8, Dark9, Dark10, Dark11,
+    ];
+
+    let name = name.trim().to_lowercase();
+    let name = name.strip_prefix("tablestyle").unwrap_or(&name);
+    styles
+        .into_iter()
+        .find(|style| style.to_string().to_lowercase() == format!("tablestyle{name}"))
+}
+
+#[cfg(test)]
+mod tests_config {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+    use log::LevelFilter;
+
+    #[test]
+    fn command_line_beats_project_beats_user() -> MyResult<()> {
+        let user: Config = toml::from_str(
+            r#"
+            delimiter = ";"
+            format = ["xlsx", "csv"]
+            theme = "Light1"
+            "#,
+        )?;
+        let project: Config = toml::from_str(
+            r#"
+            remove-empty = true
+            theme = "Medium2"
+            sort = ["-valor_do_per"]
+
+            [[filters]]
+            column = "Situação"
+            operator = "not_equal_to"
+            value = "Cancelado"
+            "#,
+        )?;
+        project.validate()?;
+
+        let matches =
+            Arguments::command().try_get_matches_from(["test", "-d", "|", "-p", "a.csv"])?;
+        let mut args = Arguments::from_arg_matches(&matches)?;
+        user.merge(project).apply(&mut args, &matches);
+
+        assert_eq!(args.input.delimiter, '|');
+        assert_eq!(args.convert.format, [OutputFormat::Xlsx, OutputFormat::Csv]);
+        assert!(args.convert.remove_empty);
+        assert_eq!(args.convert.theme.as_deref(), Some("Medium2"));
+        assert!(table_style("medium2") == Some(TableStyle::Medium2));
+
+        let perdcomp = |situacao: &str| PerDcomp {
+            situacao: Some(situacao.into()),
+            ..Default::default()
+        };
+        assert!(args.convert.filters[0].matches(&perdcomp("Deferido")));
+        assert!(!args.convert.filters[0].matches(&perdcomp("CANCELADO")));
+
+        let shown: Config = toml::from_str(&Config::from_arguments(&args).to_toml()?)?;
+        assert_eq!(shown.sort, Some(vec!["-valor_do_per".to_string()]));
+        Ok(())
+    }
+
+    #[test]
+    fn quiet_and_verbose_are_set_together() -> MyResult<()> {
+        let user: Config = toml::from_str("quiet = true")?;
+        let project: Config = toml::from_str("verbose = 1")?;
+        let merged = user.clone().merge(project);
+        assert_eq!((merged.quiet, merged.verbose), (None, Some(1)));
+
+        let matches = Arguments::command().try_get_matches_from(["test", "-p", "a.csv", "-v"])?;
+        let mut args = Arguments::from_arg_matches(&matches)?;
+        user.apply(&mut args, &matches);
+
+        assert!(!args.log.quiet);
+        assert_eq!(args.log_level(), LevelFilter::Debug);
+        Ok(())
+    }
+}
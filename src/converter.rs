@@ -0,0 +1,977 @@
+//! The conversion pipeline, as a library.
+//!
+//! [`Converter`] runs the same steps as the command line: each input is
+//! converted to UTF-8 in a temporary file, parsed, enriched (credit catalog,
+//! annotations, debts), filtered, sorted and written in every requested
+//! format. [`Converter::run`] returns a [`ConversionReport`] instead of
+//! printing a summary, so other programs can embed the conversion.
+
+use log::{Level, debug, info, log_enabled, trace, warn};
+use std::{
+    cmp::Reverse,
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+
+use crate::{
+    ANNOTATIONS_FILE, Annotations, Arguments, ColumnWidths, Command, CreditCatalog, CsvExporter,
+    Debito, EfdCredit, Exporter, FormatRules, GENERIC_SHEET_NAME, GenericTable, HeaderReport,
+    InputFile, JsonExporter, MyResult, Origem, OutputFormat, ParquetExporter, Paths, PerDcomp,
+    Provenance, QueryResult, RecordDiff, SheetOptions, XlsxExporter, collect_column_stats,
+    compare_with_efd, detect_anomalies, diff_records, drop_debitos_of, format_input_csv_file,
+    input_role, is_xlsx, link_debitos, new_workbook, output_stem, perdcomp_records, perdcomp_table,
+    push_cells_worksheets, push_metadata_worksheet, read_companion_csv, read_xlsx, rename_file,
+    run_query, save_workbook, sort_records, spinner, stream_csv_to_xlsx, summary_tables,
+};
+
+/// Output path without extension; each format adds its own.
+const OUTPUT_STEM: &str = "perdcomp";
+
+/// Sort keys of the in-memory pipeline, as recorded in the "Metadados" worksheet.
+const SORT_KEYS: [&str; 5] = [
+    "Ano",
+    "Período de Apuração do Crédito",
+    "Código do Tipo de Crédito (decrescente)",
+    "Tipo de Crédito (decrescente)",
+    "Data da Transmissão",
+];
+
+/// Sorts by [`SORT_KEYS`]. Records with the same credit code, such as the
+/// ones without a catalog code, are ordered by the credit type name.
+fn sort_by_default_keys(perdcomps: &mut [PerDcomp]) {
+    perdcomps.sort_by_key(|perdcomp| {
+        (
+            perdcomp.ano,
+            perdcomp.trimestre_de_apuracao.clone(),
+            Reverse(perdcomp.codigo_do_credito.clone()),
+            Reverse(perdcomp.tipo_do_credito.clone()),
+            perdcomp.data_da_transmissao,
+        )
+    });
+}
+
+/// Appends `.extension` to `stem`.
+///
+/// Unlike [`Path::with_extension`], a dot inside the stem is kept:
+/// "perdcomp_01.02.2024" becomes "perdcomp_01.02.2024.xlsx".
+fn add_extension(stem: &Path, extension: &str) -> PathBuf {
+    let mut name = stem.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Builds and runs a conversion.
+///
+/// The options are the ones of the command line ([`Arguments`]); the
+/// builder methods set the most common ones.
+///
+/// ```no_run
+/// use perdcomp_csv_to_xlsx::{Converter, MyResult, OutputFormat};
+///
+/// fn main() -> MyResult<()> {
+///     let report = Converter::new()
+///         .path("perdcomp.csv")
+///         .delimiter(';')
+///         .formats(&[OutputFormat::Xlsx, OutputFormat::Csv])
+///         .filter("Somente 2023", |perdcomp| perdcomp.ano == Some(2023))
+///         .run()?;
+///
+///     println!("{report}");
+///     Ok(())
+/// }
+/// ```
+#[derive(Default)]
+pub struct Converter {
+    args: Arguments,
+    readers: Vec<(String, Box<dyn Read>)>,
+    filters: Vec<RowFilter>,
+    output: Option<PathBuf>,
+    skip_invalid: bool,
+}
+
+/// Keeps the records for which the predicate is `true`.
+struct RowFilter {
+    description: String,
+    predicate: Box<dyn Fn(&PerDcomp) -> bool>,
+}
+
+/// What a conversion read, rejected and wrote.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConversionReport {
+    pub rows_read: usize,
+    pub rows_written: usize,
+    /// Records left out by a filter or, with [`Converter::skip_invalid`], unparseable.
+    pub rejected: Vec<RejectedRow>,
+    /// The warnings also printed to stderr.
+    pub warnings: Vec<String>,
+    /// Sums of the written records; `None` when the records are not kept
+    /// in memory (streaming and generic modes).
+    pub totals: Option<Totals>,
+    /// Files written, in order.
+    pub outputs: Vec<PathBuf>,
+    /// What a subcommand reports instead of converting, for the caller to print.
+    pub command_output: Option<CommandOutput>,
+}
+
+/// The result of the subcommands that do not write a conversion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandOutput {
+    /// `inspect-headers`: how the headers of each CSV input are read.
+    Headers(Vec<(PathBuf, HeaderReport)>),
+    /// `validate`: the number of valid records; the invalid ones are in
+    /// [`ConversionReport::rejected`].
+    Validation { valid: usize },
+    /// `query` without `--output`.
+    Query(QueryResult),
+    /// `summary`: counts and totals, by situação and by quarter.
+    Summary(Vec<QueryResult>),
+    /// `diff`: the changes from the previous export.
+    Diff(RecordDiff),
+}
+
+/// A record left out of the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedRow {
+    /// Where the record came from, when known.
+    pub origem: Option<Origem>,
+    pub reason: String,
+}
+
+/// Money sums of the written records.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Totals {
+    pub valor_total_do_credito: f64,
+    pub valor_do_per: f64,
+    pub total_debitos: f64,
+}
+
+impl Totals {
+    pub fn of(perdcomps: &[PerDcomp]) -> Self {
+        perdcomps
+            .iter()
+            .fold(Totals::default(), |totals, perdcomp| Totals {
+                valor_total_do_credito: totals.valor_total_do_credito
+                    + perdcomp.valor_total_do_credito,
+                valor_do_per: totals.valor_do_per + perdcomp.valor_do_per,
+                total_debitos: totals.total_debitos + perdcomp.total_debitos.unwrap_or_default(),
+            })
+    }
+}
+
+impl fmt::Display for ConversionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Rows read: {}", self.rows_read)?;
+        writeln!(f, "Rows rejected: {}", self.rejected.len())?;
+        writeln!(f, "Rows written: {}", self.rows_written)?;
+        if let Some(totals) = &self.totals {
+            writeln!(
+                f,
+                "Totals: crédito {:.2}, PER {:.2}, débitos {:.2}",
+                totals.valor_total_do_credito, totals.valor_do_per, totals.total_debitos
+            )?;
+        }
+        writeln!(f, "Warnings: {}", self.warnings.len())?;
+        for output in &self.outputs {
+            writeln!(f, "Output: {}", output.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl ConversionReport {
+    /// Prints and records a warning.
+    fn warn(&mut self, message: String) {
+        warn!("{message}");
+        self.warnings.push(message);
+    }
+}
+
+impl Converter {
+    /// A conversion with the defaults of the command line and no input.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A conversion configured by parsed command line arguments.
+    ///
+    /// The filters and the output template of the configuration files
+    /// become [`filter`](Self::filter) and [`output`](Self::output).
+    pub fn from_arguments(args: Arguments) -> Self {
+        let output = args
+            .convert
+            .output
+            .as_deref()
+            .map(|template| output_stem(template, &args));
+        let filters = args.convert.filters.clone();
+        let converter = Self {
+            args,
+            output,
+            ..Default::default()
+        };
+
+        filters.into_iter().fold(converter, |converter, rule| {
+            converter.filter(rule.to_string(), move |perdcomp| rule.matches(perdcomp))
+        })
+    }
+
+    /// The options of the conversion.
+    pub fn arguments(&self) -> &Arguments {
+        &self.args
+    }
+
+    /// Adds a CSV or `.xlsx` input file.
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.input.path.push(path.into());
+        self
+    }
+
+    /// Adds a CSV input read from memory, a socket, etc.
+    ///
+    /// `name` stands for the file in the "Origem" column and in the
+    /// "Metadados" worksheet.
+    pub fn reader(mut self, name: impl Into<String>, reader: impl Read + 'static) -> Self {
+        self.readers.push((name.into(), Box::new(reader)));
+        self
+    }
+
+    /// Sets the field delimiter of the CSV inputs.
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.args.input.delimiter = delimiter;
+        self
+    }
+
+    /// Sets the output formats.
+    pub fn formats(mut self, formats: &[OutputFormat]) -> Self {
+        self.args.convert.format = formats.to_vec();
+        self
+    }
+
+    /// Sets the output path without extension. The default is `perdcomp`.
+    pub fn output(mut self, stem: impl Into<PathBuf>) -> Self {
+        self.output = Some(stem.into());
+        self
+    }
+
+    /// Hides the columns that are empty in all rows.
+    pub fn remove_empty(mut self, remove_empty: bool) -> Self {
+        self.args.convert.remove_empty = remove_empty;
+        self
+    }
+
+    /// Adds a total row to the PERDComp table.
+    pub fn totals(mut self, totals: bool) -> Self {
+        self.args.convert.totals = totals;
+        self
+    }
+
+    /// Keeps only the records for which `predicate` is `true`.
+    ///
+    /// The description is recorded in the "Metadados" worksheet and as the
+    /// reason of each rejected row. Not applied in streaming mode.
+    pub fn filter(
+        mut self,
+        description: impl Into<String>,
+        predicate: impl Fn(&PerDcomp) -> bool + 'static,
+    ) -> Self {
+        self.filters.push(RowFilter {
+            description: description.into(),
+            predicate: Box::new(predicate),
+        });
+        self
+    }
+
+    /// Rejects the records that cannot be parsed, instead of failing.
+    pub fn skip_invalid(mut self, skip_invalid: bool) -> Self {
+        self.skip_invalid = skip_invalid;
+        self
+    }
+
+    /// Path of the output in `format`.
+    fn output_path(&self, format: OutputFormat) -> PathBuf {
+        let stem = self.output.as_deref().unwrap_or(Path::new(OUTPUT_STEM));
+        add_extension(stem, format.extension())
+    }
+
+    /// Runs the conversion.
+    pub fn run(mut self) -> MyResult<ConversionReport> {
+        let args = &self.args;
+        let mut report = ConversionReport::default();
+
+        // Load the conditional formats and width overrides before parsing, so a bad rule file fails fast
+        let format_rules = FormatRules::load(args.convert.format_rules.as_deref())?;
+        let column_widths = ColumnWidths::load(args.convert.column_widths.as_deref())?;
+
+        // Hash the original input files before any conversion
+        let mut provenance = Provenance::from_arguments(args)?;
+
+        // Inputs given as readers are saved as they are, then follow the files
+        let mut sources: Vec<(PathBuf, Option<NamedTempFile>)> = args
+            .input
+            .path
+            .iter()
+            .map(|path| (path.clone(), None))
+            .collect();
+
+        for (name, reader) in &mut self.readers {
+            let mut raw = NamedTempFile::new()?;
+            io::copy(reader, &mut raw)?;
+
+            let mut input = InputFile::inspect(input_role(args), raw.path(), args.input.encoding)?;
+            input.path = PathBuf::from(&*name);
+            provenance.inputs.push(input);
+            sources.push((PathBuf::from(&*name), Some(raw)));
+        }
+
+        // See https://docs.rs/tempfile
+        // Convert each csv file (WINDOWS_1252) to UTF8 inside of `std::env::temp_dir()`.
+        // Workbooks are read back directly, without conversion.
+        let mut inputs: Vec<Paths> = Vec::new();
+        let mut temporaries: Vec<NamedTempFile> = Vec::new();
+        let mut workbooks: Vec<PathBuf> = Vec::new();
+
+        for (path, raw) in &sources {
+            if raw.is_none() && is_xlsx(path) {
+                workbooks.push(path.clone());
+                continue;
+            }
+
+            let temporary = NamedTempFile::new()?;
+            let source = raw.as_ref().map_or(path.as_path(), |raw| raw.path());
+            let conversion = Paths {
+                input: source.into(),            // CSV file
+                output: temporary.path().into(), // Temp file
+            };
+
+            debug!("{conversion:?}");
+
+            format_input_csv_file(args, &conversion)?;
+            inputs.push(Paths {
+                input: path.as_path().into(),
+                output: conversion.output,
+            });
+            temporaries.push(temporary);
+        }
+
+        // List the headers instead of converting
+        if let Some(Command::InspectHeaders) = &args.command {
+            let headers = inputs
+                .iter()
+                .map(|paths| Ok((paths.input.to_path_buf(), HeaderReport::read(args, paths)?)))
+                .collect::<MyResult<_>>()?;
+            report.command_output = Some(CommandOutput::Headers(headers));
+            for workbook in &workbooks {
+                report.warn(format!(
+                    "{}: workbook headers are not inspected.",
+                    workbook.display()
+                ));
+            }
+            self.keep_temporary_files(temporaries)?;
+            return Ok(report);
+        }
+
+        // Convert arbitrary CSV files, without the PER/DCOMP schema
+        if args.input.generic && args.is_conversion() {
+            if !workbooks.is_empty() {
+                return Err("Planilhas XLSX não podem ser convertidas com --generic.".into());
+            }
+
+            self.generic_conversion(
+                &inputs,
+                &format_rules,
+                &column_widths,
+                &provenance,
+                &mut report,
+            )?;
+            self.keep_temporary_files(temporaries)?;
+            return Ok(report);
+        }
+
+        // Map the free text `Tipo de Crédito` to canonical codes
+        let catalog = CreditCatalog::load(args.convert.credit_catalog.as_deref())?;
+
+        // Read the auditor annotations before the previous workbook is replaced
+        let workbook_file = self.output_path(OutputFormat::Xlsx);
+        let sidecar = args
+            .convert
+            .annotations
+            .clone()
+            .unwrap_or_else(|| workbook_file.with_file_name(ANNOTATIONS_FILE));
+        let annotations = Annotations::load(&sidecar, &workbook_file)?;
+
+        // Write the workbook while parsing, without holding the records in memory
+        if args.convert.stream && args.is_conversion() {
+            if !workbooks.is_empty() {
+                return Err(
+                    "Planilhas XLSX não podem ser lidas com --stream: execute sem --stream.".into(),
+                );
+            }
+
+            self.stream_conversion(
+                &inputs,
+                &catalog,
+                SheetOptions {
+                    table: None,
+                    format_rules: Some(&format_rules),
+                    column_widths: Some(&column_widths),
+                    stats: None,
+                    annotations: Some(&annotations),
+                    columns: None,
+                },
+                &mut provenance,
+                &mut report,
+            )?;
+            annotations.save(&sidecar)?;
+            self.keep_temporary_files(temporaries)?;
+            return Ok(report);
+        }
+
+        // Merge the records of every input file
+        let validate = matches!(args.command, Some(Command::Validate));
+        let mut perdcomps: Vec<PerDcomp> = Vec::new();
+        for paths in &inputs {
+            for result in perdcomp_records(args, paths)? {
+                match result {
+                    Ok(perdcomp) => perdcomps.push(perdcomp),
+                    Err(error) if self.skip_invalid || validate => {
+                        report.rejected.push(RejectedRow {
+                            origem: error.line().map(|line| Origem {
+                                file: paths.input.display().to_string().into(),
+                                line,
+                            }),
+                            reason: error.to_string(),
+                        })
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+        for workbook in &workbooks {
+            perdcomps.extend(read_xlsx(workbook)?);
+        }
+        report.rows_read = perdcomps.len() + report.rejected.len();
+
+        // List the invalid records instead of converting
+        if validate {
+            report.command_output = Some(CommandOutput::Validation {
+                valid: perdcomps.len(),
+            });
+            self.keep_temporary_files(temporaries)?;
+            return Ok(report);
+        }
+
+        catalog.apply(&mut perdcomps);
+        annotations.apply(&mut perdcomps);
+
+        // Link the debts compensated by each DCOMP
+        let mut debitos: Vec<Debito> = match &args.convert.debitos {
+            Some(debitos_path) => {
+                let debitos: Vec<Debito> = read_companion_csv(args, debitos_path)?;
+                let unmatched = link_debitos(&mut perdcomps, &debitos);
+                if unmatched > 0 {
+                    report.warn(format!("{unmatched} debts without a matching PER/DCOMP."));
+                }
+                debitos
+            }
+            None => Vec::new(),
+        };
+
+        // Leave out the records rejected by a filter, with their debts
+        let mut filtered_out: Vec<String> = Vec::new();
+        for filter in &self.filters {
+            perdcomps.retain(|perdcomp| {
+                let keep = (filter.predicate)(perdcomp);
+                if !keep {
+                    filtered_out.extend(perdcomp.per_dcomp.clone());
+                    report.rejected.push(RejectedRow {
+                        origem: perdcomp.origem.clone(),
+                        reason: filter.description.clone(),
+                    });
+                }
+                keep
+            });
+            provenance.filters.push(filter.description.clone());
+        }
+        drop_debitos_of(&mut debitos, &filtered_out, &perdcomps);
+        provenance.rows_read = report.rows_read;
+        provenance.rows_rejected = report.rejected.len();
+
+        // Sort Vec<PerDcomp> by key
+        let progress = spinner(format!("Sorting {} records", perdcomps.len()));
+        if args.convert.sort.is_empty() {
+            sort_by_default_keys(&mut perdcomps);
+            provenance.sort_keys = SORT_KEYS.map(String::from).to_vec();
+        } else {
+            sort_records(&mut perdcomps, &args.convert.sort);
+            provenance.sort_keys = args.convert.sort.clone();
+        }
+        progress.finish_and_clear();
+
+        if log_enabled!(Level::Trace) {
+            trace!("Display up to the first 50 lines:");
+            perdcomps
+                .iter()
+                .take(50)
+                .enumerate()
+                .for_each(|(index, perdcomp)| {
+                    trace!("line {:02}: {perdcomp:?}", index + 1);
+                })
+        }
+
+        self.keep_temporary_files(temporaries)?;
+        report.rows_written = perdcomps.len();
+        report.totals = Some(Totals::of(&perdcomps));
+
+        // Run a SQL statement instead of the conversion
+        if let Some(Command::Query(query)) = &args.command {
+            let result = run_query(&perdcomps, &query.sql)?;
+            match &query.output {
+                Some(output) => {
+                    result.write(output, args.input.delimiter as u8)?;
+                    report.outputs.push(output.clone());
+                }
+                None => report.command_output = Some(CommandOutput::Query(result)),
+            }
+            return Ok(report);
+        }
+
+        // Print the counts and totals instead of converting
+        if let Some(Command::Summary) = &args.command {
+            let tables = summary_tables(&report, &perdcomps);
+            report.command_output = Some(CommandOutput::Summary(tables));
+            return Ok(report);
+        }
+
+        // Compare with a previous export instead of converting
+        if let Some(Command::Diff(diff)) = &args.command {
+            let previous = self.read_previous(&diff.previous, &catalog)?;
+            let changes = diff_records(&previous, &perdcomps);
+            info!(
+                "{} added, {} removed, {} fields changed.",
+                changes.added.len(),
+                changes.removed.len(),
+                changes.changed.len()
+            );
+            report.command_output = Some(CommandOutput::Diff(changes));
+            return Ok(report);
+        }
+
+        // 1. Coleta as estatísticas das colunas numa única passagem
+        // (larguras, colunas vazias e perfil), apenas se forem usadas
+        let profile = log_enabled!(Level::Debug);
+        let needs_stats = profile
+            || args.convert.remove_empty
+            || args.convert.format.contains(&OutputFormat::Xlsx);
+        // Sem --origin, a coluna Origem não é gravada nem entra nas estatísticas
+        let sheet_columns = PerDcomp::sheet_columns(args.convert.origin);
+        let stats = needs_stats.then(|| {
+            let stats = collect_column_stats(&perdcomps, &column_widths, Some(&sheet_columns));
+            sheet_columns.select(&stats)
+        });
+
+        if profile && let Some(stats) = &stats {
+            stats.print_profile();
+        }
+
+        // Detecta colunas vazias apenas se o flag estiver ativo
+        let columns_to_hide = match &stats {
+            Some(stats) if args.convert.remove_empty => stats.empty_column_indices(),
+            _ => Vec::new(),
+        };
+
+        if let Some(stats) = &stats
+            && !columns_to_hide.is_empty()
+        {
+            let hidden: Vec<&str> = columns_to_hide
+                .iter()
+                .map(|&index| stats.columns[index as usize].header.as_str())
+                .collect();
+            provenance
+                .filters
+                .push(format!("Colunas vazias ocultas: {}", hidden.join(", ")));
+        }
+        provenance.rows_written = perdcomps.len();
+
+        // 2. Verifica a consistência dos registros (duplicidades, valores divergentes)
+        let anomalies = detect_anomalies(&perdcomps);
+        if !anomalies.is_empty() {
+            report.warn(format!("{} inconsistencies found.", anomalies.len()));
+        }
+
+        // 3. Confronta os créditos de PIS/COFINS com a EFD-Contribuições
+        let efd_comparison = match &args.convert.efd {
+            Some(efd_path) => {
+                let efd_credits: Vec<EfdCredit> = read_companion_csv(args, efd_path)?;
+                compare_with_efd(&perdcomps, &efd_credits, &catalog)
+            }
+            None => Vec::new(),
+        };
+
+        // 4. Exporta os registros em cada formato solicitado
+        for &format in &args.convert.format {
+            let exporter: Box<dyn Exporter> = match format {
+                OutputFormat::Xlsx => Box::new(XlsxExporter {
+                    hide_cols: &columns_to_hide,
+                    charts: args.convert.charts,
+                    column_widths: Some(&column_widths),
+                    stats: stats.as_ref(),
+                    debitos: &debitos,
+                    anomalies: &anomalies,
+                    efd_comparison: &efd_comparison,
+                    format_rules: Some(&format_rules),
+                    annotations: Some(&annotations),
+                    totals: args.convert.totals,
+                    theme: args.convert.theme.as_deref(),
+                    origin: args.convert.origin,
+                    provenance: Some(&provenance),
+                }),
+                OutputFormat::Csv => Box::new(CsvExporter {
+                    delimiter: args.input.delimiter as u8,
+                    origin: args.convert.origin,
+                }),
+                OutputFormat::Json => Box::new(JsonExporter {
+                    ndjson: false,
+                    origin: args.convert.origin,
+                }),
+                OutputFormat::Ndjson => Box::new(JsonExporter {
+                    ndjson: true,
+                    origin: args.convert.origin,
+                }),
+                OutputFormat::Parquet => Box::new(ParquetExporter {
+                    origin: args.convert.origin,
+                }),
+            };
+
+            let output = self.output_path(format);
+            exporter.export(&perdcomps, &output)?;
+            report.outputs.push(output);
+        }
+
+        if args.convert.format.contains(&OutputFormat::Xlsx) {
+            annotations.save(&sidecar)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Reads the records of a previous export, a CSV file or a workbook.
+    fn read_previous(&self, path: &Path, catalog: &CreditCatalog) -> MyResult<Vec<PerDcomp>> {
+        if is_xlsx(path) {
+            return read_xlsx(path);
+        }
+
+        let temporary = NamedTempFile::new()?;
+        let paths = Paths {
+            input: path.into(),
+            output: temporary.path().into(),
+        };
+        format_input_csv_file(&self.args, &paths)?;
+        let mut perdcomps = perdcomp_records(&self.args, &paths)?.collect::<MyResult<Vec<_>>>()?;
+        catalog.apply(&mut perdcomps);
+        Ok(perdcomps)
+    }
+
+    /// Streams the converted CSV to the workbook with constant memory worksheets.
+    fn stream_conversion(
+        &self,
+        inputs: &[Paths],
+        catalog: &CreditCatalog,
+        options: SheetOptions,
+        provenance: &mut Provenance,
+        report: &mut ConversionReport,
+    ) -> MyResult<()> {
+        let args = &self.args;
+        let ignored: Vec<&str> = [
+            (args.convert.debitos.is_some(), "--debitos"),
+            (args.convert.efd.is_some(), "--efd"),
+            (args.convert.charts, "--charts"),
+            (args.convert.remove_empty, "--remove-empty"),
+            (!self.filters.is_empty(), "row filters"),
+            (!args.convert.sort.is_empty(), "sort keys"),
+            (
+                args.convert.format.iter().any(|f| *f != OutputFormat::Xlsx),
+                "non-xlsx formats",
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(used, name)| used.then_some(name))
+        .collect();
+
+        if !ignored.is_empty() {
+            report.warn(format!(
+                "ignored in streaming mode: {}.",
+                ignored.join(", ")
+            ));
+        }
+
+        let table = perdcomp_table(
+            args.convert.totals,
+            args.convert.theme.as_deref(),
+            args.convert.origin,
+        );
+        let columns = PerDcomp::sheet_columns(args.convert.origin);
+        let options = SheetOptions {
+            table: table.as_ref(),
+            columns: Some(&columns),
+            ..options
+        };
+
+        let output = self.output_path(OutputFormat::Xlsx);
+        let rows = stream_csv_to_xlsx(args, inputs, catalog, options, Some(provenance), &output)?;
+        info!("{rows} rows streamed.");
+
+        report.rows_read = rows;
+        report.rows_written = rows;
+        if rows > 0 {
+            report.outputs.push(output);
+        }
+
+        Ok(())
+    }
+
+    /// Path of the generic workbook of `input`: the output stem, suffixed
+    /// with the input name when there are several inputs, or else
+    /// `<name>.xlsx` next to the input.
+    fn generic_output_path(&self, input: &Path, inputs: usize) -> PathBuf {
+        let name = input.file_stem().unwrap_or(input.as_os_str());
+        let stem = match &self.output {
+            Some(output) if inputs == 1 => output.clone(),
+            Some(output) => {
+                let mut stem = output.as_os_str().to_owned();
+                stem.push("_");
+                stem.push(name);
+                PathBuf::from(stem)
+            }
+            None => input.with_file_name(name),
+        };
+        add_extension(&stem, OutputFormat::Xlsx.extension())
+    }
+
+    /// Writes each CSV file to its own workbook with inferred column types.
+    fn generic_conversion(
+        &self,
+        inputs: &[Paths],
+        format_rules: &FormatRules,
+        column_widths: &ColumnWidths,
+        provenance: &Provenance,
+        report: &mut ConversionReport,
+    ) -> MyResult<()> {
+        let args = &self.args;
+        let ignored: Vec<&str> = [
+            (args.convert.annotations.is_some(), "--annotations"),
+            (args.convert.debitos.is_some(), "--debitos"),
+            (args.convert.efd.is_some(), "--efd"),
+            (args.convert.credit_catalog.is_some(), "--credit-catalog"),
+            (args.convert.charts, "--charts"),
+            (args.convert.origin, "--origin"),
+            (args.convert.stream, "--stream"),
+            (args.convert.totals, "--totals"),
+            (!self.filters.is_empty(), "row filters"),
+            (!args.input.mapping.is_empty(), "header mapping"),
+            (!args.convert.sort.is_empty(), "sort keys"),
+            (args.convert.theme.is_some(), "theme"),
+            (
+                args.convert.format.iter().any(|f| *f != OutputFormat::Xlsx),
+                "non-xlsx formats",
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(used, name)| used.then_some(name))
+        .collect();
+
+        if !ignored.is_empty() {
+            report.warn(format!("ignored in generic mode: {}.", ignored.join(", ")));
+        }
+
+        let options = SheetOptions {
+            format_rules: Some(format_rules),
+            column_widths: Some(column_widths),
+            ..Default::default()
+        };
+
+        for paths in inputs {
+            let table = GenericTable::read(args, paths)?;
+
+            let hide_cols = if args.convert.remove_empty {
+                table.empty_column_indices()
+            } else {
+                Vec::new()
+            };
+
+            // Record only this input file, with its own counts
+            let mut provenance = provenance.clone();
+            provenance
+                .inputs
+                .retain(|input| input.role != "CSV" || *input.path == *paths.input);
+            provenance.rows_read = table.rows.len();
+            provenance.rows_written = table.rows.len();
+            if !hide_cols.is_empty() {
+                let hidden: Vec<&str> = hide_cols
+                    .iter()
+                    .map(|&col| table.headers[col as usize].as_str())
+                    .collect();
+                provenance
+                    .filters
+                    .push(format!("Colunas vazias ocultas: {}", hidden.join(", ")));
+            }
+
+            let mut workbook = new_workbook()?;
+            push_cells_worksheets(
+                &mut workbook,
+                GENERIC_SHEET_NAME,
+                &table.headers,
+                &table.rows,
+                &hide_cols,
+                &options,
+            )?;
+            push_metadata_worksheet(&mut workbook, &provenance)?;
+
+            let output = self.generic_output_path(&paths.input, inputs.len());
+            save_workbook(&mut workbook, &output)?;
+
+            report.rows_read += table.rows.len();
+            report.rows_written += table.rows.len();
+            report.outputs.push(output);
+        }
+
+        Ok(())
+    }
+
+    /// Prevent the temporary files from being deleted, with `--keep`.
+    ///
+    /// And then, rename them to “temporary.csv”, “temporary_2.csv”, ...
+    fn keep_temporary_files(&self, temporaries: Vec<NamedTempFile>) -> MyResult<()> {
+        if !self.args.convert.keep {
+            return Ok(());
+        }
+
+        for (index, temporary) in temporaries.into_iter().enumerate() {
+            let new_name = match index {
+                0 => "temporary.csv".to_string(),
+                _ => format!("temporary_{}.csv", index + 1),
+            };
+            let path_buf = temporary.into_temp_path().keep()?;
+            rename_file(&path_buf, &new_name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests_converter {
+    use super::*;
+    use chrono::{Datelike, NaiveDate};
+    use clap::Parser;
+
+    #[test]
+    fn converts_a_reader_into_a_report() -> MyResult<()> {
+        let csv = "PER/DCOMP;Valor Total Crédito;Valor Crédito Data Transmissão;\
+                   Valor Total Débitos/Valor Pedido Rest/Ress.\n\
+                   111;1.000,00;900,00;800,00\n\
+                   222;2,50;2,50;2,50\n";
+        let dir = tempfile::tempdir()?;
+
+        let report = Converter::new()
+            .reader("memoria.csv", csv.as_bytes())
+            .delimiter(';')
+            .formats(&[OutputFormat::Csv])
+            .output(dir.path().join("saida_01.02.2024"))
+            .filter("Acima de 100", |perdcomp| perdcomp.valor_do_per > 100.0)
+            .run()?;
+
+        assert_eq!((report.rows_read, report.rows_written), (2, 1));
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(report.rejected[0].reason, "Acima de 100");
+        assert_eq!(
+            report.rejected[0].origem.as_ref().map(|o| o.to_string()),
+            Some("memoria.csv:3".to_string())
+        );
+        assert_eq!(
+            report.totals,
+            Some(Totals {
+                valor_total_do_credito: 1000.0,
+                valor_do_per: 800.0,
+                total_debitos: 0.0,
+            })
+        );
+        assert_eq!(report.outputs, [dir.path().join("saida_01.02.2024.csv")]);
+        assert!(report.outputs[0].exists());
+        Ok(())
+    }
+
+    #[test]
+    fn generic_workbooks_follow_the_output() -> MyResult<()> {
+        let dir = tempfile::tempdir()?;
+        let args = Arguments::try_parse_from(["test", "--generic", "-d", ";"])?;
+
+        let report = Converter::from_arguments(args)
+            .reader("a.csv", "Nome;Valor\nX;1\n".as_bytes())
+            .reader("b.csv", "Nome;Valor\nY;2\n".as_bytes())
+            .output(dir.path().join("saida"))
+            .run()?;
+
+        assert_eq!(
+            report.outputs,
+            [
+                dir.path().join("saida_a.xlsx"),
+                dir.path().join("saida_b.xlsx")
+            ]
+        );
+        assert!(report.outputs.iter().all(|output| output.exists()));
+        Ok(())
+    }
+
+    #[test]
+    fn subcommands_return_their_output() -> MyResult<()> {
+        let csv = "PER/DCOMP;Valor Total Crédito;Valor Crédito Data Transmissão;\
+                   Valor Total Débitos/Valor Pedido Rest/Ress.\n\
+                   111;1.000,00;900,00;800,00\n\
+                   222;2,50;2,50;2,50\n";
+        let dir = tempfile::tempdir()?;
+
+        for (command, expected) in [("summary", 3), ("validate", 2)] {
+            let args = Arguments::try_parse_from(["test", command, "-d", ";"])?;
+            let report = Converter::from_arguments(args)
+                .reader("memoria.csv", csv.as_bytes())
+                .output(dir.path().join("saida"))
+                .run()?;
+
+            assert!(report.outputs.is_empty());
+            match report.command_output {
+                Some(CommandOutput::Summary(tables)) => assert_eq!(tables.len(), expected),
+                Some(CommandOutput::Validation { valid }) => assert_eq!(valid, expected),
+                other => panic!("{command}: {other:?}"),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn credit_types_without_code_are_sorted_by_name() {
+        let perdcomp = |tipo: &str, day: u32| PerDcomp {
+            tipo_do_credito: Some(tipo.to_string()),
+            data_da_transmissao: NaiveDate::from_ymd_opt(2024, 1, day),
+            ..Default::default()
+        };
+        let mut perdcomps = vec![
+            perdcomp("Cofins", 1),
+            perdcomp("Pis", 2),
+            perdcomp("Cofins", 3),
+        ];
+
+        sort_by_default_keys(&mut perdcomps);
+
+        let order: Vec<(&str, u32)> = perdcomps
+            .iter()
+            .map(|perdcomp| {
+                (
+                    perdcomp.tipo_do_credito.as_deref().unwrap_or_default(),
+                    perdcomp.data_da_transmissao.map_or(0, |date| date.day()),
+                )
+            })
+            .collect();
+        assert_eq!(order, [("Pis", 2), ("Cofins", 1), ("Cofins", 3)]);
+    }
+}
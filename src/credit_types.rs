@@ -0,0 +1,147 @@
+//! Catalogue of canonical credit types.
+//!
+//! The SCC exports describe the same credit nature with different wordings
+//! across versions ("Cofins Não-Cumulativa – Exportação",
+//! "COFINS NAO CUMULATIVA EXPORTACAO", ...). The catalogue maps these raw
+//! strings to a stable code such as `COFINS_NC_EXP`.
+
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::{MyResult, PerDcomp, WithPath, normalize_text};
+
+/// Catalogue embedded in the binary, used when no file is given.
+const EMBEDDED_CATALOG: &str = include_str!("data/credit_types.json");
+
+/// A canonical credit type and the terms that identify it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreditType {
+    /// Stable code written to the workbook (e.g. `PIS_NC_EXP`).
+    pub code: String,
+    /// Human readable description.
+    pub description: String,
+    /// Alternative term lists. The entry matches when every term of at least
+    /// one list starts a word of the normalized raw string.
+    pub terms: Vec<Vec<String>>,
+    /// Whether the credit is declared in EFD-Contribuições, and so takes
+    /// part in the `--efd` comparison.
+    #[serde(default)]
+    pub efd: bool,
+}
+
+impl CreditType {
+    fn matches(&self, normalized: &str) -> bool {
+        let text = format!(" {normalized}");
+        self.terms.iter().any(|alternative| {
+            alternative
+                .iter()
+                .all(|term| text.contains(&format!(" {}", normalize_text(term))))
+        })
+    }
+}
+
+/// Ordered list of credit types. The first matching entry wins, so more
+/// specific entries must come before generic ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreditCatalog {
+    pub entries: Vec<CreditType>,
+}
+
+impl CreditCatalog {
+    /// Loads the catalogue from a JSON file, or the embedded one if `path` is `None`.
+    ///
+    /// The file has the same layout as `src/data/credit_types.json` and
+    /// replaces the embedded catalogue entirely.
+    pub fn load(path: Option<&Path>) -> MyResult<Self> {
+        let entries: Vec<CreditType> = match path {
+            Some(path) => {
+                let json = fs::read_to_string(path).with_path(path)?;
+                serde_json::from_str(&json).with_path(path)?
+            }
+            None => serde_json::from_str(EMBEDDED_CATALOG)?,
+        };
+
+        Ok(Self { entries })
+    }
+
+    /// Returns the canonical credit type of a raw `Tipo de Crédito` value.
+    ///
+    /// A value that is already a catalogue code (e.g. `PIS_NC_EXP`) maps to itself.
+    pub fn classify(&self, raw: &str) -> Option<&CreditType> {
+        let normalized = normalize_text(raw);
+        if normalized.is_empty() {
+            return None;
+        }
+        self.entries
+            .iter()
+            .find(|entry| entry.code.eq_ignore_ascii_case(raw.trim()))
+            .or_else(|| self.entries.iter().find(|entry| entry.matches(&normalized)))
+    }
+
+    /// Whether the entry with `code` is declared in EFD-Contribuições.
+    pub fn is_efd(&self, code: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.efd && entry.code == code)
+    }
+
+    /// Fills `codigo_do_credito` on every record.
+    pub fn apply(&self, perdcomps: &mut [PerDcomp]) {
+        for perdcomp in perdcomps {
+            perdcomp.codigo_do_credito = perdcomp
+                .tipo_do_credito
+                .as_deref()
+                .and_then(|raw| self.classify(raw))
+                .map(|credit_type| credit_type.code.clone());
+        }
+    }
+}
+
+impl Default for CreditCatalog {
+    fn default() -> Self {
+        Self::load(None).expect("embedded credit catalogue must be valid")
+    }
+}
+
+#[cfg(test)]
+mod tests_credit_catalog {
+    use super::*;
+
+    fn code(catalog: &CreditCatalog, raw: &str) -> Option<String> {
+        catalog
+            .classify(raw)
+            .map(|credit_type| credit_type.code.clone())
+    }
+
+    #[test]
+    fn maps_wording_variants_to_same_code() {
+        let catalog = CreditCatalog::default();
+        let expected = Some("COFINS_NC_EXP".to_string());
+
+        assert_eq!(
+            code(&catalog, "Cofins Não-Cumulativa – Exportação"),
+            expected
+        );
+        assert_eq!(code(&catalog, "COFINS NAO CUMULATIVA EXPORTACAO"), expected);
+        assert_eq!(
+            code(&catalog, "PIS/Pasep Não-Cumulativo - Mercado Interno"),
+            Some("PIS_NC_MI".to_string())
+        );
+    }
+
+    #[test]
+    fn specific_entries_take_precedence() {
+        let catalog = CreditCatalog::default();
+
+        assert_eq!(
+            code(&catalog, "Pagamento Indevido ou a Maior - Cofins"),
+            Some("PAGAMENTO_INDEVIDO".to_string())
+        );
+        assert_eq!(
+            code(&catalog, "Saldo Negativo de CSLL"),
+            Some("SALDO_NEGATIVO_CSLL".to_string())
+        );
+        assert_eq!(code(&catalog, "Crédito de Município"), None);
+        assert_eq!(code(&catalog, ""), None);
+    }
+}
@@ -0,0 +1,269 @@
+//! Debts compensated by each DCOMP, read from a companion SCC export.
+//!
+//! The export has its own layout, with one row per debt. Debts are linked to
+//! the `PerDcomp` records through the PER/DCOMP number.
+
+use chrono::NaiveDate;
+use rust_xlsxwriter::{SerializeFieldOptions, XlsxSerialize, serialize_option_datetime_to_excel};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    PerDcomp, SheetColumns,
+    structures::{FORMAT, string_as_date, string_as_f64, string_as_option_f64},
+};
+
+/// Name of the worksheet that receives the debts.
+pub const DEBITOS_SHEET_NAME: &str = "Débitos";
+
+/// A debt compensated by a DCOMP.
+///
+/// Fine (`Multa`) and interest (`Juros`) are often blank in the export.
+///
+/// Its workbook columns and value formats are those of [`Debito::sheet_columns`].
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Debito {
+    #[serde(rename = "PER/DCOMP", alias = "Número da DCOMP", alias = "DCOMP")]
+    pub per_dcomp: Option<String>,
+
+    #[serde(rename = "Código da Receita", alias = "Código Receita")]
+    pub codigo_receita: Option<String>,
+
+    #[serde(rename = "Denominação da Receita", alias = "Receita")]
+    pub denominacao_receita: Option<String>,
+
+    #[serde(
+        rename = "Período de Apuração",
+        alias = "Período Apuração",
+        alias = "PA"
+    )]
+    pub periodo_apuracao: Option<String>,
+
+    #[serde(default)]
+    #[serde(
+        rename = "Data de Vencimento",
+        alias = "Data Vencimento",
+        deserialize_with = "string_as_date",
+        serialize_with = "serialize_option_datetime_to_excel"
+    )]
+    pub data_vencimento: Option<NaiveDate>,
+
+    #[serde(
+        rename = "Valor Principal",
+        alias = "Principal",
+        deserialize_with = "string_as_f64"
+    )]
+    pub valor_principal: f64,
+
+    #[serde(default)]
+    #[serde(
+        rename = "Multa",
+        alias = "Valor Multa",
+        deserialize_with = "string_as_option_f64"
+    )]
+    pub valor_multa: Option<f64>,
+
+    #[serde(default)]
+    #[serde(
+        rename = "Juros",
+        alias = "Valor Juros",
+        deserialize_with = "string_as_option_f64"
+    )]
+    pub valor_juros: Option<f64>,
+
+    #[serde(
+        rename = "Valor Total",
+        alias = "Total",
+        alias = "Valor Total do Débito",
+        deserialize_with = "string_as_f64"
+    )]
+    pub valor_total: f64,
+}
+
+impl Debito {
+    /// The workbook columns, with their value formats.
+    pub fn sheet_columns() -> SheetColumns {
+        let fields = [
+            ("PER/DCOMP", Some(FORMAT.bold_centered.clone())),
+            ("Código da Receita", Some(FORMAT.centered.clone())),
+            ("Denominação da Receita", None),
+            ("Período de Apuração", Some(FORMAT.centered.clone())),
+            ("Data de Vencimento", Some(FORMAT.date.clone())),
+            ("Valor Principal", Some(FORMAT.currency.clone())),
+            ("Multa", Some(FORMAT.currency.clone())),
+            ("Juros", Some(FORMAT.currency.clone())),
+            ("Valor Total", Some(FORMAT.bold_currency.clone())),
+        ];
+        SheetColumns::new("Debito", &FORMAT.header, &fields)
+    }
+}
+
+impl XlsxSerialize for Debito {
+    fn to_serialize_field_options() -> SerializeFieldOptions {
+        Debito::sheet_columns().field_options().clone()
+    }
+}
+
+/// Fills `total_debitos` on every DCOMP with the sum of its compensated debts.
+///
+/// PER/DCOMP numbers are compared by their digits only, so "1234.5678-90"
+/// and "1234567890" match. Returns the number of debts whose PER/DCOMP was
+/// not found among `perdcomps`, counting the debts without a number.
+pub fn link_debitos(perdcomps: &mut [PerDcomp], debitos: &[Debito]) -> usize {
+    let mut totals: HashMap<String, f64> = HashMap::new();
+
+    for debito in debitos {
+        if let Some(number) = number(debito.per_dcomp.as_deref()) {
+            *totals.entry(number).or_default() += debito.valor_total;
+        }
+    }
+
+    let mut found: HashSet<String> = HashSet::new();
+
+    for perdcomp in perdcomps.iter_mut() {
+        let number = number(perdcomp.per_dcomp.as_deref());
+        perdcomp.total_debitos = number
+            .as_ref()
+            .and_then(|number| totals.get(number).copied());
+
+        if let Some(number) = number {
+            found.insert(number);
+        }
+    }
+
+    debitos
+        .iter()
+        .filter(|debito| {
+            number(debito.per_dcomp.as_deref()).is_none_or(|number| !found.contains(&number))
+        })
+        .count()
+}
+
+/// Drops the debts of the PER/DCOMP numbers in `removed`, the records left
+/// out by a filter, unless a record of `perdcomps` still has that number.
+pub fn drop_debitos_of(debitos: &mut Vec<Debito>, removed: &[String], perdcomps: &[PerDcomp]) {
+    let kept: HashSet<String> = perdcomps
+        .iter()
+        .filter_map(|perdcomp| number(perdcomp.per_dcomp.as_deref()))
+        .collect();
+    let removed: HashSet<String> = removed
+        .iter()
+        .filter_map(|text| number(Some(text)))
+        .filter(|number| !kept.contains(number))
+        .collect();
+
+    debitos.retain(|debito| {
+        number(debito.per_dcomp.as_deref()).is_none_or(|number| !removed.contains(&number))
+    });
+}
+
+/// The digits of a PER/DCOMP number; `None` when there are none.
+fn number(text: Option<&str>) -> Option<String> {
+    let digits: String = text?.chars().filter(char::is_ascii_digit).collect();
+    (!digits.is_empty()).then_some(digits)
+}
+
+#[cfg(test)]
+mod tests_debitos {
+    use super::*;
+    use crate::MyResult;
+
+    fn debito(per_dcomp: &str, valor_total: f64) -> Debito {
+        Debito {
+            per_dcomp: Some(per_dcomp.to_string()),
+            valor_total,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fine_and_interest_may_be_blank() -> MyResult<()> {
+        let csv = "PER/DCOMP;Valor Principal;Multa;Juros;Valor Total\n\
+                   123;100,00;;2,50;102,50\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(csv.as_bytes());
+
+        let debitos: Vec<Debito> = reader.deserialize().collect::<Result<_, _>>()?;
+
+        assert_eq!(debitos[0].valor_multa, None);
+        assert_eq!(debitos[0].valor_juros, Some(2.5));
+        assert_eq!(debitos[0].valor_total, 102.5);
+        Ok(())
+    }
+
+    #[test]
+    fn sums_debts_per_dcomp() {
+        let mut perdcomps = vec![
+            PerDcomp {
+                per_dcomp: Some("12345.67890.120921.1.3.04-1234".to_string()),
+                ..Default::default()
+            },
+            PerDcomp {
+                per_dcomp: Some("99999.99999.120921.1.1.04-0000".to_string()),
+                ..Default::default()
+            },
+        ];
+        let debitos = vec![
+            debito("12345.67890.120921.1.3.04-1234", 100.0),
+            debito("1234567890120921130412 34", 50.5),
+            debito("00000.00000.000000.0.0.00-0000", 1.0),
+        ];
+
+        let unmatched = link_debitos(&mut perdcomps, &debitos);
+
+        assert_eq!(perdcomps[0].total_debitos, Some(150.5));
+        assert_eq!(perdcomps[1].total_debitos, None);
+        assert_eq!(unmatched, 1);
+    }
+
+    #[test]
+    fn debts_of_filtered_records_are_dropped() {
+        let mut perdcomps = vec![
+            PerDcomp {
+                per_dcomp: Some("111".to_string()),
+                ..Default::default()
+            },
+            PerDcomp {
+                per_dcomp: Some("222".to_string()),
+                ..Default::default()
+            },
+        ];
+        let mut debitos = vec![
+            debito("111", 10.0),
+            debito("222", 20.0),
+            debito("333", 30.0),
+        ];
+
+        let unmatched = link_debitos(&mut perdcomps, &debitos);
+        let removed = perdcomps.remove(1);
+        drop_debitos_of(&mut debitos, &[removed.per_dcomp.unwrap()], &perdcomps);
+
+        assert_eq!(unmatched, 1);
+        assert_eq!(debitos, vec![debito("111", 10.0), debito("333", 30.0)]);
+    }
+
+    #[test]
+    fn debts_without_number_are_unmatched() {
+        let mut perdcomps = vec![
+            PerDcomp::default(),
+            PerDcomp {
+                per_dcomp: Some("-".to_string()),
+                ..Default::default()
+            },
+        ];
+        let debitos = vec![
+            Debito {
+                valor_total: 10.0,
+                ..Default::default()
+            },
+            debito(" ", 20.0),
+        ];
+
+        let unmatched = link_debitos(&mut perdcomps, &debitos);
+
+        assert_eq!(perdcomps[0].total_debitos, None);
+        assert_eq!(perdcomps[1].total_debitos, None);
+        assert_eq!(unmatched, 2);
+    }
+}
@@ -0,0 +1,170 @@
+//! Differences between two sets of PER/DCOMP records, for the `diff` subcommand.
+//!
+//! Records are matched by their `per_dcomp` number. The origin and the
+//! annotation columns are not compared: they describe the file and the
+//! analysis, not the document.
+
+use std::collections::BTreeMap;
+
+use crate::{Cell, PerDcomp, QueryResult, Tabular};
+
+/// A field whose value changed between the two sets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub per_dcomp: String,
+    /// Canonical header of the column.
+    pub column: &'static str,
+    pub previous: Cell,
+    pub current: Cell,
+}
+
+/// What was added, removed and changed from the previous records to the current ones.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordDiff {
+    /// `per_dcomp` numbers found only in the current records.
+    pub added: Vec<String>,
+    /// `per_dcomp` numbers found only in the previous records.
+    pub removed: Vec<String>,
+    pub changed: Vec<FieldChange>,
+}
+
+impl RecordDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// One row per added or removed document and per changed field.
+    pub fn table(&self) -> QueryResult {
+        let text = |value: &str| Cell::Text(value.to_string());
+
+        let added = self.added.iter().map(|per_dcomp| {
+            vec![
+                text("added"),
+                text(per_dcomp),
+                Cell::Null,
+                Cell::Null,
+                Cell::Null,
+            ]
+        });
+        let removed = self.removed.iter().map(|per_dcomp| {
+            vec![
+                text("removed"),
+                text(per_dcomp),
+                Cell::Null,
+                Cell::Null,
+                Cell::Null,
+            ]
+        });
+        let changed = self.changed.iter().map(|change| {
+            vec![
+                text("changed"),
+                text(&change.per_dcomp),
+                text(change.column),
+                change.previous.clone(),
+                change.current.clone(),
+            ]
+        });
+
+        QueryResult {
+            columns: ["Change", "PER/DCOMP", "Column", "Previous", "Current"]
+                .map(String::from)
+                .to_vec(),
+            rows: added.chain(removed).chain(changed).collect(),
+        }
+    }
+}
+
+/// Compares `previous` with `current`, both keyed by `per_dcomp`.
+///
+/// Records without a number are skipped. When a number appears more than
+/// once in a set, its last record is used.
+pub fn diff_records(previous: &[PerDcomp], current: &[PerDcomp]) -> RecordDiff {
+    let by_number = |perdcomps: &[PerDcomp]| -> BTreeMap<String, Vec<Cell>> {
+        perdcomps
+            .iter()
+            .filter_map(|perdcomp| Some((perdcomp.per_dcomp.clone()?, perdcomp.cells())))
+            .collect()
+    };
+    let previous = by_number(previous);
+    let current = by_number(current);
+
+    let compared: Vec<(usize, &'static str)> = PerDcomp::columns()
+        .into_iter()
+        .enumerate()
+        .filter(|(_, column)| PerDcomp::is_document_column(column))
+        .map(|(index, column)| (index, column.header))
+        .collect();
+
+    let mut diff = RecordDiff {
+        added: current
+            .keys()
+            .filter(|number| !previous.contains_key(*number))
+            .cloned()
+            .collect(),
+        removed: previous
+            .keys()
+            .filter(|number| !current.contains_key(*number))
+            .cloned()
+            .collect(),
+        changed: Vec::new(),
+    };
+
+    for (number, before) in &previous {
+        let Some(after) = current.get(number) else {
+            continue;
+        };
+        for &(index, column) in &compared {
+            if before[index] != after[index] {
+                diff.changed.push(FieldChange {
+                    per_dcomp: number.clone(),
+                    column,
+                    previous: before[index].clone(),
+                    current: after[index].clone(),
+                });
+            }
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests_diff {
+    use super::*;
+
+    #[test]
+    fn records_are_added_removed_and_changed() {
+        let previous = [
+            PerDcomp::sample("1", 10.0),
+            PerDcomp::sample("2", 20.0),
+            PerDcomp::sample("3", 30.0),
+        ];
+        let mut changed = PerDcomp::sample("2", 25.0);
+        changed.analise = Some("Revisado".to_string());
+        let current = [
+            changed,
+            PerDcomp::sample("3", 30.0),
+            PerDcomp::sample("4", 40.0),
+        ];
+
+        let diff = diff_records(&previous, &current);
+        let valor_do_per = PerDcomp::columns()
+            .into_iter()
+            .find(|column| column.name == "valor_do_per")
+            .unwrap();
+
+        assert_eq!(diff.added, ["4"]);
+        assert_eq!(diff.removed, ["1"]);
+        assert_eq!(
+            diff.changed,
+            [FieldChange {
+                per_dcomp: "2".to_string(),
+                column: valor_do_per.header,
+                previous: Cell::Money(20.0),
+                current: Cell::Money(25.0),
+            }]
+        );
+        assert_eq!(diff.table().rows.len(), 3);
+        assert!(diff_records(&current, &current).is_empty());
+    }
+}
@@ -0,0 +1,355 @@
+//! Cross-reference between PER/DCOMP claims and the EFD-Contribuições credit ledger.
+//!
+//! The ledger is a CSV with one credit balance per CNPJ, period and credit type.
+//! Monthly periods are grouped into quarters, which is how PIS/COFINS credits
+//! are claimed in PER/DCOMP.
+
+use log::warn;
+use rust_xlsxwriter::{SerializeFieldOptions, XlsxSerialize};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    CreditCatalog, PerDcomp, SheetColumns, Situacao, TipoDocumento, parse_quarter,
+    structures::{FORMAT, string_as_f64},
+};
+
+/// Name of the worksheet that receives the comparison.
+pub const EFD_SHEET_NAME: &str = "EFD x PERDCOMP";
+
+/// Claims up to this amount above the EFD credit are not flagged.
+const TOLERANCE: f64 = 0.01;
+
+/// A credit balance reported in EFD-Contribuições.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EfdCredit {
+    #[serde(rename = "CNPJ")]
+    pub cnpj: String,
+
+    #[serde(
+        rename = "Período",
+        alias = "Período de Apuração",
+        alias = "Periodo",
+        alias = "PA"
+    )]
+    pub periodo: String,
+
+    #[serde(rename = "Tipo de Crédito", alias = "Tipo Crédito")]
+    pub tipo_do_credito: String,
+
+    #[serde(
+        rename = "Valor do Crédito",
+        alias = "Valor Crédito",
+        alias = "Saldo",
+        deserialize_with = "string_as_f64"
+    )]
+    pub valor: f64,
+}
+
+/// Outcome of the comparison for one CNPJ, quarter and credit type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EfdStatus {
+    #[serde(rename = "Conforme")]
+    Conforme,
+    #[serde(rename = "Excesso")]
+    Excesso,
+    #[serde(rename = "Sem EFD")]
+    SemEfd,
+    #[serde(rename = "Sem PER/DCOMP")]
+    SemPerDcomp,
+}
+
+/// One row of the comparison worksheet.
+///
+/// Its workbook columns and value formats are those of [`EfdComparison::sheet_columns`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EfdComparison {
+    #[serde(rename = "CNPJ Base")]
+    pub cnpj_base: String,
+
+    #[serde(rename = "Ano")]
+    pub ano: u32,
+
+    #[serde(rename = "Trimestre")]
+    pub trimestre: u32,
+
+    #[serde(rename = "Código do Tipo de Crédito")]
+    pub codigo_do_credito: String,
+
+    #[serde(rename = "Crédito Declarado na EFD")]
+    pub credito_efd: f64,
+
+    #[serde(rename = "Valor Pedido em PER/DCOMP")]
+    pub valor_pedido: f64,
+
+    #[serde(rename = "Diferença (EFD - PER/DCOMP)")]
+    pub diferenca: f64,
+
+    #[serde(rename = "Quantidade de PER/DCOMP")]
+    pub documentos: u32,
+
+    #[serde(rename = "Situação")]
+    pub situacao: EfdStatus,
+}
+
+impl EfdComparison {
+    /// The workbook columns, with their value formats.
+    pub fn sheet_columns() -> SheetColumns {
+        let fields = [
+            ("CNPJ Base", Some(FORMAT.bold_centered.clone())),
+            ("Ano", Some(FORMAT.centered.clone())),
+            ("Trimestre", Some(FORMAT.centered.clone())),
+            ("Código do Tipo de Crédito", Some(FORMAT.centered.clone())),
+            ("Crédito Declarado na EFD", Some(FORMAT.currency.clone())),
+            ("Valor Pedido em PER/DCOMP", Some(FORMAT.currency.clone())),
+            (
+                "Diferença (EFD - PER/DCOMP)",
+                Some(FORMAT.bold_currency.clone()),
+            ),
+            ("Quantidade de PER/DCOMP", Some(FORMAT.centered.clone())),
+            ("Situação", Some(FORMAT.centered.clone())),
+        ];
+        SheetColumns::new("EfdComparison", &FORMAT.header, &fields)
+    }
+}
+
+impl XlsxSerialize for EfdComparison {
+    fn to_serialize_field_options() -> SerializeFieldOptions {
+        EfdComparison::sheet_columns().field_options().clone()
+    }
+}
+
+/// Join key: CNPJ base, year, quarter and canonical credit code.
+type Key = (String, u32, u32, String);
+
+#[derive(Debug, Default)]
+struct Totals {
+    efd: Option<f64>,
+    /// Amounts of the PER documents.
+    per: Option<f64>,
+    /// Credit used by the DCOMP documents.
+    dcomp: Option<f64>,
+    documentos: u32,
+}
+
+impl Totals {
+    /// The amount claimed: the PER documents, or the DCOMP documents when the
+    /// credit was compensated without a PER. A DCOMP that follows a PER uses
+    /// the credit that PER already claimed.
+    fn pedido(&self) -> Option<f64> {
+        self.per.or(self.dcomp)
+    }
+}
+
+/// Compares the PIS/COFINS amounts claimed in PER/DCOMP with the EFD credits.
+///
+/// Records are joined on CNPJ base, quarter and canonical credit code. Only
+/// the credit types flagged `efd` in the catalogue take part; cancelled and
+/// rectified documents no longer claim anything and are left out.
+/// EFD rows with an unknown credit type or period are skipped with a warning.
+pub fn compare_with_efd(
+    perdcomps: &[PerDcomp],
+    efd_credits: &[EfdCredit],
+    catalog: &CreditCatalog,
+) -> Vec<EfdComparison> {
+    let mut groups: BTreeMap<Key, Totals> = BTreeMap::new();
+
+    for credit in efd_credits {
+        let code = catalog.classify(&credit.tipo_do_credito);
+        let period = parse_quarter(&credit.periodo);
+
+        match (cnpj_base(&credit.cnpj), period, code) {
+            (Some(base), Some((ano, trimestre)), Some(code)) => {
+                let totals = groups
+                    .entry((base, ano, trimestre, code.code.clone()))
+                    .or_default();
+                *totals.efd.get_or_insert(0.0) += credit.valor;
+            }
+            _ => warn!("EFD row ignored (CNPJ, period or credit type unknown): {credit:?}"),
+        }
+    }
+
+    for perdcomp in perdcomps {
+        if matches!(
+            perdcomp.situacao,
+            Some(Situacao::Cancelado | Situacao::Retificado)
+        ) {
+            continue;
+        }
+
+        let Some(code) = perdcomp
+            .codigo_do_credito
+            .as_ref()
+            .filter(|code| catalog.is_efd(code))
+        else {
+            continue;
+        };
+
+        let holder = perdcomp
+            .cnpj_detentor_do_credito
+            .as_deref()
+            .and_then(cnpj_base)
+            .or_else(|| perdcomp.cnpj_declarante.as_deref().and_then(cnpj_base));
+
+        if let (Some(base), Some((ano, trimestre))) = (holder, perdcomp.quarter()) {
+            let totals = groups
+                .entry((base, ano, trimestre, code.clone()))
+                .or_default();
+            let claim = match perdcomp.tipo_do_documento {
+                Some(TipoDocumento::DeclaracaoDeCompensacao) => &mut totals.dcomp,
+                _ => &mut totals.per,
+            };
+            *claim.get_or_insert(0.0) += perdcomp.valor_do_per;
+            totals.documentos += 1;
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|((cnpj_base, ano, trimestre, codigo_do_credito), totals)| {
+            let credito_efd = totals.efd.unwrap_or_default();
+            let valor_pedido = totals.pedido().unwrap_or_default();
+
+            let situacao = match (totals.efd, totals.pedido()) {
+                (None, _) => EfdStatus::SemEfd,
+                (_, None) => EfdStatus::SemPerDcomp,
+                _ if valor_pedido > credito_efd + TOLERANCE => EfdStatus::Excesso,
+                _ => EfdStatus::Conforme,
+            };
+
+            EfdComparison {
+                cnpj_base,
+                ano,
+                trimestre,
+                codigo_do_credito,
+                credito_efd,
+                valor_pedido,
+                diferenca: credito_efd - valor_pedido,
+                documentos: totals.documentos,
+                situacao,
+            }
+        })
+        .collect()
+}
+
+/// Returns the first 8 digits of a CNPJ ("12.345.678/0001-90" -> "12345678").
+fn cnpj_base(cnpj: &str) -> Option<String> {
+    let digits: String = cnpj.chars().filter(char::is_ascii_digit).collect();
+    (digits.len() >= 8).then(|| digits[..8].to_string())
+}
+
+#[cfg(test)]
+mod tests_efd {
+    use super::*;
+    use crate::{CreditType, MyResult};
+
+    fn efd(periodo: &str, valor: f64) -> EfdCredit {
+        EfdCredit {
+            cnpj: "12345678000190".to_string(),
+            periodo: periodo.to_string(),
+            tipo_do_credito: "Cofins Não-Cumulativa - Exportação".to_string(),
+            valor,
+        }
+    }
+
+    #[test]
+    fn aggregates_months_into_quarters_and_flags_overclaim() {
+        let perdcomps = vec![
+            PerDcomp::sample("1", 250.0),
+            PerDcomp::sample("1", 100.0),
+            PerDcomp::sample("1", 50.0).with_period("2º TRIMESTRE", 2021),
+        ];
+        let efd_credits = vec![
+            efd("01/2021", 100.0),
+            efd("02/2021", 100.0),
+            efd("04/2021", 80.0),
+        ];
+
+        let rows = compare_with_efd(&perdcomps, &efd_credits, &CreditCatalog::default());
+
+        assert_eq!(rows.len(), 2);
+
+        assert_eq!((rows[0].ano, rows[0].trimestre), (2021, 1));
+        assert_eq!(rows[0].cnpj_base, "12345678");
+        assert_eq!(rows[0].credito_efd, 200.0);
+        assert_eq!(rows[0].valor_pedido, 350.0);
+        assert_eq!(rows[0].diferenca, -150.0);
+        assert_eq!(rows[0].documentos, 2);
+        assert_eq!(rows[0].situacao, EfdStatus::Excesso);
+
+        assert_eq!(rows[1].trimestre, 2);
+        assert_eq!(rows[1].situacao, EfdStatus::Conforme);
+    }
+
+    #[test]
+    fn cancelled_and_rectified_documents_are_not_claims() {
+        let mut cancelado = PerDcomp::sample("1", 500.0);
+        cancelado.situacao = Some(Situacao::Cancelado);
+        let mut retificado = PerDcomp::sample("1", 300.0);
+        retificado.situacao = Some(Situacao::Retificado);
+        let perdcomps = vec![cancelado, retificado, PerDcomp::sample("1", 150.0)];
+        let efd_credits = vec![efd("01/2021", 200.0)];
+
+        let rows = compare_with_efd(&perdcomps, &efd_credits, &CreditCatalog::default());
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].valor_pedido, 150.0);
+        assert_eq!(rows[0].documentos, 1);
+        assert_eq!(rows[0].situacao, EfdStatus::Conforme);
+    }
+
+    #[test]
+    fn dcomp_of_a_per_does_not_add_to_the_claim() {
+        let mut per = PerDcomp::sample("1", 150.0);
+        per.tipo_do_documento = Some(TipoDocumento::PedidoDeRessarcimento);
+        let mut dcomp = PerDcomp::sample("1", 120.0);
+        dcomp.tipo_do_documento = Some(TipoDocumento::DeclaracaoDeCompensacao);
+        let mut alone = PerDcomp::sample("1", 90.0).with_period("2º TRIMESTRE", 2021);
+        alone.tipo_do_documento = Some(TipoDocumento::DeclaracaoDeCompensacao);
+        let efd_credits = vec![efd("01/2021", 200.0), efd("04/2021", 80.0)];
+
+        let rows = compare_with_efd(
+            &[per, dcomp, alone],
+            &efd_credits,
+            &CreditCatalog::default(),
+        );
+
+        assert_eq!(rows[0].valor_pedido, 150.0);
+        assert_eq!(rows[0].documentos, 2);
+        assert_eq!(rows[0].situacao, EfdStatus::Conforme);
+        assert_eq!(rows[1].valor_pedido, 90.0);
+        assert_eq!(rows[1].situacao, EfdStatus::Excesso);
+    }
+
+    #[test]
+    fn eligible_credits_come_from_the_catalogue() -> MyResult<()> {
+        let catalog: Vec<CreditType> = serde_json::from_str(
+            r#"[{"code": "CRED_EXP", "description": "Exportação", "terms": [["exportacao"]], "efd": true},
+                {"code": "OUTRO", "description": "Outro", "terms": [["outro"]]}]"#,
+        )?;
+        let catalog = CreditCatalog { entries: catalog };
+        let mut exportacao = PerDcomp::sample("1", 100.0);
+        exportacao.codigo_do_credito = Some("CRED_EXP".to_string());
+        let mut outro = PerDcomp::sample("1", 100.0);
+        outro.codigo_do_credito = Some("OUTRO".to_string());
+
+        let rows = compare_with_efd(&[exportacao, outro], &[efd("01/2021", 100.0)], &catalog);
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].codigo_do_credito, "CRED_EXP");
+        assert_eq!(rows[0].situacao, EfdStatus::Conforme);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_missing_sides() {
+        let perdcomps = vec![PerDcomp::sample("1", 10.0).with_period("3º TRIMESTRE", 2021)];
+        let efd_credits = vec![efd("10/2021", 10.0)];
+
+        let rows = compare_with_efd(&perdcomps, &efd_credits, &CreditCatalog::default());
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].situacao, EfdStatus::SemEfd);
+        assert_eq!(rows[1].situacao, EfdStatus::SemPerDcomp);
+    }
+}
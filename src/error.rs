@@ -0,0 +1,381 @@
+//! Errors of the conversion, with the context needed to fix the input.
+//!
+//! Every failure carries what is known about where it happened: the file,
+//! the line, the column and the raw value. The messages are meant for the
+//! person running the tool, in Portuguese, and each category has its own
+//! process exit code (see [`PerDcompError::exit_code`]).
+
+use csv::StringRecord;
+use std::{
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// The error type of this crate.
+///
+/// | Variant      | Exit code | Cause                                       |
+/// |--------------|-----------|---------------------------------------------|
+/// | `Other`      | 1         | Anything else, e.g. an invalid SQL query    |
+/// | (clap)       | 2         | Invalid command line arguments              |
+/// | `Io`         | 3         | A file cannot be read or written            |
+/// | `Encoding`   | 4         | A line is neither UTF-8 nor WINDOWS-1252    |
+/// | `Csv`        | 5         | Malformed CSV, e.g. a row with extra fields |
+/// | `Parse`      | 6         | A field value of the wrong type             |
+/// | `Xlsx`       | 7         | A workbook cannot be read or written        |
+/// | `Config`     | 8         | An invalid JSON or TOML configuration file  |
+#[derive(Debug)]
+pub enum PerDcompError {
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    Encoding {
+        path: Option<PathBuf>,
+        line: Option<u64>,
+    },
+    Csv {
+        path: Option<PathBuf>,
+        line: Option<u64>,
+        message: String,
+    },
+    Parse {
+        path: Option<PathBuf>,
+        line: Option<u64>,
+        column: Option<String>,
+        value: Option<String>,
+        message: String,
+    },
+    Xlsx {
+        path: Option<PathBuf>,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    Config {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    Other(String),
+}
+
+impl PerDcompError {
+    /// The process exit code of the error category.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            PerDcompError::Other(_) => 1,
+            PerDcompError::Io { .. } => 3,
+            PerDcompError::Encoding { .. } => 4,
+            PerDcompError::Csv { .. } => 5,
+            PerDcompError::Parse { .. } => 6,
+            PerDcompError::Xlsx { .. } => 7,
+            PerDcompError::Config { .. } => 8,
+        }
+    }
+
+    /// The line of the input file, when known.
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            PerDcompError::Encoding { line, .. }
+            | PerDcompError::Csv { line, .. }
+            | PerDcompError::Parse { line, .. } => *line,
+            _ => None,
+        }
+    }
+
+    /// Sets the file of the error, unless it already has one.
+    pub fn with_path(mut self, file: &Path) -> Self {
+        match &mut self {
+            PerDcompError::Io { path, .. }
+            | PerDcompError::Encoding { path, .. }
+            | PerDcompError::Csv { path, .. }
+            | PerDcompError::Parse { path, .. }
+            | PerDcompError::Xlsx { path, .. }
+            | PerDcompError::Config { path, .. } => {
+                path.get_or_insert_with(|| file.to_path_buf());
+            }
+            PerDcompError::Other(_) => {}
+        }
+        self
+    }
+
+    /// Converts a CSV error of `record`, naming the column and the raw value.
+    ///
+    /// The CSV reader only reports the column of its own parse errors. For the
+    /// errors of the custom deserializers, which quote the raw value, the column
+    /// is the only one holding that value.
+    pub fn from_record(error: csv::Error, headers: &StringRecord, record: &StringRecord) -> Self {
+        let field = match error.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err.field().map(|field| field as usize),
+            _ => None,
+        };
+
+        let mut error = PerDcompError::from(error);
+        if let PerDcompError::Parse {
+            column,
+            value,
+            message,
+            ..
+        } = &mut error
+        {
+            let field = field.or_else(|| {
+                let quoted = quoted_value(message)?;
+                let mut matches = record
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, field)| field.trim() == quoted.trim());
+                match (matches.next(), matches.next()) {
+                    (Some((index, _)), None) => Some(index),
+                    _ => None,
+                }
+            });
+
+            if let Some(field) = field {
+                *column = headers.get(field).map(String::from);
+                *value = record.get(field).map(String::from);
+            }
+        }
+        error
+    }
+}
+
+/// The text between the first and the last double quote of a message.
+fn quoted_value(message: &str) -> Option<&str> {
+    let start = message.find('"')?;
+    let end = message.rfind('"')?;
+    (end > start).then(|| &message[start + 1..end])
+}
+
+/// `Arquivo "perdcomp.csv", linha 12: ` or nothing, depending on what is known.
+fn location(path: &Option<PathBuf>, line: Option<u64>) -> String {
+    match (path, line) {
+        (Some(path), Some(line)) => format!("Arquivo {:?}, linha {line}: ", path.display()),
+        (Some(path), None) => format!("Arquivo {:?}: ", path.display()),
+        (None, Some(line)) => format!("Linha {line}: "),
+        (None, None) => String::new(),
+    }
+}
+
+impl fmt::Display for PerDcompError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PerDcompError::Io { path, source } => {
+                write!(
+                    f,
+                    "{}erro de leitura ou gravação: {source}",
+                    location(path, None)
+                )
+            }
+            PerDcompError::Encoding { path, line } => write!(
+                f,
+                "{}o texto não está em UTF-8 nem em WINDOWS-1252.",
+                location(path, *line)
+            ),
+            PerDcompError::Csv {
+                path,
+                line,
+                message,
+            } => write!(f, "{}CSV malformado: {message}", location(path, *line)),
+            PerDcompError::Parse {
+                path,
+                line,
+                column,
+                value,
+                message,
+            } => {
+                write!(f, "{}", location(path, *line))?;
+                match (column, value) {
+                    (Some(column), Some(value)) => {
+                        write!(f, "valor {value:?} inválido na coluna {column:?}")?
+                    }
+                    (Some(column), None) => write!(f, "valor inválido na coluna {column:?}")?,
+                    _ => write!(f, "valor inválido")?,
+                }
+                write!(f, " ({})", message.trim())
+            }
+            PerDcompError::Xlsx { path, source } => {
+                write!(f, "{}erro na planilha: {source}", location(path, None))
+            }
+            PerDcompError::Config { path, message } => {
+                write!(
+                    f,
+                    "{}configuração inválida: {message}",
+                    location(path, None)
+                )
+            }
+            PerDcompError::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl Error for PerDcompError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PerDcompError::Io { source, .. } => Some(source),
+            PerDcompError::Xlsx { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PerDcompError {
+    fn from(source: io::Error) -> Self {
+        PerDcompError::Io { path: None, source }
+    }
+}
+
+impl From<csv::Error> for PerDcompError {
+    fn from(error: csv::Error) -> Self {
+        let line = error.position().map(csv::Position::line);
+        match error.into_kind() {
+            csv::ErrorKind::Io(source) => PerDcompError::Io { path: None, source },
+            csv::ErrorKind::Utf8 { .. } => PerDcompError::Encoding { path: None, line },
+            csv::ErrorKind::UnequalLengths {
+                expected_len, len, ..
+            } => PerDcompError::Csv {
+                path: None,
+                line,
+                message: format!("{len} campos, mas o cabeçalho tem {expected_len}"),
+            },
+            csv::ErrorKind::Deserialize { err, .. } => PerDcompError::Parse {
+                path: None,
+                line,
+                column: None,
+                value: None,
+                message: err.kind().to_string(),
+            },
+            kind => PerDcompError::Csv {
+                path: None,
+                line,
+                message: format!("{kind:?}"),
+            },
+        }
+    }
+}
+
+impl From<rust_xlsxwriter::XlsxError> for PerDcompError {
+    fn from(source: rust_xlsxwriter::XlsxError) -> Self {
+        PerDcompError::Xlsx {
+            path: None,
+            source: Box::new(source),
+        }
+    }
+}
+
+impl From<calamine::Error> for PerDcompError {
+    fn from(source: calamine::Error) -> Self {
+        PerDcompError::Xlsx {
+            path: None,
+            source: Box::new(source),
+        }
+    }
+}
+
+impl From<serde_json::Error> for PerDcompError {
+    fn from(error: serde_json::Error) -> Self {
+        PerDcompError::Config {
+            path: None,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<toml::de::Error> for PerDcompError {
+    fn from(error: toml::de::Error) -> Self {
+        PerDcompError::Config {
+            path: None,
+            message: error.message().to_string(),
+        }
+    }
+}
+
+impl From<tempfile::PathPersistError> for PerDcompError {
+    fn from(error: tempfile::PathPersistError) -> Self {
+        PerDcompError::Io {
+            path: Some(error.path.to_path_buf()),
+            source: error.error,
+        }
+    }
+}
+
+impl From<parquet::errors::ParquetError> for PerDcompError {
+    fn from(error: parquet::errors::ParquetError) -> Self {
+        PerDcompError::Other(format!("Erro ao gravar Parquet: {error}"))
+    }
+}
+
+impl From<rusqlite::Error> for PerDcompError {
+    fn from(error: rusqlite::Error) -> Self {
+        PerDcompError::Other(format!("Erro na consulta SQL: {error}"))
+    }
+}
+
+impl From<clap::Error> for PerDcompError {
+    fn from(error: clap::Error) -> Self {
+        PerDcompError::Other(error.to_string())
+    }
+}
+
+impl From<String> for PerDcompError {
+    fn from(message: String) -> Self {
+        PerDcompError::Other(message)
+    }
+}
+
+impl From<&str> for PerDcompError {
+    fn from(message: &str) -> Self {
+        PerDcompError::Other(message.to_string())
+    }
+}
+
+/// Adds the file to the error of a `Result`.
+pub trait WithPath<T> {
+    fn with_path(self, path: &Path) -> Result<T, PerDcompError>;
+}
+
+impl<T, E> WithPath<T> for Result<T, E>
+where
+    E: Into<PerDcompError>,
+{
+    fn with_path(self, path: &Path) -> Result<T, PerDcompError> {
+        self.map_err(|error| error.into().with_path(path))
+    }
+}
+
+#[cfg(test)]
+mod tests_error {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Row {
+        #[serde(rename = "Nome")]
+        _nome: String,
+        #[serde(
+            rename = "Valor",
+            deserialize_with = "crate::structures::string_as_f64"
+        )]
+        _valor: f64,
+    }
+
+    #[test]
+    fn parse_errors_name_the_column_and_value() {
+        let data = "Nome;Valor\nAna;1,00\nBia;12x\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(data.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        let record = reader.records().nth(1).unwrap().unwrap();
+
+        let error = record.deserialize::<Row>(Some(&headers)).unwrap_err();
+        let error = PerDcompError::from_record(error, &headers, &record)
+            .with_path(Path::new("perdcomp.csv"));
+
+        assert_eq!(error.exit_code(), 6);
+        assert!(matches!(
+            &error,
+            PerDcompError::Parse { line: Some(3), column: Some(column), value: Some(value), .. }
+                if column == "Valor" && value == "12x"
+        ));
+        assert!(error.to_string().starts_with(
+            "Arquivo \"perdcomp.csv\", linha 3: valor \"12x\" inválido na coluna \"Valor\""
+        ));
+    }
+}
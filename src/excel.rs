@@ -6,18 +6,29 @@
 //! this module coordinates workbook construction, parallel worksheet populating,
 //! column hiding, and diagnostic logging.
 
+use log::{Level, debug, info, log_enabled, trace, warn};
 use rayon::prelude::*;
-use rust_xlsxwriter::{Format, FormatAlign, Workbook, Worksheet, XlsxError, XlsxSerialize};
+use rust_xlsxwriter::{
+    CustomSerializeField, Format, FormatAlign, SerializeFieldOptions, Table, Workbook, Worksheet,
+    XlsxError, XlsxSerialize,
+};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use std::path::Path;
 
-use crate::structures::FONT_SIZE;
+use crate::{
+    Annotations, CHECKBOX_WIDTH, Cell, ColumnWidths, FormatRules, HEADER_ROW_HEIGHT, MyResult,
+    TableStats, WithPath, collect_column_stats, money_text, progress_bar,
+    structures::{FONT_SIZE, FORMAT},
+    text_width,
+};
 
 /// The maximum number of rows allowed in a single worksheet.
 /// Excel's strict physical limit is 1,048,576 rows. We split at 1,000,000
 /// to maintain a clean margin.
-const MAX_NUMBER_OF_ROWS: usize = 1_000_000;
+pub(crate) const MAX_NUMBER_OF_ROWS: usize = 1_000_000;
+
+/// Rows serialized between two updates of a worksheet progress bar.
+const PROGRESS_STEP: usize = 10_000;
 
 /// Writes any slice of serializable items implementing XlsxSerialize into an Excel file.
 ///
@@ -27,48 +38,230 @@ const MAX_NUMBER_OF_ROWS: usize = 1_000_000;
 ///
 /// # Errors
 ///
-/// Returns an [`XlsxError`] if the format setup, parallel serialization, or file system
-/// write operation encounters an issue.
+/// Returns a [`PerDcompError::Xlsx`](crate::PerDcompError) if the format setup, parallel
+/// serialization, or file system write operation encounters an issue.
 pub fn write_xlsx<'de, T, P>(
     lines: &[T],
     sheet_name: &str,
     output_file: P,
     hide_cols: &[u16],
-    verbose: bool,
-) -> Result<(), XlsxError>
+) -> MyResult<()>
 where
     P: AsRef<Path>,
     T: Serialize + Deserialize<'de> + XlsxSerialize + Send + Sync,
 {
     // 1. Exit early if the dataset is empty to prevent creating a corrupted, zero-byte file.
     if lines.is_empty() {
-        eprintln!("Warning: Input data is empty. Skipping XLSX generation.");
+        warn!("Input data is empty. Skipping XLSX generation.");
         return Ok(());
     }
 
-    let output_path = output_file.as_ref();
-
-    // Log the file creation process.
-    eprintln!("Write XLSX File (Parallel Mode): {output_path:?}");
-
-    // 2. Concurrently calculate optimal column widths using Rayon.
-    // This parses structures on worker threads to avoid stalling the main writer process.
-    let col_widths = calculate_max_column_widths(lines, verbose);
+    let mut workbook = new_workbook()?;
+    push_worksheets(&mut workbook, lines, sheet_name, hide_cols)?;
+    save_workbook(&mut workbook, output_file)
+}
 
-    // 3. Initialize a new empty Excel workbook.
+/// Creates an empty workbook configured with the global formatting defaults.
+///
+/// Standard cells are vertically centered and use [`FONT_SIZE`], with rows
+/// set to 24pt height and columns to a fallback width of 80pt.
+pub fn new_workbook() -> Result<Workbook, XlsxError> {
     let mut workbook = Workbook::new();
 
-    // 4. Define fallback formatting rules for standard worksheet cells.
-    // This vertically centers content and applies standard configuration sizes.
     let default_format = Format::new()
         .set_align(FormatAlign::VerticalCenter)
         .set_font_size(FONT_SIZE);
 
-    // 5. Establish global workbook formatting defaults.
-    // Set standard rows to 24pt height and columns to a fallback width of 80pt.
     workbook.set_default_format(&default_format, 24, 80)?;
 
-    // 6. Partition datasets into parallel chunks and generate worksheets concurrently.
+    Ok(workbook)
+}
+
+/// Generates the worksheets for one dataset and appends them to `workbook`.
+///
+/// Datasets larger than [`MAX_NUMBER_OF_ROWS`] are split into numbered sheets
+/// (`"PERDComp"`, `"PERDComp 2"`, ...). Worksheets are populated concurrently and
+/// pushed in order. An empty dataset adds no worksheet.
+pub fn push_worksheets<'de, T>(
+    workbook: &mut Workbook,
+    lines: &[T],
+    sheet_name: &str,
+    hide_cols: &[u16],
+) -> Result<(), XlsxError>
+where
+    T: Serialize + Deserialize<'de> + XlsxSerialize + Send + Sync,
+{
+    push_worksheets_with_options(
+        workbook,
+        lines,
+        sheet_name,
+        hide_cols,
+        &SheetOptions::default(),
+    )
+}
+
+/// Optional worksheet features applied to every chunk sheet of a dataset.
+#[derive(Default, Clone, Copy)]
+pub struct SheetOptions<'a> {
+    /// Replaces the table declared by the serialized type, e.g. to add a total row.
+    ///
+    /// Each chunk sheet receives its own copy of the table, so a total row
+    /// (see [`Table::set_total_row`]) sums only the rows of that sheet and
+    /// follows the table filters.
+    pub table: Option<&'a Table>,
+    /// Conditional formats matched against the column headers.
+    pub format_rules: Option<&'a FormatRules>,
+    /// Per-column width overrides; the embedded ones when `None`.
+    pub column_widths: Option<&'a ColumnWidths>,
+    /// Statistics already collected for the whole dataset, reused for the widths.
+    pub stats: Option<&'a TableStats>,
+    /// Dropdowns of the annotation columns.
+    pub annotations: Option<&'a Annotations>,
+    /// Writes only these serialized columns, instead of every field of the type.
+    pub columns: Option<&'a SheetColumns>,
+}
+
+/// The serialized columns written to a sheet, with their value formats.
+///
+/// Fields left out are not written at all: their values are skipped by the
+/// serializer and their statistics are dropped before the widths are set.
+#[derive(Clone)]
+pub struct SheetColumns {
+    headers: Vec<String>,
+    value_formats: Vec<Option<Format>>,
+    field_options: SerializeFieldOptions,
+}
+
+impl SheetColumns {
+    /// `fields` are the serialized headers, in field order, with their value formats.
+    pub fn new(
+        struct_name: &str,
+        header_format: &Format,
+        fields: &[(&str, Option<Format>)],
+    ) -> Self {
+        let custom_headers: Vec<CustomSerializeField> = fields
+            .iter()
+            .map(|(header, value_format)| {
+                let field = CustomSerializeField::new(*header);
+                match value_format {
+                    Some(format) => field.set_value_format(format.clone()),
+                    None => field,
+                }
+            })
+            .collect();
+
+        Self {
+            headers: fields
+                .iter()
+                .map(|(header, _)| header.to_string())
+                .collect(),
+            value_formats: fields
+                .iter()
+                .map(|(_, value_format)| value_format.clone())
+                .collect(),
+            field_options: SerializeFieldOptions::new()
+                .set_header_format(header_format.clone())
+                .set_table(Table::new())
+                .set_struct_name(struct_name)
+                .use_custom_headers_only(true)
+                .set_custom_headers(&custom_headers),
+        }
+    }
+
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    /// The value format of the column with this header.
+    fn value_format(&self, header: &str) -> Option<&Format> {
+        let index = self.headers.iter().position(|h| h == header)?;
+        self.value_formats[index].as_ref()
+    }
+
+    /// Values of the column with this header are written in bold.
+    pub fn is_bold(&self, header: &str) -> bool {
+        self.value_format(header)
+            .is_some_and(|format| FORMAT.is_bold(format))
+    }
+
+    /// Values of the column with this header are dates, displayed as `dd/mm/aaaa`.
+    pub fn is_date(&self, header: &str) -> bool {
+        self.value_format(header) == Some(&FORMAT.date)
+    }
+
+    /// The header and value formats, for [`XlsxSerialize::to_serialize_field_options`].
+    pub fn field_options(&self) -> &SerializeFieldOptions {
+        &self.field_options
+    }
+
+    /// Keeps the statistics of the written columns only.
+    pub fn select(&self, stats: &TableStats) -> TableStats {
+        TableStats {
+            rows: stats.rows,
+            columns: stats
+                .columns
+                .iter()
+                .filter(|column| self.headers.contains(&column.header))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Same as [`push_worksheets`], with the extra features of [`SheetOptions`].
+pub fn push_worksheets_with_options<'de, T>(
+    workbook: &mut Workbook,
+    lines: &[T],
+    sheet_name: &str,
+    hide_cols: &[u16],
+    options: &SheetOptions,
+) -> Result<(), XlsxError>
+where
+    T: Serialize + Deserialize<'de> + XlsxSerialize + Send + Sync,
+{
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    // 1. Concurrently calculate optimal column widths using Rayon.
+    // This walks the records on worker threads to avoid stalling the main writer process.
+    let default_widths;
+    let column_widths = match options.column_widths {
+        Some(column_widths) => column_widths,
+        None => {
+            default_widths = ColumnWidths::default();
+            &default_widths
+        }
+    };
+    if log_enabled!(Level::Trace) {
+        print_first_row(lines);
+    }
+
+    // Column statistics are gathered in one parallel pass, unless the caller already has them.
+    let computed_stats;
+    let stats = match options.stats {
+        Some(stats) => stats,
+        None => {
+            computed_stats = collect_column_stats(lines, column_widths, options.columns);
+            &computed_stats
+        }
+    };
+    let selected_stats;
+    let stats = match options.columns {
+        Some(columns) => {
+            selected_stats = columns.select(stats);
+            &selected_stats
+        }
+        None => stats,
+    };
+    let col_widths = stats.column_widths(column_widths);
+    let headers: Vec<String> = stats
+        .columns
+        .iter()
+        .map(|column| column.header.clone())
+        .collect();
+
+    // 2. Partition datasets into parallel chunks and generate worksheets concurrently.
     // This avoids thread-blocking bottlenecks during major document assembly tasks.
     let worksheets_result: Result<Vec<Worksheet>, XlsxError> = lines
         .par_chunks(MAX_NUMBER_OF_ROWS)
@@ -76,38 +269,205 @@ where
         .map(|(index, data_chunk)| {
             let dynamic_sheet_name = format_sheet_name(sheet_name, index + 1);
             if index > 0 {
-                eprintln!(
-                    "Notice: Dataset size ({}) exceeds limit ({}).
+                info!(
+                    "Dataset size ({}) exceeds limit ({}). \
                     Preparing additional sheet in parallel: {dynamic_sheet_name}",
                     lines.len(),
                     MAX_NUMBER_OF_ROWS
                 );
             }
-            create_and_populate_worksheet(&dynamic_sheet_name, hide_cols, &col_widths, data_chunk)
+            create_and_populate_worksheet(
+                &dynamic_sheet_name,
+                hide_cols,
+                &col_widths,
+                &headers,
+                options,
+                data_chunk,
+            )
         })
         .collect();
 
-    // 7. Sequentially push completed worksheets onto the main thread's workbook registry.
+    // 3. Sequentially push completed worksheets onto the main thread's workbook registry.
     for worksheet in worksheets_result? {
         workbook.push_worksheet(worksheet);
     }
 
-    // 8. Commit structural changes to disk, logging any filesystem issues encountered.
-    workbook.save(output_path).inspect_err(|err| {
-        eprintln!("fn write_xlsx()");
-        eprintln!("File {output_path:?}");
-        eprintln!("Failed to write XLSX file: {err}");
-    })?;
+    Ok(())
+}
+
+/// Saves the workbook to disk.
+///
+/// A workbook without worksheets is not written, to avoid producing a file
+/// that contains only Excel's blank default sheet. Failures are returned
+/// as [`PerDcompError::Xlsx`](crate::PerDcompError) with the output path.
+pub fn save_workbook<P>(workbook: &mut Workbook, output_file: P) -> MyResult<()>
+where
+    P: AsRef<Path>,
+{
+    let output_path = output_file.as_ref();
+
+    if workbook.worksheets().is_empty() {
+        warn!("Input data is empty. Skipping XLSX generation.");
+        return Ok(());
+    }
+
+    // Log the file creation process.
+    debug!("Write XLSX file (parallel mode): {output_path:?}");
+
+    workbook.save(output_path).with_path(output_path)?;
 
     // Log the successful file generation path.
-    eprintln!("Success: XLSX file generated at {output_path:?}");
+    info!("XLSX file generated at {output_path:?}");
+    Ok(())
+}
+
+/// Appends a worksheet built from dynamic columns and typed cells.
+///
+/// Used for results whose layout is only known at runtime, such as SQL
+/// queries. Cells take their format from [`FORMAT`] according to their type.
+pub fn push_cells_worksheet(
+    workbook: &mut Workbook,
+    sheet_name: &str,
+    headers: &[String],
+    rows: &[Vec<Cell>],
+) -> Result<(), XlsxError> {
+    push_cells_worksheets(
+        workbook,
+        sheet_name,
+        headers,
+        rows,
+        &[],
+        &SheetOptions::default(),
+    )
+}
+
+/// Same as [`push_cells_worksheet`], split into numbered sheets like [`push_worksheets`].
+///
+/// Widths are measured once over all rows, so every chunk sheet shares them.
+/// Of the [`SheetOptions`], the format rules, column widths and annotation
+/// dropdowns are applied; the table and stats belong to serialized types.
+/// An empty dataset still adds one worksheet with the headers.
+pub fn push_cells_worksheets(
+    workbook: &mut Workbook,
+    sheet_name: &str,
+    headers: &[String],
+    rows: &[Vec<Cell>],
+    hide_cols: &[u16],
+    options: &SheetOptions,
+) -> Result<(), XlsxError> {
+    let default_widths;
+    let column_widths = match options.column_widths {
+        Some(column_widths) => column_widths,
+        None => {
+            default_widths = ColumnWidths::default();
+            &default_widths
+        }
+    };
+
+    let col_widths: Vec<f64> = headers
+        .par_iter()
+        .enumerate()
+        .map(|(col, header)| {
+            let max_width = rows
+                .iter()
+                .filter_map(|row| row.get(col))
+                .map(|cell| match cell {
+                    Cell::Money(value) => text_width(&money_text(*value), FONT_SIZE, false),
+                    Cell::Date(_) => text_width("00/00/0000", FONT_SIZE, false),
+                    Cell::Bool(_) => CHECKBOX_WIDTH,
+                    _ => text_width(&cell.to_text(), FONT_SIZE, false),
+                })
+                .fold(0.0, f64::max);
+            column_widths.fit(header, max_width)
+        })
+        .collect();
+
+    let chunks: Vec<&[Vec<Cell>]> = if rows.is_empty() {
+        vec![rows]
+    } else {
+        rows.chunks(MAX_NUMBER_OF_ROWS).collect()
+    };
+
+    let worksheets_result: Result<Vec<Worksheet>, XlsxError> = chunks
+        .into_par_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let dynamic_sheet_name = format_sheet_name(sheet_name, index + 1);
+            let mut worksheet = Worksheet::new();
+            worksheet.set_name(&dynamic_sheet_name)?;
+
+            for (col, header) in headers.iter().enumerate() {
+                worksheet.write_string_with_format(0, col as u16, header, &FORMAT.header)?;
+            }
+
+            let progress = progress_bar(
+                chunk.len() as u64,
+                format!("Populating '{dynamic_sheet_name}'"),
+            );
+            for (index, row) in chunk.iter().enumerate() {
+                let row_idx = (index + 1) as u32;
+                for (col, cell) in row.iter().enumerate() {
+                    write_cell(&mut worksheet, row_idx, col as u16, cell)?;
+                }
+                progress.inc(1);
+            }
+            progress.finish_and_clear();
+
+            worksheet.set_row_height(0, HEADER_ROW_HEIGHT)?;
+            worksheet.set_freeze_panes(1, 0)?;
+
+            if !headers.is_empty() {
+                worksheet.autofilter(0, 0, chunk.len() as u32, (headers.len() - 1) as u16)?;
+            }
+
+            for (col, &width) in col_widths.iter().enumerate() {
+                worksheet.set_column_width(col as u16, width)?;
+            }
+            for &col in hide_cols {
+                worksheet.set_column_hidden(col)?;
+            }
+
+            if let Some(format_rules) = options.format_rules {
+                format_rules.apply(&mut worksheet, headers, chunk.len() as u32)?;
+            }
+            if let Some(annotations) = options.annotations {
+                annotations.add_validations(&mut worksheet, headers, chunk.len() as u32)?;
+            }
+
+            Ok(worksheet)
+        })
+        .collect();
+
+    for worksheet in worksheets_result? {
+        workbook.push_worksheet(worksheet);
+    }
+
+    Ok(())
+}
+
+/// Writes one typed cell with the format of its type. Null cells are left blank.
+fn write_cell(worksheet: &mut Worksheet, row: u32, col: u16, cell: &Cell) -> Result<(), XlsxError> {
+    match cell {
+        Cell::Null => return Ok(()),
+        Cell::Text(text) => worksheet.write_string(row, col, text)?,
+        Cell::Money(value) => {
+            worksheet.write_number_with_format(row, col, *value, &FORMAT.currency)?
+        }
+        Cell::Integer(value) => {
+            worksheet.write_number_with_format(row, col, *value as f64, &FORMAT.centered)?
+        }
+        Cell::Date(date) => worksheet.write_with_format(row, col, date, &FORMAT.date)?,
+        Cell::Bool(value) => {
+            worksheet.write_boolean_with_format(row, col, *value, &FORMAT.checkbox)?
+        }
+    };
     Ok(())
 }
 
 /// Names the worksheet depending on the chunk split index.
 ///
 /// Suffixes are omitted if only one chunk exists.
-fn format_sheet_name(base_name: &str, index: usize) -> String {
+pub(crate) fn format_sheet_name(base_name: &str, index: usize) -> String {
     if index > 1 {
         format!("{} {}", base_name, index)
     } else {
@@ -122,199 +482,161 @@ fn format_sheet_name(base_name: &str, index: usize) -> String {
 fn create_and_populate_worksheet<'de, T>(
     sheet_name: &str,
     hide_cols: &[u16],
-    col_widths: &[u16],
+    col_widths: &[f64],
+    headers: &[String],
+    options: &SheetOptions,
     data: &[T],
 ) -> Result<Worksheet, XlsxError>
 where
     T: Serialize + Deserialize<'de> + XlsxSerialize,
 {
     // Log the worksheet assembly process, matching the desired columns and rows count.
-    eprintln!(
-        "Info: Populating worksheet '{}' with {} columns and {} rows.",
+    debug!(
+        "Populating worksheet '{}' with {} columns and {} rows.",
         sheet_name,
         col_widths.len(),
         data.len()
     );
 
     let mut worksheet = Worksheet::new();
-    worksheet.set_name(sheet_name)?;
-
-    // 1. Serialize headers.
-    // They will inherit the struct level `#[xlsx(header_format = ...)]` automatically.
-    worksheet.set_serialize_headers::<T>(0, 0)?;
 
-    // 2. Serialize the data rows.
+    // 1. Name the sheet, serialize headers and lay out the columns.
+    prepare_worksheet(
+        &mut worksheet,
+        sheet_name,
+        hide_cols,
+        col_widths,
+        options.table,
+        options.columns,
+        data.first(),
+    )?;
+
+    // 2. Serialize the data rows, in steps that advance the progress bar.
     // Fields mapped with `#[xlsx(value_format = ...)]` apply explicit layouts,
     // while unformatted fields fall back to the workbook's Calibri 14 default.
-    worksheet.serialize(&data)?;
-
-    // 3. Configure the exact height of the header row (Row 0) to 42.0 pixels/points.
-    worksheet.set_row_height(0, 62.0)?;
-    worksheet.set_freeze_panes(1, 0)?;
+    let progress = progress_bar(data.len() as u64, format!("Populating '{sheet_name}'"));
+    for rows in data.chunks(PROGRESS_STEP) {
+        worksheet.serialize(&rows)?;
+        progress.inc(rows.len() as u64);
+    }
+    progress.finish_and_clear();
 
-    // 4. Set dynamically calculated column widths.
-    for (col_idx, &width) in col_widths.iter().enumerate() {
-        worksheet.set_column_width(col_idx as u16, width as f64)?;
+    // 3. Highlight cells according to the conditional formatting rules.
+    if let Some(format_rules) = options.format_rules {
+        format_rules.apply(&mut worksheet, headers, data.len() as u32)?;
     }
 
-    // 5. Hide target empty or requested columns.
-    if !hide_cols.is_empty() {
-        eprintln!(
-            "Info: Hiding {} columns in worksheet '{}'...",
-            hide_cols.len(),
-            sheet_name
-        );
-        for &col_idx in hide_cols {
-            worksheet.set_column_hidden(col_idx)?;
-        }
+    // 4. Offer the dropdowns of the annotation columns.
+    if let Some(annotations) = options.annotations {
+        annotations.add_validations(&mut worksheet, headers, data.len() as u32)?;
     }
 
     // Log the successful population statement.
-    eprintln!(
-        "Info: Worksheet '{}' populated and formatted successfully.",
+    debug!(
+        "Worksheet '{}' populated and formatted successfully.",
         sheet_name
     );
     Ok(worksheet)
 }
 
-/// Computes the maximum character length of all cells for each column in parallel.
-///
-/// By converting records to intermediate `serde_json::Value` structures, this function
-/// scans the grid across multiple threads using Rayon. It determines baseline widths
-/// from header keys, checks row contents, adds padding for visual readability,
-/// and applies safety boundaries to prevent excessively narrow or wide columns.
-///
-/// # Performance
+/// Names a worksheet, writes the serialization headers and lays out the columns.
 ///
-/// Rayon divides the dataset into balanced workloads. Parallel reduction merges
-/// the local maximum widths with zero allocation overhead during reduction steps.
-pub fn calculate_max_column_widths<'de, T>(data: &[T], verbose: bool) -> Vec<u16>
+/// Everything here happens before the first data row, so it is shared by the
+/// in-memory worksheets and the constant memory ones of the streaming writer.
+pub(crate) fn prepare_worksheet<T>(
+    worksheet: &mut Worksheet,
+    sheet_name: &str,
+    hide_cols: &[u16],
+    col_widths: &[f64],
+    table: Option<&Table>,
+    columns: Option<&SheetColumns>,
+    first: Option<&T>,
+) -> Result<(), XlsxError>
 where
-    T: Serialize + Deserialize<'de> + Send + Sync,
+    T: Serialize + XlsxSerialize,
 {
-    // 1. Safe boundary exit for empty slices.
-    if data.is_empty() {
-        return Vec::new();
-    }
-
-    // 2. Convert the first record to an intermediate JSON format to evaluate structure properties.
-    let first_val = serde_json::to_value(&data[0]).unwrap_or(Value::Null);
+    worksheet.set_name(sheet_name)?;
 
-    // 3. Print the first data row properties if verbose diagnostics mode is active.
-    if verbose && let Ok(pretty_json) = serde_json::to_string_pretty(&first_val) {
-        eprintln!(
-            "First data row representation (explicit structure check):\n{}\n",
-            pretty_json
-        );
+    // 1. Serialize headers.
+    // They will inherit the struct level `#[xlsx(header_format = ...)]` automatically.
+    // A custom table keeps the field attributes and only replaces `#[xlsx(table = ...)]`.
+    let field_options = match (columns, table) {
+        (None, None) => None,
+        (Some(columns), None) => Some(columns.field_options().clone()),
+        (columns, Some(table)) => Some(
+            columns
+                .map(|columns| columns.field_options().clone())
+                .unwrap_or_else(T::to_serialize_field_options)
+                .set_table(table.clone()),
+        ),
+    };
+    match (field_options, first) {
+        (Some(options), Some(first)) => {
+            worksheet.serialize_headers_with_options(0, 0, first, &options)?;
+        }
+        _ => {
+            worksheet.set_serialize_headers::<T>(0, 0)?;
+        }
     }
 
-    let mut headers = Vec::new();
+    // 2. Configure the exact height of the header row (Row 0), which the header widths assume.
+    worksheet.set_row_height(0, HEADER_ROW_HEIGHT)?;
+    worksheet.set_freeze_panes(1, 0)?;
 
-    // 4. Capture character lengths of fields to initialize header baseline widths.
-    if let Value::Object(ref map) = first_val {
-        for key in map.keys() {
-            headers.push(key.chars().count() as u16);
-        }
-    } else {
-        return Vec::new();
+    // 3. Set dynamically calculated column widths.
+    for (col_idx, &width) in col_widths.iter().enumerate() {
+        worksheet.set_column_width(col_idx as u16, width)?;
     }
 
-    let num_cols = headers.len();
-
-    // 5. Parallel search over split chunks.
-    // Rayon divides task sequences and processes values concurrently to build a local maximum width map.
-    let max_lengths = data
-        .par_chunks(1024)
-        .map(|chunk| {
-            let mut local_maxes = vec![0u16; num_cols];
-            for item in chunk {
-                if let Ok(Value::Object(map)) = serde_json::to_value(item) {
-                    for (i, value) in map.values().enumerate() {
-                        if i < num_cols {
-                            let len = get_value_length(value);
-                            if len > local_maxes[i] {
-                                local_maxes[i] = len;
-                            }
-                        }
-                    }
-                }
-            }
-            local_maxes
-        })
-        .reduce(
-            || vec![0u16; num_cols],
-            |mut acc, local| {
-                for i in 0..num_cols {
-                    acc[i] = acc[i].max(local[i]);
-                }
-                acc
-            },
+    // 4. Hide target empty or requested columns.
+    if !hide_cols.is_empty() {
+        debug!(
+            "Hiding {} columns in worksheet '{}'...",
+            hide_cols.len(),
+            sheet_name
         );
+        for &col_idx in hide_cols {
+            worksheet.set_column_hidden(col_idx)?;
+        }
+    }
 
-    // 6. Map elements to computed column widths, adding safety padding and bounding limits.
-    max_lengths
-        .into_iter()
-        .enumerate()
-        .map(|(i, max_val_len)| {
-            // Divide the raw header length by 4 to let titles wrap comfortably.
-            let header_len = headers[i] / 4;
-            let raw_max = header_len.max(max_val_len);
-
-            // Add safe padding to handle font metrics and clamp results within standard visual boundaries.
-            (raw_max + 2).clamp(8, 100)
-        })
-        .collect()
+    Ok(())
 }
 
-/// Approximates the string length of a serialized JSON field.
+/// Computes the auto-fit width of each column in parallel.
 ///
-/// For string types (including dates serialized in ISO 8601 "YYYY-MM-DD" format),
-/// it returns the direct character count. The 10-character length of "YYYY-MM-DD"
-/// matches the 10-character length of "DD/MM/YYYY" rendered in Excel.
-fn get_value_length(value: &Value) -> u16 {
-    match value {
-        Value::Null => 0,
-        Value::String(s) => s.chars().count() as u16,
-        Value::Number(n) => estimate_formatted_number_length(n),
-        Value::Bool(b) => {
-            if *b {
-                4
-            } else {
-                5
-            }
-        }
-        _ => 0,
-    }
+/// Every record is walked once by [`collect_column_stats`]: each value is rendered as
+/// Excel displays it (money as `#,##0.00`) and measured with the Calibri metrics of
+/// [`text_width`], in bold where `column_widths` says so. The final width also fits the
+/// wrapped header and honours the per-column bounds (see [`ColumnWidths::fit`]).
+pub fn calculate_max_column_widths<T>(data: &[T], column_widths: &ColumnWidths) -> Vec<f64>
+where
+    T: Serialize + Sync,
+{
+    collect_column_stats(data, column_widths, None).column_widths(column_widths)
 }
 
-/// Estimates the final rendered length of a number formatted in Excel
-/// with standard thousands separators and decimals (e.g., `#,##0.00`).
-fn estimate_formatted_number_length(n: &serde_json::Number) -> u16 {
-    if let Some(val_f64) = n.as_f64() {
-        let abs_val = val_f64.abs();
-        if !abs_val.is_finite() {
-            return 0;
-        }
-
-        // 1. Compute digits count of the integer portion mathematically using base-10 log.
-        let int_part = abs_val.trunc() as i64;
-        let digits = if int_part == 0 {
-            1
-        } else {
-            (int_part as f64).log10().floor() as u16 + 1
-        };
-
-        // 2. Count the number of financial thousands separators (periods).
-        let separators = (digits - 1) / 3;
-
-        // 3. Standard decimal suffix addition (e.g., ",00" represents 3 extra characters).
-        let decimal_and_separator = 3;
-
-        // 4. Check if a negative sign character (-) needs to be accounted for.
-        let sign = if val_f64 < 0.0 { 1 } else { 0 };
+/// Writes `headers` in the header format, from (`row`, `col`) to the right.
+pub(crate) fn write_headers(
+    worksheet: &mut Worksheet,
+    row: u32,
+    col: u16,
+    headers: &[&str],
+) -> Result<(), XlsxError> {
+    for (offset, header) in headers.iter().enumerate() {
+        worksheet.write_string_with_format(row, col + offset as u16, *header, &FORMAT.header)?;
+    }
+    Ok(())
+}
 
-        digits + separators + decimal_and_separator + sign
-    } else {
-        n.to_string().chars().count() as u16
+/// Traces the first record as pretty JSON, to check the serialized structure.
+fn print_first_row<T: Serialize>(data: &[T]) {
+    if let Some(first) = data.first()
+        && let Ok(pretty_json) = serde_json::to_string_pretty(first)
+    {
+        trace!(
+            "First data row representation (explicit structure check):\n{}",
+            pretty_json
+        );
     }
 }
@@ -0,0 +1,897 @@
+//! Output sinks for the sorted `PerDcomp` records.
+//!
+//! Every format implements [`Exporter`]. Non-Excel formats share a typed cell
+//! model ([`Cell`]), so that dates and money values keep the same types in
+//! CSV, JSON/NDJSON and Parquet as they have in the workbook.
+
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use log::info;
+use parquet::{
+    basic::{LogicalType, Repetition, Type as PhysicalType},
+    data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type,
+};
+use rust_xlsxwriter::{Table, TableColumn, TableFunction};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    ANNOTATION_HEADERS, ANOMALY_SHEET_NAME, Annotations, Anomaly, ColumnWidths, DEBITOS_SHEET_NAME,
+    Debito, EFD_SHEET_NAME, EfdComparison, FormatRules, MyResult, PerDcomp, Provenance,
+    SheetOptions, TableStats, WithPath, new_workbook, push_charts_worksheet,
+    push_metadata_worksheet, push_worksheets_with_options, save_workbook, structures::FORMAT,
+    table_style,
+};
+
+/// Name of the main worksheet; the records past its row limit go to
+/// "PERDComp 2", "PERDComp 3", ...
+pub const PERDCOMP_SHEET_NAME: &str = "PERDComp";
+
+/// Number of records per Parquet row group.
+const PARQUET_ROW_GROUP_SIZE: usize = 100_000;
+
+/// Output formats selectable with `--format`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Excel workbook with auxiliary worksheets.
+    Xlsx,
+    /// CSV with canonical headers.
+    Csv,
+    /// JSON array of objects.
+    Json,
+    /// Newline-delimited JSON, one object per line.
+    Ndjson,
+    /// Apache Parquet.
+    Parquet,
+}
+
+impl OutputFormat {
+    /// File extension of the generated file.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Xlsx => "xlsx",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Type of a column, shared by all non-Excel formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Text,
+    Money,
+    Integer,
+    Date,
+    Bool,
+}
+
+/// A typed cell value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Null,
+    Text(String),
+    Money(f64),
+    Integer(i64),
+    Date(NaiveDate),
+    Bool(bool),
+}
+
+impl From<Option<&str>> for Cell {
+    fn from(value: Option<&str>) -> Self {
+        value.map_or(Cell::Null, |s| Cell::Text(s.to_string()))
+    }
+}
+
+impl From<Option<NaiveDate>> for Cell {
+    fn from(value: Option<NaiveDate>) -> Self {
+        value.map_or(Cell::Null, Cell::Date)
+    }
+}
+
+impl From<Option<bool>> for Cell {
+    fn from(value: Option<bool>) -> Self {
+        value.map_or(Cell::Null, Cell::Bool)
+    }
+}
+
+impl From<Option<f64>> for Cell {
+    fn from(value: Option<f64>) -> Self {
+        value.map_or(Cell::Null, Cell::Money)
+    }
+}
+
+impl Cell {
+    /// Text representation used by CSV: ISO dates and `.` as decimal separator.
+    pub fn to_text(&self) -> String {
+        match self {
+            Cell::Null => String::new(),
+            Cell::Text(text) => text.clone(),
+            Cell::Money(value) => format!("{value:.2}"),
+            Cell::Integer(value) => value.to_string(),
+            Cell::Date(date) => date.format("%Y-%m-%d").to_string(),
+            Cell::Bool(value) => value.to_string(),
+        }
+    }
+
+    /// JSON value; money is rounded to cents, as in [`to_text`](Self::to_text).
+    fn to_json(&self) -> Value {
+        match self {
+            Cell::Null => Value::Null,
+            Cell::Text(text) => Value::String(text.clone()),
+            Cell::Money(value) => {
+                serde_json::Number::from_f64(round_cents(*value)).map_or(Value::Null, Value::Number)
+            }
+            Cell::Integer(value) => Value::from(*value),
+            Cell::Date(date) => Value::String(date.format("%Y-%m-%d").to_string()),
+            Cell::Bool(value) => Value::Bool(*value),
+        }
+    }
+}
+
+/// Rounds money to cents, so that every format holds the value shown by the CSV.
+fn round_cents(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// A column of a [`Tabular`] record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    /// Field name, used as SQL column name.
+    pub name: &'static str,
+    /// Canonical header, the same one written to the workbook.
+    pub header: &'static str,
+    pub kind: ColumnKind,
+}
+
+impl Column {
+    const fn new(name: &'static str, header: &'static str, kind: ColumnKind) -> Self {
+        Self { name, header, kind }
+    }
+}
+
+/// Records that can be flattened into typed cells.
+pub trait Tabular {
+    /// Columns in the same order as the workbook.
+    fn columns() -> Vec<Column>;
+
+    /// Cell values, one per column.
+    fn cells(&self) -> Vec<Cell>;
+
+    /// Worksheet table with a total row.
+    ///
+    /// The first column shows the number of visible records and money columns
+    /// their `SUBTOTAL(109, ...)` sum, so the totals follow the table filters.
+    /// Money columns take the plain currency format in this table.
+    fn totals_table() -> Table {
+        totals_table_of(&Self::columns())
+    }
+}
+
+/// Worksheet table of `columns` with a total row (see [`Tabular::totals_table`]).
+fn totals_table_of(columns: &[Column]) -> Table {
+    let columns: Vec<TableColumn> = columns
+        .iter()
+        .enumerate()
+        .map(|(index, column)| {
+            let table_column = TableColumn::new().set_header(column.header);
+            match column.kind {
+                _ if index == 0 => table_column
+                    .set_total_function(TableFunction::Count)
+                    .set_format(FORMAT.bold_centered.clone()),
+                ColumnKind::Money => table_column
+                    .set_total_function(TableFunction::Sum)
+                    .set_format(FORMAT.currency.clone()),
+                _ => table_column,
+            }
+        })
+        .collect();
+
+    Table::new().set_total_row(true).set_columns(&columns)
+}
+
+impl Tabular for PerDcomp {
+    fn columns() -> Vec<Column> {
+        use ColumnKind::*;
+        vec![
+            Column::new("per_dcomp", "PER/DCOMP", Text),
+            Column::new("cnpj_declarante", "CNPJ/CPF Declarante/Sucessora", Text),
+            Column::new("tipo_do_credito", "Tipo de Crédito", Text),
+            Column::new("codigo_do_credito", "Código do Tipo de Crédito", Text),
+            Column::new("valor_total_do_credito", "Valor Total do Crédito", Money),
+            Column::new(
+                "valor_do_credito_na_data_de_transmissao",
+                "Valor do Crédito na Data de Transmissão",
+                Money,
+            ),
+            Column::new(
+                "valor_do_per",
+                "Valor Total do Pedido de Resssarcimento (PER)",
+                Money,
+            ),
+            Column::new("total_debitos", "Total de Débitos Compensados", Money),
+            Column::new("data_da_transmissao", "Data da Transmissão", Date),
+            Column::new("demonstra_credito", "Demonstra Crédito", Bool),
+            Column::new("pendente_atuacao", "Pendente de Atuação", Bool),
+            Column::new("tipo_do_documento", "Tipo de Documento", Text),
+            Column::new("nome_empresarial", "Nome Empresarial/Nome", Text),
+            Column::new("ua_declarante", "UA Declarante/Sucessora", Text),
+            Column::new("cnpj_detentor_do_credito", "Detentor do Crédito", Text),
+            Column::new(
+                "trimestre_de_apuracao",
+                "Período de Apuração do Crédito",
+                Text,
+            ),
+            Column::new("ano", "Ano", Integer),
+            Column::new("pa_pagamento", "Período de Apuração do Pagamento", Date),
+            Column::new("data_dcomp_ativa", "Data 1ª DCOMP Ativa", Date),
+            Column::new(
+                "per_ativo_com_credito",
+                "PER/DCOMP Ativo com Demonstrativo de Crédito",
+                Bool,
+            ),
+            Column::new(
+                "num_processo_atribuido_ao_perdcomp",
+                "Processo Atribuído ao PER/DCOMP",
+                Text,
+            ),
+            Column::new(
+                "num_processo_administrativo_anterior",
+                "Processo Administrativo Anterior",
+                Text,
+            ),
+            Column::new("processo_judicial", "Processo Judicial", Text),
+            Column::new("origem_judicial", "Origem Discussão Judicial", Text),
+            Column::new("situacao", "Situação", Text),
+            Column::new("motivo", "Motivo", Text),
+            Column::new("origem", "Origem", Text),
+            Column::new("analise", "Análise", Text),
+            Column::new("responsavel", "Responsável", Text),
+            Column::new("status_interno", "Status interno", Text),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::from(self.per_dcomp.as_deref()),
+            Cell::from(self.cnpj_declarante.as_deref()),
+            Cell::from(self.tipo_do_credito.as_deref()),
+            Cell::from(self.codigo_do_credito.as_deref()),
+            Cell::Money(self.valor_total_do_credito),
+            Cell::Money(self.valor_do_credito_na_data_de_transmissao),
+            Cell::Money(self.valor_do_per),
+            Cell::from(self.total_debitos),
+            Cell::from(self.data_da_transmissao),
+            Cell::from(self.demonstra_credito),
+            Cell::from(self.pendente_atuacao),
+            Cell::from(self.tipo_do_documento.as_ref().map(|t| t.as_str())),
+            Cell::from(self.nome_empresarial.as_deref()),
+            Cell::from(self.ua_declarante.as_deref()),
+            Cell::from(self.cnpj_detentor_do_credito.as_deref()),
+            Cell::from(self.trimestre_de_apuracao.as_deref()),
+            self.ano.map_or(Cell::Null, |ano| Cell::Integer(ano.into())),
+            Cell::from(self.pa_pagamento),
+            Cell::from(self.data_dcomp_ativa),
+            Cell::from(self.per_ativo_com_credito),
+            Cell::from(self.num_processo_atribuido_ao_perdcomp.as_deref()),
+            Cell::from(self.num_processo_administrativo_anterior.as_deref()),
+            Cell::from(self.processo_judicial.as_deref()),
+            Cell::from(self.origem_judicial.as_deref()),
+            Cell::from(self.situacao.as_ref().map(|s| s.as_str())),
+            Cell::from(self.motivo.as_deref()),
+            Cell::from(self.origem.as_ref().map(|o| o.to_string()).as_deref()),
+            Cell::from(self.analise.as_deref()),
+            Cell::from(self.responsavel.as_deref()),
+            Cell::from(self.status_interno.as_deref()),
+        ]
+    }
+}
+
+impl PerDcomp {
+    /// Whether `column` is written: the "Origem" column only with `--origin`.
+    fn is_exported(column: &Column, origin: bool) -> bool {
+        origin || column.name != "origem"
+    }
+
+    /// The columns written to the output files (see [`is_exported`](Self::is_exported)).
+    pub fn exported_columns(origin: bool) -> Vec<Column> {
+        Self::columns()
+            .into_iter()
+            .filter(|column| Self::is_exported(column, origin))
+            .collect()
+    }
+
+    /// Cell values, one per column of [`exported_columns`](Self::exported_columns).
+    pub fn exported_cells(&self, origin: bool) -> Vec<Cell> {
+        Self::columns()
+            .iter()
+            .zip(self.cells())
+            .filter(|(column, _)| Self::is_exported(column, origin))
+            .map(|(_, cell)| cell)
+            .collect()
+    }
+
+    /// Whether `column` holds document data, rather than the record origin
+    /// or the auditor annotations.
+    pub fn is_document_column(column: &Column) -> bool {
+        column.name != "origem" && !ANNOTATION_HEADERS.contains(&column.header)
+    }
+
+    /// Cell values of the document columns (see [`is_document_column`](Self::is_document_column)).
+    pub fn document_cells(&self) -> Vec<Cell> {
+        Self::columns()
+            .iter()
+            .zip(self.cells())
+            .filter(|(column, _)| Self::is_document_column(column))
+            .map(|(_, cell)| cell)
+            .collect()
+    }
+}
+
+/// A sink for the sorted records.
+pub trait Exporter {
+    /// Writes `perdcomps` to `output`.
+    fn export(&self, perdcomps: &[PerDcomp], output: &Path) -> MyResult<()>;
+}
+
+/// Excel workbook with the main worksheet and the auxiliary worksheets.
+#[derive(Debug, Default)]
+pub struct XlsxExporter<'a> {
+    pub hide_cols: &'a [u16],
+    pub debitos: &'a [Debito],
+    pub anomalies: &'a [Anomaly],
+    pub efd_comparison: &'a [EfdComparison],
+    /// Add the "Gráficos" worksheet.
+    pub charts: bool,
+    /// Per-column width overrides for every worksheet.
+    pub column_widths: Option<&'a ColumnWidths>,
+    /// Column statistics of `perdcomps`, when already collected.
+    pub stats: Option<&'a TableStats>,
+    /// Conditional formats for the PERDComp worksheet.
+    pub format_rules: Option<&'a FormatRules>,
+    /// Dropdowns of the annotation columns of the PERDComp worksheet.
+    pub annotations: Option<&'a Annotations>,
+    /// Add a total row to the PERDComp table.
+    pub totals: bool,
+    /// Name of the PERDComp table style (see [`table_style`]); Excel's default when `None`.
+    pub theme: Option<&'a str>,
+    /// Write the "Origem" column.
+    pub origin: bool,
+    /// Add the "Metadados" worksheet and the document properties.
+    pub provenance: Option<&'a Provenance>,
+}
+
+/// The table of the PERDComp worksheet, when it differs from the one of the type.
+pub(crate) fn perdcomp_table(totals: bool, theme: Option<&str>, origin: bool) -> Option<Table> {
+    let table = totals.then(|| totals_table_of(&PerDcomp::exported_columns(origin)));
+    match theme.and_then(table_style) {
+        Some(style) => Some(table.unwrap_or_else(Table::new).set_style(style)),
+        None => table,
+    }
+}
+
+impl Exporter for XlsxExporter<'_> {
+    fn export(&self, perdcomps: &[PerDcomp], output: &Path) -> MyResult<()> {
+        let mut workbook = new_workbook()?;
+        let table = perdcomp_table(self.totals, self.theme, self.origin);
+        let columns = PerDcomp::sheet_columns(self.origin);
+        let options = SheetOptions {
+            table: table.as_ref(),
+            format_rules: self.format_rules,
+            column_widths: self.column_widths,
+            stats: self.stats,
+            annotations: self.annotations,
+            columns: Some(&columns),
+        };
+        let widths_only = |columns| SheetOptions {
+            column_widths: self.column_widths,
+            columns: Some(columns),
+            ..Default::default()
+        };
+        let (debito_columns, anomaly_columns, efd_columns) = (
+            Debito::sheet_columns(),
+            Anomaly::sheet_columns(),
+            EfdComparison::sheet_columns(),
+        );
+        push_worksheets_with_options(
+            &mut workbook,
+            perdcomps,
+            PERDCOMP_SHEET_NAME,
+            self.hide_cols,
+            &options,
+        )?;
+        push_worksheets_with_options(
+            &mut workbook,
+            self.debitos,
+            DEBITOS_SHEET_NAME,
+            &[],
+            &widths_only(&debito_columns),
+        )?;
+        push_worksheets_with_options(
+            &mut workbook,
+            self.anomalies,
+            ANOMALY_SHEET_NAME,
+            &[],
+            &widths_only(&anomaly_columns),
+        )?;
+        push_worksheets_with_options(
+            &mut workbook,
+            self.efd_comparison,
+            EFD_SHEET_NAME,
+            &[],
+            &widths_only(&efd_columns),
+        )?;
+        if self.charts {
+            push_charts_worksheet(&mut workbook, perdcomps)?;
+        }
+        if let Some(provenance) = self.provenance
+            && !workbook.worksheets().is_empty()
+        {
+            push_metadata_worksheet(&mut workbook, provenance)?;
+        }
+        save_workbook(&mut workbook, output)?;
+        Ok(())
+    }
+}
+
+/// CSV with canonical headers, ISO dates and `.` as decimal separator.
+#[derive(Debug)]
+pub struct CsvExporter {
+    pub delimiter: u8,
+    /// Write the "Origem" column.
+    pub origin: bool,
+}
+
+impl Exporter for CsvExporter {
+    fn export(&self, perdcomps: &[PerDcomp], output: &Path) -> MyResult<()> {
+        info!("Write CSV file: {output:?}");
+
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_path(output)
+            .with_path(output)?;
+
+        let columns = PerDcomp::exported_columns(self.origin);
+        writer.write_record(columns.iter().map(|column| column.header))?;
+
+        for perdcomp in perdcomps {
+            writer.write_record(
+                perdcomp
+                    .exported_cells(self.origin)
+                    .iter()
+                    .map(Cell::to_text),
+            )?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// JSON array, or NDJSON when `ndjson` is set.
+#[derive(Debug)]
+pub struct JsonExporter {
+    pub ndjson: bool,
+    /// Write the "Origem" column.
+    pub origin: bool,
+}
+
+impl Exporter for JsonExporter {
+    fn export(&self, perdcomps: &[PerDcomp], output: &Path) -> MyResult<()> {
+        info!("Write JSON file: {output:?}");
+
+        let columns = PerDcomp::exported_columns(self.origin);
+        let mut writer = BufWriter::new(File::create(output).with_path(output)?);
+
+        let to_object = |perdcomp: &PerDcomp| -> Value {
+            let map: Map<String, Value> = columns
+                .iter()
+                .zip(perdcomp.exported_cells(self.origin))
+                .map(|(column, cell)| (column.header.to_string(), cell.to_json()))
+                .collect();
+            Value::Object(map)
+        };
+
+        if self.ndjson {
+            for perdcomp in perdcomps {
+                serde_json::to_writer(&mut writer, &to_object(perdcomp))?;
+                writeln!(writer)?;
+            }
+        } else {
+            let array: Vec<Value> = perdcomps.iter().map(to_object).collect();
+            serde_json::to_writer_pretty(&mut writer, &array)?;
+            writeln!(writer)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Apache Parquet with optional columns: dates as `DATE`, money as `DOUBLE`.
+#[derive(Debug)]
+pub struct ParquetExporter {
+    /// Write the "Origem" column.
+    pub origin: bool,
+}
+
+impl Exporter for ParquetExporter {
+    fn export(&self, perdcomps: &[PerDcomp], output: &Path) -> MyResult<()> {
+        info!("Write Parquet file: {output:?}");
+
+        let columns = PerDcomp::exported_columns(self.origin);
+        let schema = parquet_schema(&columns)?;
+        let properties = Arc::new(WriterProperties::builder().build());
+        let mut writer =
+            SerializedFileWriter::new(File::create(output).with_path(output)?, schema, properties)?;
+
+        for chunk in perdcomps.chunks(PARQUET_ROW_GROUP_SIZE) {
+            let rows: Vec<Vec<Cell>> = chunk
+                .iter()
+                .map(|perdcomp| perdcomp.exported_cells(self.origin))
+                .collect();
+            let mut row_group = writer.next_row_group()?;
+            let mut index = 0;
+
+            while let Some(mut column) = row_group.next_column()? {
+                let cells: Vec<&Cell> = rows.iter().map(|row| &row[index]).collect();
+                let def_levels: Vec<i16> = cells
+                    .iter()
+                    .map(|cell| i16::from(**cell != Cell::Null))
+                    .collect();
+
+                match columns[index].kind {
+                    ColumnKind::Text => {
+                        let values: Vec<ByteArray> = cells
+                            .iter()
+                            .filter_map(|cell| match cell {
+                                Cell::Text(text) => Some(ByteArray::from(text.as_str())),
+                                _ => None,
+                            })
+                            .collect();
+                        column.typed::<ByteArrayType>().write_batch(
+                            &values,
+                            Some(&def_levels),
+                            None,
+                        )?;
+                    }
+                    ColumnKind::Money => {
+                        let values: Vec<f64> = cells
+                            .iter()
+                            .filter_map(|cell| match cell {
+                                Cell::Money(value) => Some(round_cents(*value)),
+                                _ => None,
+                            })
+                            .collect();
+                        column.typed::<DoubleType>().write_batch(
+                            &values,
+                            Some(&def_levels),
+                            None,
+                        )?;
+                    }
+                    ColumnKind::Integer => {
+                        let values: Vec<i64> = cells
+                            .iter()
+                            .filter_map(|cell| match cell {
+                                Cell::Integer(value) => Some(*value),
+                                _ => None,
+                            })
+                            .collect();
+                        column.typed::<Int64Type>().write_batch(
+                            &values,
+                            Some(&def_levels),
+                            None,
+                        )?;
+                    }
+                    ColumnKind::Date => {
+                        let values: Vec<i32> = cells
+                            .iter()
+                            .filter_map(|cell| match cell {
+                                Cell::Date(date) => Some(days_since_epoch(*date)),
+                                _ => None,
+                            })
+                            .collect();
+                        column.typed::<Int32Type>().write_batch(
+                            &values,
+                            Some(&def_levels),
+                            None,
+                        )?;
+                    }
+                    ColumnKind::Bool => {
+                        let values: Vec<bool> = cells
+                            .iter()
+                            .filter_map(|cell| match cell {
+                                Cell::Bool(value) => Some(*value),
+                                _ => None,
+                            })
+                            .collect();
+                        column
+                            .typed::<BoolType>()
+                            .write_batch(&values, Some(&def_levels), None)?;
+                    }
+                }
+
+                column.close()?;
+                index += 1;
+            }
+
+            row_group.close()?;
+        }
+
+        writer.close()?;
+        Ok(())
+    }
+}
+
+/// Builds a flat Parquet schema with one optional column per header.
+fn parquet_schema(columns: &[Column]) -> MyResult<Arc<Type>> {
+    let fields = columns
+        .iter()
+        .map(|column| {
+            let (physical_type, logical_type) = match column.kind {
+                ColumnKind::Text => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+                ColumnKind::Money => (PhysicalType::DOUBLE, None),
+                ColumnKind::Integer => (PhysicalType::INT64, None),
+                ColumnKind::Date => (PhysicalType::INT32, Some(LogicalType::Date)),
+                ColumnKind::Bool => (PhysicalType::BOOLEAN, None),
+            };
+
+            Type::primitive_type_builder(column.header, physical_type)
+                .with_repetition(Repetition::OPTIONAL)
+                .with_logical_type(logical_type)
+                .build()
+                .map(Arc::new)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let schema = Type::group_type_builder("perdcomp")
+        .with_fields(fields)
+        .build()?;
+
+    Ok(Arc::new(schema))
+}
+
+fn days_since_epoch(date: NaiveDate) -> i32 {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+    (date - epoch).num_days() as i32
+}
+
+#[cfg(test)]
+mod tests_export {
+    use super::*;
+
+    #[test]
+    fn columns_match_serialized_headers() {
+        let value = serde_json::to_value(PerDcomp::default()).unwrap();
+        let headers: Vec<&str> = match &value {
+            Value::Object(map) => map.keys().map(String::as_str).collect(),
+            _ => Vec::new(),
+        };
+
+        let columns: Vec<&str> = PerDcomp::columns().iter().map(|c| c.header).collect();
+
+        assert_eq!(columns, headers);
+        assert_eq!(PerDcomp::default().cells().len(), columns.len());
+        assert_eq!(PerDcomp::sheet_columns(true).headers(), headers);
+    }
+
+    #[test]
+    fn sheet_columns_carry_the_value_formats() {
+        let headers = |value: Value| -> Vec<String> {
+            match value {
+                Value::Object(map) => map.keys().cloned().collect(),
+                _ => Vec::new(),
+            }
+        };
+        let anomaly = Anomaly {
+            rule_id: "R01".to_string(),
+            severity: crate::Severity::High,
+            description: String::new(),
+            per_dcomp: None,
+            rows: String::new(),
+        };
+        let comparison = EfdComparison {
+            cnpj_base: String::new(),
+            ano: 2021,
+            trimestre: 1,
+            codigo_do_credito: String::new(),
+            credito_efd: 0.0,
+            valor_pedido: 0.0,
+            diferenca: 0.0,
+            documentos: 0,
+            situacao: crate::EfdStatus::Conforme,
+        };
+        let (debitos, anomalies, efd) = (
+            Debito::sheet_columns(),
+            Anomaly::sheet_columns(),
+            EfdComparison::sheet_columns(),
+        );
+
+        assert_eq!(
+            debitos.headers(),
+            headers(serde_json::json!(Debito::default()))
+        );
+        assert_eq!(anomalies.headers(), headers(serde_json::json!(anomaly)));
+        assert_eq!(efd.headers(), headers(serde_json::json!(comparison)));
+
+        assert!(PerDcomp::sheet_columns(false).is_bold("PER/DCOMP"));
+        assert!(PerDcomp::sheet_columns(false).is_date("Data da Transmissão"));
+        assert!(debitos.is_bold("Valor Total") && !debitos.is_bold("Multa"));
+        assert!(debitos.is_date("Data de Vencimento"));
+        assert!(anomalies.is_bold("Regra"));
+        assert!(efd.is_bold("CNPJ Base") && efd.is_bold("Diferença (EFD - PER/DCOMP)"));
+        assert!(!efd.is_date("Ano"));
+    }
+
+    #[test]
+    fn origem_is_written_only_with_origin() -> MyResult<()> {
+        use calamine::{Reader, open_workbook_auto};
+
+        let perdcomps = vec![PerDcomp {
+            per_dcomp: Some("1".to_string()),
+            origem: Some(crate::Origem {
+                file: "scc.csv".into(),
+                line: 2,
+            }),
+            ..Default::default()
+        }];
+        let dir = tempfile::tempdir()?;
+
+        for origin in [false, true] {
+            let csv = dir.path().join("perdcomp.csv");
+            CsvExporter {
+                delimiter: b';',
+                origin,
+            }
+            .export(&perdcomps, &csv)?;
+            let text = std::fs::read_to_string(&csv)?;
+            let header = text.lines().next().unwrap_or_default();
+            assert_eq!(header.split(';').any(|header| header == "Origem"), origin);
+
+            let json = dir.path().join("perdcomp.ndjson");
+            JsonExporter {
+                ndjson: true,
+                origin,
+            }
+            .export(&perdcomps, &json)?;
+            assert_eq!(std::fs::read_to_string(&json)?.contains("scc.csv"), origin);
+
+            let xlsx = dir.path().join("perdcomp.xlsx");
+            XlsxExporter {
+                totals: true,
+                origin,
+                ..Default::default()
+            }
+            .export(&perdcomps, &xlsx)?;
+            let mut workbook = open_workbook_auto(&xlsx)?;
+            let range = workbook.worksheet_range("PERDComp")?;
+            let headers: Vec<String> = range
+                .rows()
+                .next()
+                .unwrap()
+                .iter()
+                .map(|cell| cell.to_string())
+                .collect();
+
+            assert_eq!(headers, PerDcomp::sheet_columns(origin).headers());
+            assert_eq!(headers.contains(&"Origem".to_string()), origin);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn totals_table_sums_money_columns() -> MyResult<()> {
+        use calamine::{Reader, open_workbook_auto};
+
+        let table = PerDcomp::totals_table();
+        assert!(table.has_total_row());
+
+        let perdcomps = vec![
+            PerDcomp {
+                per_dcomp: Some("1".to_string()),
+                valor_do_per: 10.0,
+                ..Default::default()
+            },
+            PerDcomp {
+                per_dcomp: Some("2".to_string()),
+                valor_do_per: 5.5,
+                ..Default::default()
+            },
+        ];
+
+        let mut workbook = new_workbook()?;
+        let options = SheetOptions {
+            table: Some(&table),
+            format_rules: Some(&FormatRules::default()),
+            ..Default::default()
+        };
+        push_worksheets_with_options(&mut workbook, &perdcomps, "PERDComp", &[], &options)?;
+        let output = tempfile::Builder::new().suffix(".xlsx").tempfile()?;
+        workbook.save(output.path())?;
+
+        // The total row follows the header and the two records
+        let mut workbook = open_workbook_auto(output.path())?;
+        let formulas = workbook.worksheet_formula("PERDComp")?;
+        let total_row = perdcomps.len() as u32 + 1;
+
+        for (index, column) in PerDcomp::columns().iter().enumerate() {
+            let formula = formulas
+                .get_value((total_row, index as u32))
+                .cloned()
+                .unwrap_or_default();
+            let expected = match column.kind {
+                _ if index == 0 => format!("SUBTOTAL(103,[{}])", column.header),
+                ColumnKind::Money => format!("SUBTOTAL(109,[{}])", column.header),
+                _ => String::new(),
+            };
+            assert_eq!(formula, expected, "{}", column.header);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn cells_keep_types() {
+        let perdcomp = PerDcomp {
+            valor_total_do_credito: 0.1 + 0.2,
+            valor_do_per: 1234.5,
+            data_da_transmissao: NaiveDate::from_ymd_opt(2024, 1, 20),
+            pendente_atuacao: Some(true),
+            ano: Some(2024),
+            ..Default::default()
+        };
+
+        let cells = perdcomp.cells();
+
+        assert_eq!(cells[4].to_text(), "0.30");
+        assert_eq!(cells[4].to_json(), Value::from(0.3));
+        assert_eq!(cells[6].to_text(), "1234.50");
+        assert_eq!(cells[8].to_text(), "2024-01-20");
+        assert_eq!(cells[8].to_json(), Value::String("2024-01-20".to_string()));
+        assert_eq!(cells[10].to_json(), Value::Bool(true));
+        assert_eq!(cells[16].to_json(), Value::from(2024));
+        assert_eq!(
+            days_since_epoch(NaiveDate::from_ymd_opt(1970, 1, 2).unwrap()),
+            1
+        );
+    }
+
+    #[test]
+    fn parquet_round_trip() -> MyResult<()> {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let perdcomps = vec![
+            PerDcomp {
+                per_dcomp: Some("111".to_string()),
+                valor_do_per: 10.5,
+                ..Default::default()
+            },
+            PerDcomp::default(),
+        ];
+
+        let output = tempfile::NamedTempFile::new()?;
+        ParquetExporter { origin: true }.export(&perdcomps, output.path())?;
+
+        let reader = SerializedFileReader::new(File::open(output.path())?)?;
+        let metadata = reader.metadata().file_metadata();
+
+        assert_eq!(metadata.num_rows(), 2);
+        assert_eq!(
+            metadata.schema_descr().num_columns(),
+            PerDcomp::columns().len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+//! Conditional formatting rules applied to the generated worksheets.
+//!
+//! Each rule targets a column by its header, compares the cell value with an
+//! operator and paints matching cells with a format from [`FormatRegistry`].
+//! The embedded rules highlight the situação, pending documents and the PER
+//! amounts; a JSON file with the same layout replaces them.
+//!
+//! [`FormatRegistry`]: crate::structures::FormatRegistry
+
+use rust_xlsxwriter::{
+    ConditionalFormatCell, ConditionalFormatCellRule, ConditionalFormatDataBar,
+    ConditionalFormatText, ConditionalFormatTextRule, ConditionalFormatValue, Format, Worksheet,
+    XlsxError,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fs, path::Path};
+
+use crate::{MyResult, PerDcompError, WithPath, normalize_text, structures::FORMAT};
+
+/// Rules embedded in the binary, used when no file is given.
+const EMBEDDED_RULES: &str = include_str!("data/format_rules.json");
+
+/// Comparison applied to the cells of the target column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleOperator {
+    EqualTo,
+    NotEqualTo,
+    GreaterThan,
+    GreaterThanOrEqualTo,
+    LessThan,
+    LessThanOrEqualTo,
+    /// Text containing `value`.
+    Contains,
+    /// Data bars proportional to the cell values; `value` and `format` are ignored.
+    DataBar,
+}
+
+/// A conditional format for one column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormatRule {
+    /// Column header, compared with [`normalize_text`] ("situacao" matches "Situação").
+    pub column: String,
+    pub operator: RuleOperator,
+    /// Text, number or boolean compared with the cells.
+    #[serde(default)]
+    pub value: Option<Value>,
+    /// Name of a [`FormatRegistry`](crate::structures::FormatRegistry) format, such as `alert`.
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// Ordered list of conditional formatting rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatRules {
+    pub rules: Vec<FormatRule>,
+}
+
+impl FormatRules {
+    /// Loads the rules from a JSON file, or the embedded ones if `path` is `None`.
+    ///
+    /// The file has the same layout as `src/data/format_rules.json`.
+    /// Every rule is checked here, so that a bad file is reported on load.
+    pub fn load(path: Option<&Path>) -> MyResult<Self> {
+        let rules: Vec<FormatRule> = match path {
+            Some(path) => {
+                let json = fs::read_to_string(path).with_path(path)?;
+                serde_json::from_str(&json).with_path(path)?
+            }
+            None => serde_json::from_str(EMBEDDED_RULES)?,
+        };
+
+        for rule in &rules {
+            rule.validate().map_err(|error| match path {
+                Some(path) => error.with_path(path),
+                None => error,
+            })?;
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// Adds the rules whose column is among `headers` to rows `1..=last_row`.
+    ///
+    /// Rules for columns not present in the worksheet are ignored.
+    pub fn apply(
+        &self,
+        worksheet: &mut Worksheet,
+        headers: &[String],
+        last_row: u32,
+    ) -> Result<(), XlsxError> {
+        if last_row == 0 {
+            return Ok(());
+        }
+
+        for rule in &self.rules {
+            let target = normalize_text(&rule.column);
+            let Some(col) = headers
+                .iter()
+                .position(|header| normalize_text(header) == target)
+            else {
+                continue;
+            };
+            rule.add_to(worksheet, col as u16, last_row)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for FormatRules {
+    fn default() -> Self {
+        Self::load(None).expect("embedded format rules must be valid")
+    }
+}
+
+impl FormatRule {
+    fn validate(&self) -> MyResult<()> {
+        if self.operator == RuleOperator::DataBar {
+            return Ok(());
+        }
+
+        if self.value.is_none() {
+            return Err(self.error("sem valor".to_string()));
+        }
+
+        match self.format.as_deref() {
+            Some(name) if FORMAT.get(name).is_some() => Ok(()),
+            Some(name) => Err(self.error(format!("formato desconhecido {name:?}"))),
+            None => Err(self.error("sem formato".to_string())),
+        }
+    }
+
+    fn error(&self, message: String) -> PerDcompError {
+        PerDcompError::Config {
+            path: None,
+            message: format!("regra de formatação da coluna {:?}: {message}", self.column),
+        }
+    }
+
+    fn add_to(&self, worksheet: &mut Worksheet, col: u16, last_row: u32) -> Result<(), XlsxError> {
+        if self.operator == RuleOperator::DataBar {
+            let data_bar = ConditionalFormatDataBar::new();
+            worksheet.add_conditional_format(1, col, last_row, col, &data_bar)?;
+            return Ok(());
+        }
+
+        let format: Format = self
+            .format
+            .as_deref()
+            .and_then(|name| FORMAT.get(name))
+            .cloned()
+            .unwrap_or_default();
+
+        if self.operator == RuleOperator::Contains {
+            let text = match &self.value {
+                Some(Value::String(text)) => text.clone(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            };
+            let conditional = ConditionalFormatText::new()
+                .set_rule(ConditionalFormatTextRule::Contains(text))
+                .set_format(format);
+            worksheet.add_conditional_format(1, col, last_row, col, &conditional)?;
+            return Ok(());
+        }
+
+        let value = condition_value(self.value.as_ref());
+        let rule = match self.operator {
+            RuleOperator::NotEqualTo => ConditionalFormatCellRule::NotEqualTo(value),
+            RuleOperator::GreaterThan => ConditionalFormatCellRule::GreaterThan(value),
+            RuleOperator::GreaterThanOrEqualTo => {
+                ConditionalFormatCellRule::GreaterThanOrEqualTo(value)
+            }
+            RuleOperator::LessThan => ConditionalFormatCellRule::LessThan(value),
+            RuleOperator::LessThanOrEqualTo => ConditionalFormatCellRule::LessThanOrEqualTo(value),
+            _ => ConditionalFormatCellRule::EqualTo(value),
+        };
+        let conditional = ConditionalFormatCell::new()
+            .set_rule(rule)
+            .set_format(format);
+        worksheet.add_conditional_format(1, col, last_row, col, &conditional)?;
+
+        Ok(())
+    }
+}
+
+/// Strings are quoted by rust_xlsxwriter; numbers and booleans are not.
+fn condition_value(value: Option<&Value>) -> ConditionalFormatValue {
+    match value {
+        Some(Value::String(text)) => text.as_str().into(),
+        Some(Value::Bool(flag)) => (*flag).into(),
+        Some(Value::Number(number)) => number.as_f64().unwrap_or_default().into(),
+        _ => "".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests_format_rules {
+    use super::*;
+
+    #[test]
+    fn embedded_rules_are_valid() {
+        let rules = FormatRules::default();
+        assert!(
+            rules
+                .rules
+                .iter()
+                .any(|rule| rule.operator == RuleOperator::DataBar)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_format() {
+        let rule = FormatRule {
+            column: "Situação".to_string(),
+            operator: RuleOperator::EqualTo,
+            value: Some(Value::from("Deferido")),
+            format: Some("purple".to_string()),
+        };
+        let error = rule.validate().unwrap_err();
+        assert_eq!(error.exit_code(), 8);
+        assert!(
+            error
+                .to_string()
+                .contains("formato desconhecido \"purple\"")
+        );
+    }
+}
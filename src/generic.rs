@@ -0,0 +1,192 @@
+//! Schema-less conversion of arbitrary CSV files (`--generic`).
+//!
+//! Columns keep the file order and their original headers. The type of each
+//! column is inferred from all of its non-empty values, so a column is only
+//! written as numbers or dates when every value parses; anything else stays
+//! text, exactly as in the CSV.
+
+use csv::StringRecord;
+use log::debug;
+use rayon::prelude::*;
+
+use crate::{
+    Arguments, Cell, ColumnKind, MyResult, Paths, REGEX_INTEIRO, REGEX_NUMERO_PT_BR, WithPath,
+    csv_reader, normalize_text, parse_date,
+};
+
+/// Name of the worksheet written in generic mode.
+pub const GENERIC_SHEET_NAME: &str = "Dados";
+
+/// Significant digits of an Excel number; longer numbers would be rounded.
+const MAX_NUMBER_DIGITS: usize = 15;
+
+/// A CSV file read as typed cells, without a schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenericTable {
+    pub headers: Vec<String>,
+    /// Inferred type of each column.
+    pub kinds: Vec<ColumnKind>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+impl GenericTable {
+    /// Reads the converted CSV file (`paths.output`) and infers the column types.
+    pub fn read(args: &Arguments, paths: &Paths) -> MyResult<Self> {
+        let mut reader = csv_reader(args, &paths.output)?;
+        let headers: Vec<String> = reader.headers()?.iter().map(String::from).collect();
+        let records: Vec<StringRecord> = reader
+            .records()
+            .collect::<Result<_, csv::Error>>()
+            .with_path(&paths.input)?;
+
+        let kinds: Vec<ColumnKind> = (0..headers.len())
+            .into_par_iter()
+            .map(|col| {
+                let values: Vec<&str> = records.iter().filter_map(|r| r.get(col)).collect();
+                infer_kind(&values)
+            })
+            .collect();
+
+        let rows: Vec<Vec<Cell>> = records
+            .par_iter()
+            .map(|record| {
+                record
+                    .iter()
+                    .zip(&kinds)
+                    .map(|(text, &kind)| parse_cell(text, kind))
+                    .collect()
+            })
+            .collect();
+
+        debug!("Inferred column types of {:?}:", paths.input);
+        for (header, kind) in headers.iter().zip(&kinds) {
+            debug!("{header:>40}: {kind:?}");
+        }
+
+        Ok(Self {
+            headers,
+            kinds,
+            rows,
+        })
+    }
+
+    /// Indices of the columns without any value.
+    pub fn empty_column_indices(&self) -> Vec<u16> {
+        (0..self.headers.len())
+            .filter(|&col| {
+                self.rows
+                    .iter()
+                    .all(|row| row.get(col).is_none_or(|cell| *cell == Cell::Null))
+            })
+            .map(|col| col as u16)
+            .collect()
+    }
+}
+
+/**
+Infers the type of a column from its values.
+
+The first type that fits every non-empty value wins: integers without
+leading zeros (so codes such as `00123` stay text), pt-BR numbers, dates,
+then "Sim"/"Não". Columns without values are text, and so are columns with
+a value of more than 15 digits, such as PER/DCOMP numbers and access keys,
+which Excel would round.
+
+```
+use perdcomp_csv_to_xlsx::{ColumnKind, infer_kind};
+
+assert_eq!(infer_kind(&["12", "", "-3"]), ColumnKind::Integer);
+assert_eq!(infer_kind(&["1.234,56", "7"]), ColumnKind::Money);
+assert_eq!(infer_kind(&["17/02/2014", "2023-04-20"]), ColumnKind::Date);
+assert_eq!(infer_kind(&["Sim", "NÃO"]), ColumnKind::Bool);
+assert_eq!(infer_kind(&["00123", "45"]), ColumnKind::Text);
+assert_eq!(infer_kind(&["1234567890123456", "45"]), ColumnKind::Text);
+assert_eq!(infer_kind(&["123456789012345", "45"]), ColumnKind::Integer);
+```
+*/
+pub fn infer_kind(values: &[&str]) -> ColumnKind {
+    let values: Vec<&str> = values
+        .iter()
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+        .collect();
+
+    let all = |fits: fn(&str) -> bool| !values.is_empty() && values.iter().all(|v| fits(v));
+    let too_long = |v: &&str| v.chars().filter(char::is_ascii_digit).count() > MAX_NUMBER_DIGITS;
+
+    if values.iter().any(too_long) {
+        ColumnKind::Text
+    } else if all(|v| REGEX_INTEIRO.is_match(v)) {
+        ColumnKind::Integer
+    } else if all(|v| REGEX_NUMERO_PT_BR.is_match(v)) {
+        ColumnKind::Money
+    } else if all(|v| parse_date(v).is_some()) {
+        ColumnKind::Date
+    } else if all(|v| matches!(normalize_text(v).as_str(), "sim" | "nao")) {
+        ColumnKind::Bool
+    } else {
+        ColumnKind::Text
+    }
+}
+
+/// Parses a value as the inferred type of its column. Empty values become [`Cell::Null`].
+fn parse_cell(text: &str, kind: ColumnKind) -> Cell {
+    let text = text.trim();
+    if text.is_empty() {
+        return Cell::Null;
+    }
+
+    let cell = match kind {
+        ColumnKind::Integer => text.parse().ok().map(Cell::Integer),
+        ColumnKind::Money => text
+            .replace('.', "")
+            .replace(',', ".")
+            .parse()
+            .ok()
+            .map(Cell::Money),
+        ColumnKind::Date => parse_date(text).map(Cell::Date),
+        ColumnKind::Bool => Some(Cell::Bool(normalize_text(text) == "sim")),
+        ColumnKind::Text => None,
+    };
+
+    cell.unwrap_or_else(|| Cell::Text(text.to_string()))
+}
+
+#[cfg(test)]
+mod tests_generic {
+    use super::*;
+    use chrono::NaiveDate;
+    use clap::Parser;
+    use std::{io::Write, path::Path};
+
+    #[test]
+    fn reads_any_csv_with_typed_columns() -> MyResult<()> {
+        let mut input = tempfile::NamedTempFile::new()?;
+        writeln!(input, "Código;Valor;Data;Ativo;Vazio;Nome")?;
+        writeln!(input, "00123;1.234,50;17/02/2014;Sim;;Ana")?;
+        writeln!(input, "45;7;2023-04-20;Não;;Bia")?;
+
+        let args = Arguments::try_parse_from(["test", "--generic", "-d", ";", "-p", "input.csv"])?;
+        let paths = Paths {
+            input: Path::new("input.csv").into(),
+            output: input.path().into(),
+        };
+        let table = GenericTable::read(&args, &paths)?;
+
+        use ColumnKind::*;
+        assert_eq!(table.kinds, [Text, Money, Date, Bool, Text, Text]);
+        assert_eq!(
+            table.rows[0],
+            [
+                Cell::Text("00123".to_string()),
+                Cell::Money(1234.5),
+                Cell::Date(NaiveDate::from_ymd_opt(2014, 2, 17).unwrap()),
+                Cell::Bool(true),
+                Cell::Null,
+                Cell::Text("Ana".to_string()),
+            ]
+        );
+        assert_eq!(table.empty_column_indices(), [4]);
+        Ok(())
+    }
+}
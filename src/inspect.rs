@@ -0,0 +1,180 @@
+//! Reports of the `inspect-headers` and `summary` subcommands.
+//!
+//! Both are printed as the tables of the `query` subcommand (see
+//! [`QueryResult::print_table`]), so that they can be read in a terminal or
+//! pasted into a ticket.
+
+use csv::StringRecord;
+
+use crate::{
+    Arguments, Cell, Column, ColumnKind, ConversionReport, MyResult, Paths, PerDcomp, QueryResult,
+    Tabular, WithPath, csv_reader, documents_by_situacao, find_column, map_headers, quarter_totals,
+};
+
+/// How the headers of one input file are read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderReport {
+    /// Each header of the file and the field it fills, if any.
+    pub headers: Vec<(String, Option<&'static str>)>,
+    /// Fields without a header in the file.
+    pub missing: Vec<&'static str>,
+}
+
+impl HeaderReport {
+    /// Reads the headers of the converted CSV file (`paths.output`).
+    pub fn read(args: &Arguments, paths: &Paths) -> MyResult<Self> {
+        let mut reader = csv_reader(args, &paths.output)?;
+        let original = reader.headers().with_path(&paths.input)?.clone();
+        let mapped = map_headers(&original, &args.input.mapping);
+
+        let headers: Vec<(String, Option<&'static str>)> = original
+            .iter()
+            .zip(&mapped)
+            .map(|(header, mapped)| (header.to_string(), column_of(mapped).map(|c| c.name)))
+            .collect();
+
+        let missing = PerDcomp::columns()
+            .into_iter()
+            .map(|column| column.name)
+            .filter(|name| !headers.iter().any(|(_, field)| field == &Some(*name)))
+            .collect();
+
+        Ok(Self { headers, missing })
+    }
+
+    /// The headers as a two-column table.
+    pub fn table(&self) -> QueryResult {
+        QueryResult {
+            columns: vec!["Header".to_string(), "Field".to_string()],
+            rows: self
+                .headers
+                .iter()
+                .map(|(header, field)| {
+                    vec![
+                        Cell::Text(header.clone()),
+                        Cell::Text(field.unwrap_or("(ignored)").to_string()),
+                    ]
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The column filled by a CSV header: its name, its canonical header or a serde alias.
+///
+/// Aliases are only declared on the `PerDcomp` fields, so they are found by
+/// reading a record with both the canonical header of a column and `header`:
+/// the deserializer rejects it as a duplicate field when both name the same one.
+pub(crate) fn column_of(header: &str) -> Option<Column> {
+    if let Some((_, column)) = find_column(header) {
+        return Some(column);
+    }
+
+    PerDcomp::columns().into_iter().find(|column| {
+        let value = sample_value(column.kind);
+        let headers = StringRecord::from(vec![column.header, header]);
+        let record = StringRecord::from(vec![value, value]);
+        record
+            .deserialize::<PerDcomp>(Some(&headers))
+            .is_err_and(|error| error.to_string().contains("duplicate field"))
+    })
+}
+
+/// A text accepted by the deserializer of every column of `kind`.
+pub(crate) fn sample_value(kind: ColumnKind) -> &'static str {
+    match kind {
+        ColumnKind::Money | ColumnKind::Integer => "0",
+        ColumnKind::Date => "01/01/2020",
+        ColumnKind::Bool => "Sim",
+        ColumnKind::Text => "x",
+    }
+}
+
+/// Counts and totals of the records, then the documents by situação and by quarter.
+pub fn summary_tables(report: &ConversionReport, perdcomps: &[PerDcomp]) -> Vec<QueryResult> {
+    let text = |value: &str| Cell::Text(value.to_string());
+    let count = |value: usize| Cell::Integer(value as i64);
+
+    let mut overview = vec![
+        vec![text("Rows read"), count(report.rows_read)],
+        vec![text("Rows rejected"), count(report.rejected.len())],
+        vec![text("Rows written"), count(report.rows_written)],
+    ];
+    if let Some(totals) = &report.totals {
+        overview.extend([
+            vec![
+                text("Valor Total do Crédito"),
+                Cell::Money(totals.valor_total_do_credito),
+            ],
+            vec![text("Valor do PER"), Cell::Money(totals.valor_do_per)],
+            vec![text("Total de Débitos"), Cell::Money(totals.total_debitos)],
+        ]);
+    }
+
+    vec![
+        QueryResult {
+            columns: vec!["Summary".to_string(), "Value".to_string()],
+            rows: overview,
+        },
+        QueryResult {
+            columns: vec!["Situação".to_string(), "Documents".to_string()],
+            rows: documents_by_situacao(perdcomps)
+                .into_iter()
+                .map(|(situacao, documents)| vec![Cell::Text(situacao), count(documents as usize)])
+                .collect(),
+        },
+        QueryResult {
+            columns: vec![
+                "Quarter".to_string(),
+                "Crédito".to_string(),
+                "PER".to_string(),
+            ],
+            rows: quarter_totals(perdcomps)
+                .into_iter()
+                .map(|quarter| {
+                    vec![
+                        Cell::Text(quarter.label),
+                        Cell::Money(quarter.credito),
+                        Cell::Money(quarter.per),
+                    ]
+                })
+                .collect(),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests_inspect {
+    use super::*;
+    use clap::Parser;
+    use std::{io::Write, path::Path};
+
+    #[test]
+    fn headers_are_matched_by_name_alias_and_mapping() -> MyResult<()> {
+        let mut input = tempfile::NamedTempFile::new()?;
+        writeln!(input, "PER/DCOMP;Tipo Crédito;Status Atual;Observação")?;
+
+        let mut args = Arguments::try_parse_from(["test", "-d", ";", "-p", "input.csv"])?;
+        args.input
+            .mapping
+            .insert("Status Atual".to_string(), "situacao".to_string());
+        let paths = Paths {
+            input: Path::new("input.csv").into(),
+            output: input.path().into(),
+        };
+        let report = HeaderReport::read(&args, &paths)?;
+
+        assert_eq!(
+            report.headers,
+            [
+                ("PER/DCOMP".to_string(), Some("per_dcomp")),
+                ("Tipo Crédito".to_string(), Some("tipo_do_credito")),
+                ("Status Atual".to_string(), Some("situacao")),
+                ("Observação".to_string(), None),
+            ]
+        );
+        assert!(report.missing.contains(&"valor_do_per"));
+        assert!(!report.missing.contains(&"situacao"));
+        Ok(())
+    }
+}
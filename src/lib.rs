@@ -1,16 +1,91 @@
+mod annotations;
+mod anomalies;
 mod args;
+mod autofit;
+mod charts;
+mod column_stats;
+mod config;
+mod converter;
+mod credit_types;
+mod debitos;
+mod diff;
+mod efd;
+mod error;
 mod excel;
+mod export;
+mod format_rules;
+mod generic;
+mod inspect;
+mod logging;
+mod metadata;
+mod progress;
+mod query;
 mod regex;
+mod streaming;
 mod structures;
+mod watch;
+mod workbook_reader;
 
-pub use args::Arguments;
-pub use excel::write_xlsx;
+pub use annotations::{
+    ANNOTATION_HEADERS, ANNOTATIONS_FILE, Annotation, Annotations, STATUS_OPTIONS,
+};
+pub use anomalies::{ANOMALY_SHEET_NAME, Anomaly, Severity, detect_anomalies};
+pub use args::{Arguments, Command, ConfigCommand, DiffArgs, QueryArgs, WatchArgs};
+pub use autofit::{
+    CHECKBOX_WIDTH, ColumnWidth, ColumnWidths, HEADER_ROW_HEIGHT, header_width, money_text,
+    text_width,
+};
+pub use charts::{
+    CHARTS_SHEET_NAME, QuarterTotals, cumulative_utilization, documents_by_situacao,
+    push_charts_worksheet, quarter_totals,
+};
+pub use column_stats::{ColumnStats, StatValue, TableStats, collect_column_stats};
+pub(crate) use config::map_headers;
+pub use config::{
+    CONFIG_FILE, Config, FilterRule, find_column, output_stem, sort_records, table_style,
+};
+pub use converter::{CommandOutput, ConversionReport, Converter, RejectedRow, Totals};
+pub use credit_types::{CreditCatalog, CreditType};
+pub use debitos::{DEBITOS_SHEET_NAME, Debito, drop_debitos_of, link_debitos};
+pub use diff::{FieldChange, RecordDiff, diff_records};
+pub use efd::{EFD_SHEET_NAME, EfdComparison, EfdCredit, EfdStatus, compare_with_efd};
+pub use error::{PerDcompError, WithPath};
+pub use excel::{
+    SheetColumns, SheetOptions, calculate_max_column_widths, new_workbook, push_cells_worksheet,
+    push_cells_worksheets, push_worksheets, push_worksheets_with_options, save_workbook,
+    write_xlsx,
+};
+pub(crate) use export::perdcomp_table;
+pub use export::{
+    Cell, Column, ColumnKind, CsvExporter, Exporter, JsonExporter, OutputFormat,
+    PERDCOMP_SHEET_NAME, ParquetExporter, Tabular, XlsxExporter,
+};
+pub use format_rules::{FormatRule, FormatRules, RuleOperator};
+pub use generic::{GENERIC_SHEET_NAME, GenericTable, infer_kind};
+pub use inspect::{HeaderReport, summary_tables};
+pub use logging::{LogFormat, init_logging};
+pub(crate) use metadata::input_role;
+pub use metadata::{
+    InputFile, METADATA_SHEET_NAME, Provenance, TOOL_VERSION, push_metadata_worksheet,
+};
+pub(crate) use progress::{bytes_bar, progress_bar, spinner};
+pub use progress::{enable_progress, progress_enabled};
+pub use query::{QUERY_TABLE_NAME, QueryResult, run_query};
 pub use regex::*;
-pub use structures::PerDcomp;
+pub use streaming::{STREAM_SAMPLE_SIZE, StreamingWriter, stream_csv_to_xlsx};
+pub use structures::{
+    Origem, PerDcomp, Situacao, TipoDocumento, normalize_text, parse_date, parse_quarter,
+};
+pub use watch::{PROCESSED_FILE, watch_folder};
+pub use workbook_reader::{is_xlsx, read_xlsx};
 
+use clap::ValueEnum;
 use claudiofsr_lib::BytesExtension;
-use csv::ReaderBuilder;
+use csv::{ReaderBuilder, StringRecord};
 use encoding_rs::WINDOWS_1252;
+use log::{debug, info, trace};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tempfile::NamedTempFile;
 
 use std::{
     collections::{BTreeMap, HashMap},
@@ -22,7 +97,7 @@ use std::{
     sync::Arc,
 };
 
-pub type MyError = Box<dyn std::error::Error>;
+pub type MyError = PerDcompError;
 pub type MyResult<T> = Result<T, MyError>;
 
 pub const NEWLINE_BYTE: u8 = b'\n';
@@ -40,7 +115,8 @@ pub fn get_first_line(paths: &Paths) -> MyResult<String> {
     let file_input: File = OpenOptions::new()
         .read(true)
         .write(false)
-        .open(&paths.input)?;
+        .open(&paths.input)
+        .with_path(&paths.input)?;
 
     let mut buffer_input = BufReader::new(file_input);
     let mut vec_bytes: Vec<u8> = Vec::new();
@@ -63,45 +139,72 @@ pub fn format_input_csv_file(args: &Arguments, paths: &Paths) -> MyResult<()> {
     let file_input: File = OpenOptions::new()
         .read(true)
         .write(false)
-        .open(&paths.input)?;
+        .open(&paths.input)
+        .with_path(&paths.input)?;
 
     let file_output: File = OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
         .truncate(true) // replace the file
-        .open(&paths.output)?;
+        .open(&paths.output)
+        .with_path(&paths.output)?;
 
     let mut buffer_output = BufWriter::with_capacity(1024 * 1024, file_output);
+    let progress = bytes_bar(file_input.metadata()?.len(), "Decoding");
+
+    decode_lines(
+        args,
+        BufReader::new(file_input),
+        &mut buffer_output,
+        &paths.input,
+        |bytes| progress.inc(bytes as u64),
+    )?;
+
+    buffer_output.flush()?;
+    progress.finish_and_clear();
+
+    Ok(())
+}
 
-    BufReader::new(file_input)
+/// Decodes each line of `reader` and writes it to `writer` at once.
+///
+/// Only one line is held in memory, so `--stream` runs in bounded memory
+/// whatever the size of the input. `read` receives the bytes of each line.
+fn decode_lines<R, W>(
+    args: &Arguments,
+    reader: R,
+    writer: &mut W,
+    path: &Path,
+    read: impl Fn(usize),
+) -> MyResult<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    reader
         .split(NEWLINE_BYTE)
         .enumerate()
-        .map(|(i, res)| {
+        .try_for_each(|(i, res)| {
             let bytes = res?;
-            let line = get_string_utf8(&bytes, i + 1, &paths.input)?;
+            read(bytes.len() + 1);
+            let line = args.input.encoding.decode(&bytes, i + 1, path)?;
 
             if i == 0 {
-                Ok(get_fields_without_duplication(&line, args))
+                writeln!(writer, "{}", get_fields_without_duplication(&line, args))?;
             } else {
-                Ok(line)
+                writeln!(writer, "{line}")?;
             }
+            Ok(()) // Interrompe no primeiro erro
         })
-        .collect::<MyResult<Vec<String>>>()? // Interrompe no primeiro erro
-        .into_iter()
-        .try_for_each(|line| writeln!(buffer_output, "{}", line))?;
-
-    buffer_output.flush()?;
-
-    Ok(())
 }
 
 /// Get fields without duplication.
 ///
 /// Add indexes on duplicate fields (column names).
 pub fn get_fields_without_duplication(line: &str, args: &Arguments) -> String {
-    let cols: Vec<String> = parse_line(line, args.delimiter, args.verbose);
-    let frequency: BTreeMap<&str, u32> = get_frequency(args, &cols);
+    let cols: Vec<String> = parse_line(line, args.input.delimiter);
+    let frequency: BTreeMap<&str, u32> = get_frequency(&cols);
     let mut count = HashMap::new();
     let mut fields_without_duplication: Vec<String> = Vec::new();
 
@@ -117,7 +220,7 @@ pub fn get_fields_without_duplication(line: &str, args: &Arguments) -> String {
             col.to_string()
         };
 
-        let column_with_quotes = if new_col_name.contains(&args.delimiter.to_string()) {
+        let column_with_quotes = if new_col_name.contains(&args.input.delimiter.to_string()) {
             format!("{new_col_name:#?}")
         } else {
             new_col_name
@@ -126,13 +229,12 @@ pub fn get_fields_without_duplication(line: &str, args: &Arguments) -> String {
         fields_without_duplication.push(column_with_quotes);
     }
 
-    let fields = fields_without_duplication.join(&args.delimiter.to_string());
+    let fields = fields_without_duplication.join(&args.input.delimiter.to_string());
 
-    if args.verbose {
-        let length = fields_without_duplication.len();
-        println!("These {length} fields are the column names:");
-        println!("fields_without_duplication: {fields_without_duplication:#?}\n");
-    }
+    debug!(
+        "These {} fields are the column names: {fields_without_duplication:?}",
+        fields_without_duplication.len()
+    );
 
     fields
 }
@@ -144,11 +246,9 @@ pub fn get_fields_without_duplication(line: &str, args: &Arguments) -> String {
 
     let line: &str = r#"a, b, "foo \nbar",def , the other"#;
     let delimiter: char = ',';
-    let verbose = true;
 
-    let cols: Vec<String> = parse_line(&line, delimiter, verbose);
+    let cols: Vec<String> = parse_line(&line, delimiter);
 
-    println!("line: {line}");
     assert_eq!(
         cols,
         vec!["a", "b", "\"foo \\nbar\"", "def", "the other"]
@@ -156,7 +256,7 @@ pub fn get_fields_without_duplication(line: &str, args: &Arguments) -> String {
 
     ```
 */
-pub fn parse_line(line: &str, delimiter: char, verbose: bool) -> Vec<String> {
+pub fn parse_line(line: &str, delimiter: char) -> Vec<String> {
     let mut reader = ReaderBuilder::new()
         .quoting(true)
         .double_quote(true)
@@ -177,15 +277,13 @@ pub fn parse_line(line: &str, delimiter: char, verbose: bool) -> Vec<String> {
         })
         .collect();
 
-    if verbose {
-        println!("colunas: {colunas:#?}\n");
-    }
+    trace!("colunas: {colunas:?}");
 
     colunas
 }
 
 /// Get word frequency
-pub fn get_frequency<'a>(args: &Arguments, cols: &'a [String]) -> BTreeMap<&'a str, u32> {
+pub fn get_frequency(cols: &[String]) -> BTreeMap<&str, u32> {
     // Ordenado pelo nome, em caso de mesma frequência.
     let mut frequency: BTreeMap<&str, u32> = BTreeMap::new();
 
@@ -193,13 +291,42 @@ pub fn get_frequency<'a>(args: &Arguments, cols: &'a [String]) -> BTreeMap<&'a s
         *frequency.entry(col).or_insert(0) += 1;
     }
 
-    if args.verbose {
-        println!("frequency: {frequency:#?}\n");
-    }
+    trace!("frequency: {frequency:?}");
 
     frequency
 }
 
+/// Text encoding of the CSV inputs (`--encoding`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextEncoding {
+    /// UTF-8, or WINDOWS-1252 for the lines that are not valid UTF-8.
+    #[default]
+    Auto,
+    /// UTF-8 only: other lines are an error.
+    Utf8,
+    /// WINDOWS-1252 only, for files whose bytes happen to be valid UTF-8.
+    Windows1252,
+}
+
+impl TextEncoding {
+    /// Decodes one line; see [`get_string_utf8`] for `Auto`.
+    pub fn decode(self, slice_bytes: &[u8], line_number: usize, path: &Path) -> MyResult<String> {
+        match self {
+            TextEncoding::Auto => get_string_utf8(slice_bytes, line_number, path),
+            TextEncoding::Utf8 => {
+                str::from_utf8(slice_bytes)
+                    .map(String::from)
+                    .map_err(|_| PerDcompError::Encoding {
+                        path: Some(path.to_path_buf()),
+                        line: Some(line_number as u64),
+                    })
+            }
+            TextEncoding::Windows1252 => Ok(WINDOWS_1252.decode(slice_bytes).0.into_owned()),
+        }
+    }
+}
+
 /**
 Converts a slice of bytes to a String, attempting to handle different encodings.
 
@@ -242,11 +369,10 @@ pub fn get_string_utf8(slice_bytes: &[u8], line_number: usize, path: &Path) -> M
 
     // If WINDOWS_1252 decoding also fails, return a detailed error
     if has_errors {
-        return Err(format!(
-            "UTF-8 and WINDOWS_1252 decoding failed!\n\
-                Failed to decode line {line_number} from file {path:?}"
-        )
-        .into());
+        return Err(PerDcompError::Encoding {
+            path: Some(path.to_path_buf()),
+            line: Some(line_number as u64),
+        });
     }
 
     Ok(res.into_owned())
@@ -258,33 +384,123 @@ Reads a CSV file from the given path and deserializes its contents into a vector
 ### Arguments
 
 * `args` - A struct containing configuration options, such as the delimiter.
-* `path` - The path to the CSV file.
+* `paths` - The original CSV file (`input`) and its UTF-8 conversion (`output`),
+  written by [`format_input_csv_file`].
 
 ### Returns
 
 A `MyResult` containing a vector of `PerDcomp` structs if successful, or a `MyError` if an error occurred.
+Each record keeps its [`Origem`]: the original file and its line in it.
 */
-pub fn read_csv<P>(args: &Arguments, path: P) -> MyResult<Vec<PerDcomp>>
+pub fn read_csv(args: &Arguments, paths: &Paths) -> MyResult<Vec<PerDcomp>> {
+    perdcomp_records(args, paths)?.collect()
+}
+
+/// Parses the converted CSV file one `PerDcomp` at a time, with its year and [`Origem`].
+///
+/// The conversion keeps one output line per input line, so the line numbers
+/// given by [`csv::Position`] refer to the original file `paths.input`.
+pub fn perdcomp_records(
+    args: &Arguments,
+    paths: &Paths,
+) -> MyResult<impl Iterator<Item = MyResult<PerDcomp>>> {
+    let mut reader = csv_reader(args, &paths.output)?;
+    let headers = map_headers(
+        reader.headers().with_path(&paths.input)?,
+        &args.input.mapping,
+    );
+    let file: Arc<str> = paths.input.display().to_string().into();
+    let input = Arc::clone(&paths.input);
+    let mut record = StringRecord::new();
+    let progress = bytes_bar(
+        std::fs::metadata(&paths.output)
+            .with_path(&paths.output)?
+            .len(),
+        "Parsing",
+    );
+
+    Ok(std::iter::from_fn(move || {
+        progress.set_position(reader.position().byte());
+        match reader.read_record(&mut record) {
+            Ok(true) => Some(parse_perdcomp(&record, &headers, &file).with_path(&input)),
+            Ok(false) => {
+                progress.finish_and_clear();
+                None
+            }
+            Err(error) => Some(Err(PerDcompError::from(error).with_path(&input))),
+        }
+    }))
+}
+
+fn parse_perdcomp(
+    record: &StringRecord,
+    headers: &StringRecord,
+    file: &Arc<str>,
+) -> MyResult<PerDcomp> {
+    let mut perdcomp: PerDcomp = record
+        .deserialize(Some(headers))
+        .map_err(|error| PerDcompError::from_record(error, headers, record))?;
+    perdcomp.get_year();
+    perdcomp.origem = record.position().map(|position| Origem {
+        file: Arc::clone(file),
+        line: position.line(),
+    });
+    Ok(perdcomp)
+}
+
+/// Reads a UTF-8 CSV file and deserializes each row into `T`.
+///
+/// Uses the same reader configuration as [`read_csv`], so it can be applied
+/// to any companion export already converted by [`format_input_csv_file`].
+pub fn read_csv_records<T, P>(args: &Arguments, path: P) -> MyResult<Vec<T>>
 where
+    T: DeserializeOwned,
     P: AsRef<Path>,
 {
-    let mut reader = ReaderBuilder::new()
+    let mut reader = csv_reader(args, path)?;
+
+    reader
+        .deserialize()
+        .map(|result: Result<T, csv::Error>| Ok(result?))
+        .collect()
+}
+
+/// Opens a UTF-8 CSV file with the reader configuration shared by every input.
+pub fn csv_reader<P>(args: &Arguments, path: P) -> MyResult<csv::Reader<File>>
+where
+    P: AsRef<Path>,
+{
+    let reader = ReaderBuilder::new()
         .quoting(true)
         .double_quote(true)
         .has_headers(true)
         .trim(csv::Trim::All)
         .flexible(false)
-        .delimiter(args.delimiter as u8)
-        .from_path(path.as_ref())?;
+        .delimiter(args.input.delimiter as u8)
+        .from_path(path.as_ref())
+        .with_path(path.as_ref())?;
 
-    reader
-        .deserialize()
-        .map(|result_perdcomp: Result<PerDcomp, csv::Error>| {
-            let mut per_comp = result_perdcomp?;
-            per_comp.get_year();
-            Ok(per_comp)
-        })
-        .collect()
+    Ok(reader)
+}
+
+/// Reads a companion CSV export (e.g. EFD credits) into records of type `T`.
+///
+/// The file goes through the same pipeline as the main input: it is converted
+/// to UTF-8 with de-duplicated headers in a temporary file, which is then parsed.
+pub fn read_companion_csv<T, P>(args: &Arguments, path: P) -> MyResult<Vec<T>>
+where
+    T: DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let temporary = NamedTempFile::new()?;
+
+    let paths = Paths {
+        input: path.as_ref().into(),
+        output: temporary.path().into(),
+    };
+
+    format_input_csv_file(args, &paths)?;
+    read_csv_records(args, &temporary).with_path(path.as_ref())
 }
 
 /// Rename a file to a new name,
@@ -292,7 +508,7 @@ where
 pub fn rename_file(old_path: &PathBuf, new_name: &str) -> MyResult<()> {
     let mut new_path = old_path.clone();
     new_path.set_file_name(new_name);
-    eprintln!("Keep temporary CSV file: {new_path:?}");
+    info!("Keep temporary CSV file: {new_path:?}");
     fs::rename(old_path, new_path)?;
     Ok(())
 }
@@ -339,6 +555,143 @@ mod tests_get_string_utf8 {
     }
 }
 
+#[cfg(test)]
+mod tests_read_csv {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn records_keep_their_origin() -> MyResult<()> {
+        let mut input = NamedTempFile::new()?;
+        writeln!(
+            input,
+            "PER/DCOMP;Valor Total Crédito;Valor Crédito Data Transmissão;Valor Total Débitos/Valor Pedido Rest/Ress.;Motivo"
+        )?;
+        writeln!(input, "111;1,00;1,00;1,00;\"linha 1\nlinha 2\"")?;
+        writeln!(input, "222;2,00;2,00;2,00;Outro")?;
+        let temporary = NamedTempFile::new()?;
+        let paths = Paths {
+            input: input.path().into(),
+            output: temporary.path().into(),
+        };
+
+        let args = Arguments::try_parse_from(["test", "-d", ";", "-p", "input.csv"])?;
+        format_input_csv_file(&args, &paths)?;
+        let perdcomps = read_csv(&args, &paths)?;
+
+        let lines: Vec<u64> = perdcomps
+            .iter()
+            .filter_map(|perdcomp| perdcomp.origem.as_ref())
+            .map(|origem| origem.line)
+            .collect();
+        assert_eq!(lines, [2, 4]); // The first record spans lines 2 and 3
+
+        let origem = perdcomps[1].origem.as_ref().unwrap().to_string();
+        assert_eq!(origem, format!("{}:4", input.path().display()));
+        Ok(())
+    }
+
+    #[test]
+    fn a_missing_input_names_the_file() -> MyResult<()> {
+        let temporary = NamedTempFile::new()?;
+        let paths = Paths {
+            input: Path::new("sem_arquivo.csv").into(),
+            output: temporary.path().into(),
+        };
+
+        let args = Arguments::try_parse_from(["test", "-d", ";", "-p", "sem_arquivo.csv"])?;
+        let error = format_input_csv_file(&args, &paths).unwrap_err();
+
+        assert!(error.to_string().contains("sem_arquivo.csv"), "{error}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests_decode_lines {
+    use super::*;
+    use clap::Parser;
+    use std::{
+        cell::Cell,
+        io::{self, Read, sink},
+    };
+
+    /// `size` bytes of CSV lines, generated as they are read.
+    struct Lines {
+        size: usize,
+        position: usize,
+    }
+
+    impl Read for Lines {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            const LINE: &[u8] = b"11111.11111.120921.1.1.04-1111;1.000,00;Deferido\n";
+            let len = buf.len().min(self.size - self.position);
+            for (offset, byte) in buf[..len].iter_mut().enumerate() {
+                *byte = LINE[(self.position + offset) % LINE.len()];
+            }
+            self.position += len;
+            Ok(len)
+        }
+    }
+
+    /// Resident memory of the process in bytes, from `/proc/self/statm`.
+    #[cfg(target_os = "linux")]
+    fn resident_bytes() -> usize {
+        let statm = fs::read_to_string("/proc/self/statm").unwrap_or_default();
+        let pages: usize = statm
+            .split_whitespace()
+            .nth(1)
+            .and_then(|pages| pages.parse().ok())
+            .unwrap_or_default();
+        pages * 4096
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "decodes 256 MB and reads the memory of the whole process: run it alone"]
+    fn memory_stays_bounded_while_decoding() -> MyResult<()> {
+        const MB: usize = 1024 * 1024;
+        let args = Arguments::try_parse_from(["test", "-d", ";", "-p", "input.csv"])?;
+        let size = 256 * MB;
+        let reader = BufReader::new(Lines { size, position: 0 });
+
+        // Sample the resident memory every 16 MB read
+        let before = resident_bytes();
+        let (read, peak) = (Cell::new(0), Cell::new(before));
+        decode_lines(
+            &args,
+            reader,
+            &mut sink(),
+            Path::new("input.csv"),
+            |bytes| {
+                if (read.get() + bytes) / (16 * MB) > read.get() / (16 * MB) {
+                    peak.set(peak.get().max(resident_bytes()));
+                }
+                read.set(read.get() + bytes);
+            },
+        )?;
+        let growth = peak.get().saturating_sub(before);
+
+        assert!(read.get() >= size);
+        assert!(growth < 64 * MB, "{} MB more resident memory", growth / MB);
+        Ok(())
+    }
+
+    #[test]
+    fn lines_are_written_before_a_decoding_error() -> MyResult<()> {
+        let args =
+            Arguments::try_parse_from(["test", "-d", ";", "--encoding", "utf8", "-p", "in.csv"])?;
+        let input: &[u8] = b"a;b\n1;2\n\xff;3\n";
+        let mut output: Vec<u8> = Vec::new();
+
+        let result = decode_lines(&args, input, &mut output, Path::new("in.csv"), |_| {});
+
+        assert!(result.is_err());
+        assert_eq!(output, b"a;b\n1;2\n");
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test_my_perdcomp {
     use super::*;
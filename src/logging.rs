@@ -0,0 +1,88 @@
+//! Levelled diagnostics on stderr, as plain text or JSON lines.
+//!
+//! The library only emits [`log`] records; the binary picks the level
+//! (`-q`, `-v`, `-vv`) and the format (`--log-format`) with [`init_logging`].
+//! Programs embedding the [`Converter`](crate::Converter) can install any other
+//! `log` implementation instead.
+
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use indicatif_log_bridge::LogWrapper;
+use log::{Level, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::Write;
+
+use crate::{MyResult, progress::PROGRESS};
+
+/// Layout of the log lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `Info: message`, as read by people.
+    #[default]
+    Text,
+    /// One JSON object per line, with `timestamp`, `level`, `target` and `message`.
+    Json,
+}
+
+/// Sends the log records up to `level` to stderr.
+///
+/// Lines are written above the progress bars (see [`enable_progress`](crate::enable_progress)).
+pub fn init_logging(level: LevelFilter, format: LogFormat) -> MyResult<()> {
+    let logger = env_logger::Builder::new()
+        .filter_level(level)
+        .target(env_logger::Target::Stderr)
+        .format(move |buf, record| match format {
+            LogFormat::Text => writeln!(buf, "{}: {}", label(record.level()), record.args()),
+            LogFormat::Json => {
+                let line = json!({
+                    "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                });
+                writeln!(buf, "{line}")
+            }
+        })
+        .build();
+
+    LogWrapper::new(PROGRESS.clone(), logger)
+        .try_init()
+        .map_err(|err| format!("Falha ao iniciar o registro de mensagens: {err}"))?;
+    log::set_max_level(level);
+
+    Ok(())
+}
+
+/// Prefix of the text lines; errors keep the Portuguese label of the exit message.
+fn label(level: Level) -> &'static str {
+    match level {
+        Level::Error => "Erro",
+        Level::Warn => "Warning",
+        Level::Info => "Info",
+        Level::Debug => "Debug",
+        Level::Trace => "Trace",
+    }
+}
+
+#[cfg(test)]
+mod tests_logging {
+    use crate::Arguments;
+    use clap::Parser;
+    use log::LevelFilter;
+
+    #[test]
+    fn verbosity_flags_pick_the_level() {
+        let level = |flags: &[&str]| {
+            let args = ["test", "-p", "input.csv"].iter().chain(flags);
+            Arguments::try_parse_from(args).map(|args| args.log_level())
+        };
+
+        assert_eq!(level(&[]).ok(), Some(LevelFilter::Info));
+        assert_eq!(level(&["-q"]).ok(), Some(LevelFilter::Error));
+        assert_eq!(level(&["-v"]).ok(), Some(LevelFilter::Debug));
+        assert_eq!(level(&["-vv"]).ok(), Some(LevelFilter::Trace));
+        assert!(level(&["-q", "-v"]).is_err());
+    }
+}
@@ -2,8 +2,8 @@
 use perdcomp_csv_to_xlsx::*;
 
 use execution_time::ExecutionTime;
-use std::cmp::Reverse;
-use tempfile::NamedTempFile;
+use log::{LevelFilter, error, info};
+use std::process::ExitCode;
 
 /*
     clear && cargo test -- --nocapture
@@ -25,80 +25,87 @@ Status PER/DCOMP: Ativo
 Demonstra Crédito: Sim
 */
 
-fn main() -> MyResult<()> {
+/// Exits with the code of the error category, see [`PerDcompError::exit_code`].
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            // Errors of the arguments and configuration files come before the logger
+            if log::max_level() == LevelFilter::Off {
+                eprintln!("Erro: {error}");
+            } else {
+                error!("{error}");
+            }
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+fn run() -> MyResult<()> {
     let timer = ExecutionTime::start();
     let arguments = Arguments::build()?;
-
-    // See https://docs.rs/tempfile
-    // Create a file inside of `std::env::temp_dir()`.
-    let temporary = NamedTempFile::new()?;
-
-    let paths = Paths {
-        input: arguments.path.clone().into(), // CSV file
-        output: temporary.path().into(),      // Temp file
-    };
-
-    if arguments.verbose {
-        dbg!(&paths);
+    init_logging(arguments.log_level(), arguments.log.log_format)?;
+    // Progress bars would break the JSON lines and do not belong in quiet runs
+    enable_progress(!arguments.log.quiet && arguments.log.log_format == LogFormat::Text);
+    let time = arguments.log.time;
+
+    if let Some(Command::Config(ConfigCommand::Show)) = &arguments.command {
+        print!("{}", Config::from_arguments(&arguments).to_toml()?);
+        return Ok(());
     }
 
-    //let first_line = get_first_line(&paths)?;
-    //println!("first_line: {first_line:?}");
-
-    // Convert a csv file (WINDOWS_1252) to UTF8
-    format_input_csv_file(&arguments, &paths)?;
-
-    let mut perdcomps: Vec<PerDcomp> = read_csv(&arguments, &temporary)?;
-
-    // Sort Vec<PerDcomp> by key
-    perdcomps.sort_by_key(|perdcomp| {
-        (
-            perdcomp.ano,
-            perdcomp.trimestre_de_apuracao.clone(),
-            Reverse(perdcomp.tipo_do_credito.clone()),
-            perdcomp.data_da_transmissao,
-        )
-    });
-
-    if arguments.verbose {
-        println!("Display up to the first 50 lines:\n");
-        perdcomps
-            .iter()
-            .take(50)
-            .enumerate()
-            .for_each(|(index, perdcomp)| {
-                println!("line {:02}: {perdcomp:?}\n", index + 1);
-            })
+    if let Some(Command::Watch(watch)) = &arguments.command {
+        let watch = watch.clone();
+        return watch_folder(arguments, &watch);
     }
 
-    // Prevent the temporary file from being deleted.
-    // And then, rename the temporary file to “temporary.csv”.
-    if arguments.keep {
-        let path_buf = temporary.into_temp_path().keep()?;
-        rename_file(&path_buf, "temporary.csv")?;
+    let validate = matches!(arguments.command, Some(Command::Validate));
+    let report = Converter::from_arguments(arguments).run()?;
+    if let Some(output) = &report.command_output {
+        print_command_output(output, &report);
     }
 
-    // 1. Detecta colunas vazias apenas se o flag estiver ativo
-    let columns_to_hide = if arguments.remove_empty {
-        PerDcomp::get_empty_column_indices(&perdcomps)
-    } else {
-        Vec::new()
-    };
-
-    //println!("perdcomps: {perdcomps:#?}");
+    if validate && !report.rejected.is_empty() {
+        return Err(format!("{} registros inválidos.", report.rejected.len()).into());
+    }
 
-    // 2. Passa a lista para a função de escrita
-    write_xlsx(
-        &perdcomps,
-        "PERDComp",
-        "perdcomp.xlsx",
-        &columns_to_hide,
-        arguments.verbose,
-    )?;
+    if !report.rejected.is_empty() {
+        info!("{} rows rejected.", report.rejected.len());
+    }
 
-    if arguments.time {
+    if time {
         timer.print_elapsed_time();
     }
 
     Ok(())
 }
+
+/// Prints the result of a subcommand as the tables of [`QueryResult::print_table`].
+fn print_command_output(output: &CommandOutput, report: &ConversionReport) {
+    match output {
+        CommandOutput::Headers(files) => {
+            for (path, headers) in files {
+                println!("{}", path.display());
+                headers.table().print_table();
+                if !headers.missing.is_empty() {
+                    println!("Missing fields: {}", headers.missing.join(", "));
+                }
+                println!();
+            }
+        }
+        CommandOutput::Validation { valid } => {
+            for rejected in &report.rejected {
+                println!("{}", rejected.reason);
+            }
+            println!("{valid} valid records, {} invalid.", report.rejected.len());
+        }
+        CommandOutput::Query(result) => result.print_table(),
+        CommandOutput::Summary(tables) => {
+            for table in tables {
+                table.print_table();
+                println!();
+            }
+        }
+        CommandOutput::Diff(changes) => changes.table().print_table(),
+    }
+}
@@ -0,0 +1,288 @@
+//! Provenance of a generated workbook.
+//!
+//! The "Metadados" worksheet and the document properties record what was
+//! converted and how: the input files with their SHA-256 hashes and the
+//! encodings they were decoded with, the delimiter, the filters and sort keys,
+//! the row counts and the tool version.
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use rust_xlsxwriter::{DocProperties, Workbook, Worksheet, XlsxError};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Arguments, HEADER_ROW_HEIGHT, MyResult, NEWLINE_BYTE, TextEncoding, WithPath,
+    excel::write_headers, is_xlsx, structures::FORMAT,
+};
+
+/// Name of the worksheet that records the provenance.
+pub const METADATA_SHEET_NAME: &str = "Metadados";
+
+/// Name and version of this tool, as written to the workbook.
+pub const TOOL_VERSION: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+/// One input file, identified by its content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputFile {
+    /// What the file was used for, such as "PER/DCOMP" or "Débitos".
+    pub role: String,
+    pub path: PathBuf,
+    /// Size in bytes.
+    pub size: u64,
+    /// Lowercase hexadecimal SHA-256 digest.
+    pub sha256: String,
+    /// The encoding the file was decoded with: the one given by `--encoding`,
+    /// or with `auto` "UTF-8", and "WINDOWS-1252" when any line is not valid
+    /// UTF-8 (the fallback of [`get_string_utf8`](crate::get_string_utf8)).
+    /// "XLSX" for workbooks.
+    pub encoding: &'static str,
+}
+
+impl InputFile {
+    /// Hashes the file and finds the encoding it is decoded with, in a single pass.
+    pub fn inspect(role: &str, path: &Path, encoding: TextEncoding) -> MyResult<Self> {
+        let mut reader = BufReader::new(File::open(path).with_path(path)?);
+        let mut hasher = Sha256::new();
+        let mut line: Vec<u8> = Vec::new();
+        let mut size: u64 = 0;
+        let mut utf8 = true;
+
+        // Lines keep their delimiter, so the digest covers every byte.
+        while reader.read_until(NEWLINE_BYTE, &mut line)? > 0 {
+            hasher.update(&line);
+            size += line.len() as u64;
+            utf8 = utf8 && std::str::from_utf8(&line).is_ok();
+            line.clear();
+        }
+
+        Ok(Self {
+            role: role.to_string(),
+            path: path.to_path_buf(),
+            size,
+            sha256: format!("{:x}", hasher.finalize()),
+            encoding: match (is_xlsx(path), encoding, utf8) {
+                (true, _, _) => "XLSX",
+                (false, TextEncoding::Utf8, _) | (false, TextEncoding::Auto, true) => "UTF-8",
+                (false, TextEncoding::Windows1252, _) | (false, TextEncoding::Auto, false) => {
+                    "WINDOWS-1252"
+                }
+            },
+        })
+    }
+}
+
+/// What was converted and how.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    pub inputs: Vec<InputFile>,
+    pub delimiter: char,
+    /// Row or column filters applied, in plain words.
+    pub filters: Vec<String>,
+    /// Sort keys, most significant first. Empty means file order.
+    pub sort_keys: Vec<String>,
+    pub rows_read: usize,
+    pub rows_rejected: usize,
+    pub rows_written: usize,
+    pub created: DateTime<Utc>,
+}
+
+impl Provenance {
+    /// Inspects every input file given in the arguments.
+    ///
+    /// Row counts, filters and sort keys are filled in as the pipeline runs.
+    pub fn from_arguments(args: &Arguments) -> MyResult<Self> {
+        // CSV files are decoded with --encoding, JSON files are read as UTF-8
+        let csv = args.input.encoding;
+        let json = TextEncoding::Utf8;
+        let csv_files = args
+            .input
+            .path
+            .iter()
+            .map(|path| (input_role(args), Some(path), csv));
+        let files = [
+            ("Débitos", args.convert.debitos.as_ref(), csv),
+            ("EFD-Contribuições", args.convert.efd.as_ref(), csv),
+            (
+                "Catálogo de Créditos",
+                args.convert.credit_catalog.as_ref(),
+                json,
+            ),
+            (
+                "Regras de Formatação",
+                args.convert.format_rules.as_ref(),
+                json,
+            ),
+            (
+                "Larguras de Coluna",
+                args.convert.column_widths.as_ref(),
+                json,
+            ),
+        ];
+
+        let inputs = csv_files
+            .chain(files)
+            .filter_map(|(role, path, encoding)| {
+                path.map(|path| InputFile::inspect(role, path, encoding))
+            })
+            .collect::<MyResult<Vec<InputFile>>>()?;
+
+        Ok(Self {
+            inputs,
+            delimiter: args.input.delimiter,
+            filters: Vec::new(),
+            sort_keys: Vec::new(),
+            rows_read: 0,
+            rows_rejected: 0,
+            rows_written: 0,
+            created: Utc::now(),
+        })
+    }
+
+    /// Title, subject, author and creation time of the workbook.
+    pub fn doc_properties(&self) -> DocProperties {
+        let source = self
+            .inputs
+            .iter()
+            .filter(|input| matches!(input.role.as_str(), "PER/DCOMP" | "CSV"))
+            .filter_map(|input| input.path.file_name())
+            .map(|name| name.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let author = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| env!("CARGO_PKG_NAME").to_string());
+
+        DocProperties::new()
+            .set_title("PER/DCOMP")
+            .set_subject(format!("Conversão de {source}"))
+            .set_author(author)
+            .set_comment(format!("Gerado por {TOOL_VERSION}"))
+            .set_creation_datetime(&self.created)
+    }
+
+    /// Item and value pairs of the summary range.
+    fn summary(&self) -> Vec<(&'static str, String)> {
+        let join = |items: &[String], empty: &str| {
+            if items.is_empty() {
+                empty.to_string()
+            } else {
+                items.join("; ")
+            }
+        };
+
+        vec![
+            ("Ferramenta", TOOL_VERSION.to_string()),
+            (
+                "Gerado em (UTC)",
+                self.created.format("%Y-%m-%d %H:%M:%S").to_string(),
+            ),
+            ("Delimitador", format!("{:?}", self.delimiter)),
+            ("Filtros", join(&self.filters, "Nenhum")),
+            ("Ordenação", join(&self.sort_keys, "Ordem do arquivo")),
+            ("Linhas Lidas", self.rows_read.to_string()),
+            ("Linhas Rejeitadas", self.rows_rejected.to_string()),
+            ("Linhas Gravadas", self.rows_written.to_string()),
+        ]
+    }
+}
+
+/// Role of the main input files: "CSV" in generic mode, else "PER/DCOMP".
+pub(crate) fn input_role(args: &Arguments) -> &'static str {
+    if args.input.generic {
+        "CSV"
+    } else {
+        "PER/DCOMP"
+    }
+}
+
+/// Sets the document properties and appends the "Metadados" worksheet.
+///
+/// The summary goes in columns A:B, followed by one row per input file.
+pub fn push_metadata_worksheet(
+    workbook: &mut Workbook,
+    provenance: &Provenance,
+) -> Result<(), XlsxError> {
+    debug!("Populating worksheet '{METADATA_SHEET_NAME}'.");
+
+    workbook.set_properties(&provenance.doc_properties());
+
+    let mut worksheet = Worksheet::new();
+    worksheet.set_name(METADATA_SHEET_NAME)?;
+
+    // 1. Summary (columns A:B).
+    write_headers(&mut worksheet, 0, 0, &["Item", "Valor"])?;
+    let summary = provenance.summary();
+    for (index, (item, value)) in summary.iter().enumerate() {
+        let row = (index + 1) as u32;
+        worksheet.write_string_with_format(row, 0, *item, &FORMAT.bold_centered)?;
+        worksheet.write_string(row, 1, value)?;
+    }
+
+    // 2. Input files, after a blank row.
+    let header_row = summary.len() as u32 + 2;
+    write_headers(
+        &mut worksheet,
+        header_row,
+        0,
+        &[
+            "Arquivo",
+            "Caminho",
+            "Tamanho (bytes)",
+            "Codificação",
+            "SHA-256",
+        ],
+    )?;
+    for (index, input) in provenance.inputs.iter().enumerate() {
+        let row = header_row + 1 + index as u32;
+        worksheet.write_string_with_format(row, 0, &input.role, &FORMAT.bold_centered)?;
+        worksheet.write_string(row, 1, input.path.display().to_string())?;
+        worksheet.write_number_with_format(row, 2, input.size as f64, &FORMAT.centered)?;
+        worksheet.write_string_with_format(row, 3, input.encoding, &FORMAT.centered)?;
+        worksheet.write_string(row, 4, &input.sha256)?;
+    }
+
+    for row in [0, header_row] {
+        worksheet.set_row_height(row, HEADER_ROW_HEIGHT)?;
+    }
+    for (col, width) in [(0, 22), (1, 60), (2, 16), (3, 16), (4, 70)] {
+        worksheet.set_column_width(col, width)?;
+    }
+
+    workbook.push_worksheet(worksheet);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests_metadata {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn inspect_hashes_and_records_encoding() -> MyResult<()> {
+        let mut utf8 = tempfile::NamedTempFile::new()?;
+        write!(utf8, "abc")?;
+        let input = InputFile::inspect("PER/DCOMP", utf8.path(), TextEncoding::Auto)?;
+        assert_eq!(
+            input.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!((input.size, input.encoding), (3, "UTF-8"));
+
+        // "Crédito" in WINDOWS-1252, on the second line.
+        let mut latin = tempfile::NamedTempFile::new()?;
+        latin.write_all(b"Ano;Valor\nCr\xe9dito;1\n")?;
+        let input = InputFile::inspect("PER/DCOMP", latin.path(), TextEncoding::Auto)?;
+        assert_eq!(input.encoding, "WINDOWS-1252");
+
+        // The encoding given by --encoding is the one used
+        let input = InputFile::inspect("PER/DCOMP", utf8.path(), TextEncoding::Windows1252)?;
+        assert_eq!(input.encoding, "WINDOWS-1252");
+        Ok(())
+    }
+}
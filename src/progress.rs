@@ -0,0 +1,93 @@
+//! Progress bars of the long steps of a conversion.
+//!
+//! Decoding, parsing, sorting, width calculation and the population of each
+//! worksheet report their progress on stderr, with the throughput and the
+//! remaining time. Bars are drawn only after the binary calls
+//! [`enable_progress`] and only while stderr is a terminal; otherwise they are
+//! hidden and cost next to nothing, so redirected runs and programs embedding
+//! the [`Converter`](crate::Converter) keep a clean stderr.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressFinish, ProgressStyle};
+use std::{
+    io::IsTerminal,
+    sync::{
+        LazyLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Every bar joins this group, which the logger suspends while writing a line.
+pub(crate) static PROGRESS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// Turns the progress bars on or off.
+///
+/// Bars stay off when stderr is not a terminal, whatever `enabled` is.
+/// Returns whether they are drawn.
+pub fn enable_progress(enabled: bool) -> bool {
+    let enabled = enabled && std::io::stderr().is_terminal();
+    ENABLED.store(enabled, Ordering::Relaxed);
+    enabled
+}
+
+/// Whether the progress bars are drawn.
+pub fn progress_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A bar of `len` items, e.g. rows.
+pub(crate) fn progress_bar(len: u64, message: impl Into<String>) -> ProgressBar {
+    new_bar(
+        Some(len),
+        "{msg:<28} [{bar:30}] {human_pos}/{human_len} ({per_sec}, ETA {eta})",
+    )
+    .with_message(message.into())
+}
+
+/// A bar of `len` bytes, for the steps that read a file.
+pub(crate) fn bytes_bar(len: u64, message: impl Into<String>) -> ProgressBar {
+    new_bar(
+        Some(len),
+        "{msg:<28} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})",
+    )
+    .with_message(message.into())
+}
+
+/// A spinner for the steps without measurable progress, such as sorting.
+pub(crate) fn spinner(message: impl Into<String>) -> ProgressBar {
+    let spinner = new_bar(None, "{spinner} {msg} ({elapsed})").with_message(message.into());
+    if !spinner.is_hidden() {
+        spinner.enable_steady_tick(Duration::from_millis(100));
+    }
+    spinner
+}
+
+fn new_bar(len: Option<u64>, template: &str) -> ProgressBar {
+    let bar = ProgressBar::with_draw_target(len, ProgressDrawTarget::hidden());
+    if !progress_enabled() {
+        return bar;
+    }
+    let style = ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ");
+    // A bar dropped early, e.g. by an error, leaves no trace either.
+    PROGRESS.add(bar.with_style(style).with_finish(ProgressFinish::AndClear))
+}
+
+#[cfg(test)]
+mod tests_progress {
+    use super::*;
+
+    #[test]
+    fn bars_are_hidden_unless_enabled() {
+        let bar = progress_bar(10, "Parsing");
+        bar.inc(4);
+
+        assert!(bar.is_hidden());
+        assert_eq!(bar.position(), 4);
+        assert_eq!(bar.length(), Some(10));
+        assert!(spinner("Sorting").is_hidden());
+    }
+}
@@ -0,0 +1,245 @@
+//! Ad-hoc SQL over the parsed records.
+//!
+//! The records are loaded into an in-memory SQLite table named `perdcomp`,
+//! whose columns are the `PerDcomp` field names (see [`Tabular::columns`]).
+//! Dates are stored as ISO 8601 text and Sim/Não values as 0/1.
+
+use log::info;
+use rusqlite::{
+    Connection, params_from_iter,
+    types::{Value, ValueRef},
+};
+use std::path::Path;
+
+use crate::{
+    Cell, ColumnKind, MyResult, PerDcomp, Tabular, WithPath, new_workbook, push_cells_worksheet,
+    save_workbook,
+};
+
+/// Name of the SQL table holding the records.
+pub const QUERY_TABLE_NAME: &str = "perdcomp";
+
+/// Columns and rows returned by a SQL statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+/// Loads `perdcomps` into an in-memory database and runs `sql`.
+pub fn run_query(perdcomps: &[PerDcomp], sql: &str) -> MyResult<QueryResult> {
+    let mut connection = Connection::open_in_memory()?;
+    load_table(&mut connection, perdcomps)?;
+
+    let mut statement = connection
+        .prepare(sql)
+        .map_err(|err| format!("Instrução SQL inválida: {err}"))?;
+
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let column_count = columns.len();
+
+    let rows = statement
+        .query_map([], |row| {
+            (0..column_count)
+                .map(|index| row.get_ref(index).map(cell_from_sql))
+                .collect::<Result<Vec<Cell>, _>>()
+        })?
+        .collect::<Result<Vec<Vec<Cell>>, _>>()?;
+
+    Ok(QueryResult { columns, rows })
+}
+
+fn load_table(connection: &mut Connection, perdcomps: &[PerDcomp]) -> MyResult<()> {
+    let columns = PerDcomp::columns();
+
+    let definitions: Vec<String> = columns
+        .iter()
+        .map(|column| {
+            let sql_type = match column.kind {
+                ColumnKind::Text | ColumnKind::Date => "TEXT",
+                ColumnKind::Money => "REAL",
+                ColumnKind::Integer | ColumnKind::Bool => "INTEGER",
+            };
+            format!("{} {sql_type}", column.name)
+        })
+        .collect();
+
+    connection.execute(
+        &format!(
+            "CREATE TABLE {QUERY_TABLE_NAME} ({})",
+            definitions.join(", ")
+        ),
+        [],
+    )?;
+
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let transaction = connection.transaction()?;
+    {
+        let mut insert = transaction.prepare(&format!(
+            "INSERT INTO {QUERY_TABLE_NAME} VALUES ({placeholders})"
+        ))?;
+
+        for perdcomp in perdcomps {
+            insert.execute(params_from_iter(perdcomp.cells().iter().map(cell_to_sql)))?;
+        }
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
+
+fn cell_to_sql(cell: &Cell) -> Value {
+    match cell {
+        Cell::Null => Value::Null,
+        Cell::Text(text) => Value::Text(text.clone()),
+        Cell::Money(value) => Value::Real(*value),
+        Cell::Integer(value) => Value::Integer(*value),
+        Cell::Date(date) => Value::Text(date.format("%Y-%m-%d").to_string()),
+        Cell::Bool(value) => Value::Integer(i64::from(*value)),
+    }
+}
+
+fn cell_from_sql(value: ValueRef) -> Cell {
+    match value {
+        ValueRef::Null => Cell::Null,
+        ValueRef::Integer(value) => Cell::Integer(value),
+        ValueRef::Real(value) => Cell::Money(value),
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => {
+            Cell::Text(String::from_utf8_lossy(bytes).into_owned())
+        }
+    }
+}
+
+impl QueryResult {
+    /// Prints the result as an aligned text table.
+    pub fn print_table(&self) {
+        let texts: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(Cell::to_text).collect())
+            .collect();
+
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                texts
+                    .iter()
+                    .map(|row| row[index].chars().count())
+                    .chain([column.chars().count()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let format_line = |values: Vec<&str>| -> String {
+            values
+                .iter()
+                .zip(&widths)
+                .map(|(value, width)| format!("{value:<width$}"))
+                .collect::<Vec<String>>()
+                .join(" | ")
+        };
+
+        println!(
+            "{}",
+            format_line(self.columns.iter().map(String::as_str).collect())
+        );
+        println!(
+            "{}",
+            widths
+                .iter()
+                .map(|width| "-".repeat(*width))
+                .collect::<Vec<String>>()
+                .join("-+-")
+        );
+        for row in &texts {
+            println!("{}", format_line(row.iter().map(String::as_str).collect()));
+        }
+        println!("\n({} rows)", self.rows.len());
+    }
+
+    /// Writes the result to an `.xlsx` or `.csv` file, chosen by extension.
+    pub fn write(&self, output: &Path, delimiter: u8) -> MyResult<()> {
+        let extension = output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase);
+
+        match extension.as_deref() {
+            Some("xlsx") => {
+                let mut workbook = new_workbook()?;
+                push_cells_worksheet(&mut workbook, "Consulta", &self.columns, &self.rows)?;
+                save_workbook(&mut workbook, output)?;
+            }
+            Some("csv") => {
+                info!("Write CSV file: {output:?}");
+                let mut writer = csv::WriterBuilder::new()
+                    .delimiter(delimiter)
+                    .from_path(output)
+                    .with_path(output)?;
+                writer.write_record(&self.columns)?;
+                for row in &self.rows {
+                    writer.write_record(row.iter().map(Cell::to_text))?;
+                }
+                writer.flush()?;
+            }
+            _ => {
+                return Err(format!(
+                    "Saída de consulta não suportada {output:?}: use .xlsx ou .csv"
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests_query {
+    use super::*;
+
+    #[test]
+    fn aggregates_with_sql() -> MyResult<()> {
+        let perdcomps = vec![
+            PerDcomp::sample("1", 10.0)
+                .with_cnpj("A")
+                .with_pendente(true),
+            PerDcomp::sample("1", 5.5)
+                .with_cnpj("A")
+                .with_pendente(true),
+            PerDcomp::sample("1", 1.0)
+                .with_cnpj("B")
+                .with_pendente(false),
+        ];
+
+        let result = run_query(
+            &perdcomps,
+            "SELECT cnpj_declarante, COUNT(*) AS n, SUM(valor_do_per) AS total \
+             FROM perdcomp WHERE pendente_atuacao = 1 AND ano = 2021 \
+             GROUP BY cnpj_declarante",
+        )?;
+
+        assert_eq!(result.columns, vec!["cnpj_declarante", "n", "total"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Cell::Text("A".to_string()),
+                Cell::Integer(2),
+                Cell::Money(15.5)
+            ]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_sql() {
+        assert!(run_query(&[], "SELEC nothing").is_err());
+    }
+}
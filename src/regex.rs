@@ -26,3 +26,24 @@ pub static REGEX_DDMMYYYY: LazyLock<Regex> =
 // Regex para capturar o primeiro ano de 4 dígitos que encontrar (fallback)
 pub static REGEX_ANO_GENERICO: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"\b(\d{4})\b").unwrap());
+
+// Número do trimestre: "3º TRIMESTRE" | "3o Trimestre" | "3 TRIMESTRE"
+pub static REGEX_NUMERO_TRIMESTRE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^\s*([1-4])\s*[ºo°]?\s*TRIMESTRE").unwrap());
+
+// Mês e ano: "07/2021" | "7-2021"
+pub static REGEX_MES_ANO: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(\d{1,2})[-/](\d{4})\s*$").unwrap());
+
+// Ano e mês: "2021-07" | "2021/7"
+pub static REGEX_ANO_MES: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(\d{4})[-/](\d{1,2})\s*$").unwrap());
+
+// Inteiro sem zeros à esquerda, até 15 dígitos: "0" | "-42" | "123456"
+// (códigos como "00123" e CNPJs com 14 dígitos e zero inicial continuam texto)
+pub static REGEX_INTEIRO: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^-?(0|[1-9]\d{0,14})$").unwrap());
+
+// Número pt-BR: "1.234,56" | "-0,5" | "1.234" | "12"
+pub static REGEX_NUMERO_PT_BR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^-?(\d{1,3}(\.\d{3})+|0|[1-9]\d*)(,\d+)?$").unwrap());
@@ -0,0 +1,261 @@
+//! Constant memory workbook writer.
+//!
+//! [`StreamingWriter`] writes each record to a constant memory worksheet as
+//! soon as it is parsed, so peak memory does not grow with the dataset. Only
+//! the first [`STREAM_SAMPLE_SIZE`] records are buffered, to measure the
+//! column widths; preset widths from [`ColumnWidths`] override the sample.
+
+use log::debug;
+use rust_xlsxwriter::{Table, TableColumn, Workbook, XlsxError, XlsxSerialize};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::{
+    Arguments, ColumnWidths, CreditCatalog, MyResult, PERDCOMP_SHEET_NAME, Paths, PerDcomp,
+    Provenance, SheetOptions, collect_column_stats,
+    excel::{MAX_NUMBER_OF_ROWS, format_sheet_name, prepare_worksheet},
+    new_workbook, perdcomp_records, push_metadata_worksheet, save_workbook,
+};
+
+/// Number of records buffered to measure the column widths.
+pub const STREAM_SAMPLE_SIZE: usize = 10_000;
+
+/// Writes records one by one to constant memory worksheets.
+///
+/// Rows are written in arrival order. A new sheet (`"PERDComp 2"`, ...) starts
+/// every [`MAX_NUMBER_OF_ROWS`] records.
+pub struct StreamingWriter<'a, T> {
+    workbook: Workbook,
+    sheet_name: String,
+    hide_cols: &'a [u16],
+    options: SheetOptions<'a>,
+    column_widths: ColumnWidths,
+    sample: Vec<T>,
+    col_widths: Option<Vec<f64>>,
+    headers: Vec<String>,
+    table: Option<Table>,
+    sheets: usize,
+    rows_in_sheet: usize,
+    rows: usize,
+}
+
+impl<'a, T> StreamingWriter<'a, T>
+where
+    T: Serialize + XlsxSerialize + Sync,
+{
+    /// Creates the workbook. `options.stats` is ignored: widths come from the sample.
+    pub fn new(
+        sheet_name: &str,
+        hide_cols: &'a [u16],
+        options: SheetOptions<'a>,
+    ) -> Result<Self, XlsxError> {
+        Ok(Self {
+            workbook: new_workbook()?,
+            sheet_name: sheet_name.to_string(),
+            hide_cols,
+            column_widths: options.column_widths.cloned().unwrap_or_default(),
+            options,
+            sample: Vec::new(),
+            col_widths: None,
+            headers: Vec::new(),
+            table: None,
+            sheets: 0,
+            rows_in_sheet: 0,
+            rows: 0,
+        })
+    }
+
+    /// Writes one record, or buffers it while the width sample is incomplete.
+    pub fn write(&mut self, record: T) -> Result<(), XlsxError> {
+        if self.col_widths.is_none() {
+            self.sample.push(record);
+            if self.sample.len() >= STREAM_SAMPLE_SIZE {
+                self.flush_sample()?;
+            }
+            return Ok(());
+        }
+
+        self.write_row(&record)
+    }
+
+    /// Finishes the last sheet and saves the workbook. Returns the number of rows written.
+    pub fn save<P>(self, output: P) -> MyResult<usize>
+    where
+        P: AsRef<Path>,
+    {
+        self.save_with(output, |_, _| Ok(()))
+    }
+
+    /// Like [`save`](Self::save), but lets `append` add worksheets after the
+    /// streamed ones. It receives the workbook and the number of rows written.
+    pub fn save_with<P, F>(mut self, output: P, append: F) -> MyResult<usize>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&mut Workbook, usize) -> Result<(), XlsxError>,
+    {
+        self.flush_sample()?;
+        self.finish_sheet()?;
+        if self.rows > 0 {
+            append(&mut self.workbook, self.rows)?;
+        }
+        save_workbook(&mut self.workbook, output)?;
+        Ok(self.rows)
+    }
+
+    /// Measures the sample, then writes its records.
+    fn flush_sample(&mut self) -> Result<(), XlsxError> {
+        if self.col_widths.is_some() {
+            return Ok(());
+        }
+
+        let mut stats =
+            collect_column_stats(&self.sample, &self.column_widths, self.options.columns);
+        if let Some(columns) = self.options.columns {
+            stats = columns.select(&stats);
+        }
+        self.col_widths = Some(stats.column_widths(&self.column_widths));
+        self.headers = stats.columns.into_iter().map(|c| c.header).collect();
+
+        // Constant memory rows are flushed before the table is stored, so the
+        // table cannot read its column names from the header cells.
+        self.table = Some(match self.options.table {
+            Some(table) => table.clone(),
+            None => {
+                let columns: Vec<TableColumn> = self
+                    .headers
+                    .iter()
+                    .map(|header| TableColumn::new().set_header(header))
+                    .collect();
+                Table::new().set_columns(&columns)
+            }
+        });
+
+        for record in std::mem::take(&mut self.sample) {
+            self.write_row(&record)?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, record: &T) -> Result<(), XlsxError> {
+        if self.sheets == 0 || self.rows_in_sheet == MAX_NUMBER_OF_ROWS {
+            self.start_sheet(record)?;
+        }
+
+        let worksheet = self.workbook.worksheet_from_index(self.sheets - 1)?;
+        worksheet.serialize(record)?;
+
+        self.rows_in_sheet += 1;
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn start_sheet(&mut self, first: &T) -> Result<(), XlsxError> {
+        self.finish_sheet()?;
+        self.sheets += 1;
+
+        let sheet_name = format_sheet_name(&self.sheet_name, self.sheets);
+        debug!("Streaming rows to worksheet '{sheet_name}' (constant memory).");
+
+        let worksheet = self.workbook.add_worksheet_with_constant_memory();
+        prepare_worksheet(
+            worksheet,
+            &sheet_name,
+            self.hide_cols,
+            self.col_widths.as_deref().unwrap_or_default(),
+            self.table.as_ref(),
+            self.options.columns,
+            Some(first),
+        )?;
+
+        self.rows_in_sheet = 0;
+        Ok(())
+    }
+
+    /// Adds the conditional formats and dropdowns, which need the final row count.
+    fn finish_sheet(&mut self) -> Result<(), XlsxError> {
+        if self.sheets == 0 {
+            return Ok(());
+        }
+
+        let worksheet = self.workbook.worksheet_from_index(self.sheets - 1)?;
+        let last_row = self.rows_in_sheet as u32;
+        if let Some(format_rules) = self.options.format_rules {
+            format_rules.apply(worksheet, &self.headers, last_row)?;
+        }
+        if let Some(annotations) = self.options.annotations {
+            annotations.add_validations(worksheet, &self.headers, last_row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the converted CSV files and streams their records to an `.xlsx` file.
+///
+/// Each record gets its year, canonical credit code, origin and the annotations
+/// of `options.annotations`, as in the in-memory pipeline, but records are not
+/// sorted: the files are written one after the other, in file order. With
+/// `provenance`, its row counts are filled in and the "Metadados" worksheet is
+/// added. Returns the number of rows written.
+pub fn stream_csv_to_xlsx<Q>(
+    args: &Arguments,
+    inputs: &[Paths],
+    catalog: &CreditCatalog,
+    options: SheetOptions,
+    provenance: Option<&mut Provenance>,
+    output: Q,
+) -> MyResult<usize>
+where
+    Q: AsRef<Path>,
+{
+    let mut writer: StreamingWriter<PerDcomp> =
+        StreamingWriter::new(PERDCOMP_SHEET_NAME, &[], options)?;
+    let mut rows_read = 0;
+
+    for paths in inputs {
+        for result in perdcomp_records(args, paths)? {
+            let mut perdcomp = result?;
+            rows_read += 1;
+            catalog.apply(std::slice::from_mut(&mut perdcomp));
+            if let Some(annotations) = options.annotations {
+                annotations.apply(std::slice::from_mut(&mut perdcomp));
+            }
+            writer.write(perdcomp)?;
+        }
+    }
+
+    let rows = match provenance {
+        Some(provenance) => writer.save_with(output, |workbook, rows| {
+            provenance.rows_read = rows_read;
+            provenance.rows_written = rows;
+            push_metadata_worksheet(workbook, provenance)
+        })?,
+        None => writer.save(output)?,
+    };
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests_streaming {
+    use super::*;
+
+    #[test]
+    fn writes_rows_past_the_width_sample() -> MyResult<()> {
+        let mut writer: StreamingWriter<PerDcomp> =
+            StreamingWriter::new("PERDComp", &[], SheetOptions::default())?;
+
+        for index in 0..STREAM_SAMPLE_SIZE + 5 {
+            writer.write(PerDcomp {
+                per_dcomp: Some(index.to_string()),
+                ..Default::default()
+            })?;
+        }
+        assert!(writer.col_widths.is_some());
+        assert!(writer.sample.is_empty());
+
+        let output = tempfile::Builder::new().suffix(".xlsx").tempfile().unwrap();
+        let rows = writer.save(output.path())?;
+
+        assert_eq!(rows, STREAM_SAMPLE_SIZE + 5);
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
-use crate::{REGEX_ANO_GENERICO, REGEX_DDMMYYYY, REGEX_TRIMESTRE_ANO};
+use crate::{
+    Column, ColumnKind, ColumnWidths, REGEX_ANO_GENERICO, REGEX_ANO_MES, REGEX_DDMMYYYY,
+    REGEX_MES_ANO, REGEX_NUMERO_TRIMESTRE, REGEX_TRIMESTRE_ANO, SheetColumns, collect_column_stats,
+};
 
 use chrono::NaiveDate;
-use rust_xlsxwriter::{Format, FormatAlign, XlsxSerialize, serialize_option_datetime_to_excel};
+use rust_xlsxwriter::{
+    Format, FormatAlign, SerializeFieldOptions, XlsxSerialize, serialize_option_datetime_to_excel,
+};
 use serde::{Deserialize, Deserializer, Serialize, de::Error};
-use serde_json::Value;
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 
 /// The base font size for all standard data cells in the workbook.
 pub const FONT_SIZE: f64 = 14.0;
@@ -30,6 +34,14 @@ pub struct FormatRegistry {
     pub bold_currency: Format,
     /// Date layout configured to standard Portuguese locale formats (dd/mm/yyyy).
     pub date: Format,
+    /// Center-aligned boolean cells rendered as Excel checkboxes.
+    pub checkbox: Format,
+    /// Conditional highlight for unfavourable values (red).
+    pub alert: Format,
+    /// Conditional highlight for favourable values (green).
+    pub success: Format,
+    /// Conditional highlight for values that need attention (bold, yellow).
+    pub warning: Format,
 }
 
 impl FormatRegistry {
@@ -74,6 +86,44 @@ impl FormatRegistry {
                 .set_font_name("Calibri")
                 .set_font_size(FONT_SIZE)
                 .set_num_format("dd/mm/yyyy"),
+            checkbox: Format::new()
+                .set_align(FormatAlign::Center)
+                .set_align(FormatAlign::VerticalCenter)
+                .set_font_name("Calibri")
+                .set_font_size(FONT_SIZE)
+                .set_checkbox(),
+            alert: Format::new()
+                .set_background_color("#FFC7CE")
+                .set_font_color("#9C0006"),
+            success: Format::new()
+                .set_background_color("#C6EFCE")
+                .set_font_color("#006100"),
+            warning: Format::new()
+                .set_background_color("#FFEB9C")
+                .set_font_color("#9C5700")
+                .set_bold(),
+        }
+    }
+
+    /// Whether `format` is one of the bold value formats.
+    pub fn is_bold(&self, format: &Format) -> bool {
+        [&self.bold_centered, &self.bold_currency].contains(&format)
+    }
+
+    /// Looks up a format by its field name (e.g. `"alert"`, `"bold_currency"`).
+    pub fn get(&self, name: &str) -> Option<&Format> {
+        match name {
+            "header" => Some(&self.header),
+            "centered" => Some(&self.centered),
+            "bold_centered" => Some(&self.bold_centered),
+            "currency" => Some(&self.currency),
+            "bold_currency" => Some(&self.bold_currency),
+            "date" => Some(&self.date),
+            "checkbox" => Some(&self.checkbox),
+            "alert" => Some(&self.alert),
+            "success" => Some(&self.success),
+            "warning" => Some(&self.warning),
+            _ => None,
         }
     }
 }
@@ -84,32 +134,31 @@ impl FormatRegistry {
 /// efficiency when processing large chunks of records.
 pub static FORMAT: LazyLock<FormatRegistry> = LazyLock::new(FormatRegistry::new);
 
-#[derive(Debug, Default, Serialize, Deserialize, XlsxSerialize)]
-#[xlsx(table = Table::new())]
-#[xlsx(header_format = FORMAT.header.clone())]
+/// One PER/DCOMP document.
+///
+/// Its workbook columns and value formats are those of [`PerDcomp::sheet_columns`].
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct PerDcomp {
     #[serde(rename = "PER/DCOMP")]
-    #[xlsx(value_format = FORMAT.bold_centered.clone())]
-    //#[xlsx(column_width = 30.0)]
     pub per_dcomp: Option<String>,
 
     #[serde(rename = "CNPJ/CPF Declarante/Sucessora")]
-    #[xlsx(value_format = FORMAT.centered.clone())]
-    //#[xlsx(column_width = 20.0)]
     pub cnpj_declarante: Option<String>,
 
     #[serde(rename = "Tipo de Crédito", alias = "Tipo Crédito")]
-    //#[xlsx(column_width = 20.0)]
     pub tipo_do_credito: Option<String>,
 
+    /// Canonical code of `tipo_do_credito`, filled by `CreditCatalog::apply`.
+    #[serde(default)]
+    #[serde(rename = "Código do Tipo de Crédito")]
+    pub codigo_do_credito: Option<String>,
+
     #[serde(
         rename = "Valor Total do Crédito",
         alias = "Valor Total Crédito",
         deserialize_with = "string_as_f64"
     )]
-    #[xlsx(value_format = FORMAT.currency.clone())]
-    //#[xlsx(column_width = 18.0)]
     pub valor_total_do_credito: f64,
 
     #[serde(
@@ -117,8 +166,6 @@ pub struct PerDcomp {
         alias = "Valor Crédito Data Transmissão",
         deserialize_with = "string_as_f64"
     )]
-    #[xlsx(value_format = FORMAT.currency.clone())]
-    //#[xlsx(column_width = 18.0)]
     pub valor_do_credito_na_data_de_transmissao: f64,
 
     #[serde(
@@ -127,10 +174,16 @@ pub struct PerDcomp {
         alias = "Vl. Crédito Utilizado/Vl. PER",
         deserialize_with = "string_as_f64"
     )]
-    #[xlsx(value_format = FORMAT.bold_currency.clone())]
-    //#[xlsx(column_width = 18.0)]
     pub valor_do_per: f64,
 
+    /// Sum of the debts compensated by this DCOMP, filled by `link_debitos`.
+    #[serde(default)]
+    #[serde(
+        rename = "Total de Débitos Compensados",
+        deserialize_with = "string_as_option_f64"
+    )]
+    pub total_debitos: Option<f64>,
+
     #[serde(default)]
     #[serde(
         rename = "Data da Transmissão",
@@ -138,30 +191,27 @@ pub struct PerDcomp {
         deserialize_with = "string_as_date",
         serialize_with = "serialize_option_datetime_to_excel"
     )]
-    #[xlsx(value_format = FORMAT.date.clone())]
-    //#[xlsx(column_width = 18.0)]
     pub data_da_transmissao: Option<NaiveDate>,
 
-    #[serde(rename = "Demonstra Crédito")]
-    #[xlsx(value_format = FORMAT.centered.clone())]
-    //#[xlsx(column_width = 12.0)]
-    pub demonstra_credito: Option<String>,
+    #[serde(default)]
+    #[serde(rename = "Demonstra Crédito", deserialize_with = "string_as_bool")]
+    pub demonstra_credito: Option<bool>,
 
-    #[serde(rename = "Pendente de Atuação", alias = "Pendente Atuação")]
-    #[xlsx(value_format = FORMAT.centered.clone())]
-    //#[xlsx(column_width = 12.0)]
-    pub pendente_atuacao: Option<String>,
+    #[serde(default)]
+    #[serde(
+        rename = "Pendente de Atuação",
+        alias = "Pendente Atuação",
+        deserialize_with = "string_as_bool"
+    )]
+    pub pendente_atuacao: Option<bool>,
 
     #[serde(rename = "Tipo de Documento", alias = "Tipo Documento")]
-    //#[xlsx(column_width = 22.0)]
-    pub tipo_do_documento: Option<String>,
+    pub tipo_do_documento: Option<TipoDocumento>,
 
     #[serde(rename = "Nome Empresarial/Nome")]
-    //#[xlsx(column_width = 30.0)]
     pub nome_empresarial: Option<String>,
 
     #[serde(rename = "UA Declarante/Sucessora")]
-    //#[xlsx(column_width = 20.0)]
     pub ua_declarante: Option<String>,
 
     #[serde(
@@ -169,21 +219,15 @@ pub struct PerDcomp {
         alias = "Detentor Crédito",
         alias = "UA Detentor Crédito"
     )]
-    #[xlsx(value_format = FORMAT.centered.clone())]
-    //#[xlsx(column_width = 20.0)]
     pub cnpj_detentor_do_credito: Option<String>,
 
     #[serde(
         rename = "Período de Apuração do Crédito",
         alias = "Período Apuração Crédito"
     )]
-    #[xlsx(value_format = FORMAT.centered.clone())]
-    //#[xlsx(column_width = 14.0)]
     pub trimestre_de_apuracao: Option<String>,
 
     #[serde(rename = "Ano")]
-    #[xlsx(value_format = FORMAT.centered.clone())]
-    //#[xlsx(column_width = 8.0)]
     pub ano: Option<u32>,
 
     #[serde(default)]
@@ -193,8 +237,6 @@ pub struct PerDcomp {
         deserialize_with = "string_as_date",
         serialize_with = "serialize_option_datetime_to_excel"
     )]
-    #[xlsx(value_format = FORMAT.date.clone())]
-    //#[xlsx(column_width = 18.0)]
     pub pa_pagamento: Option<NaiveDate>,
 
     #[serde(default)]
@@ -203,42 +245,53 @@ pub struct PerDcomp {
         deserialize_with = "string_as_date",
         serialize_with = "serialize_option_datetime_to_excel"
     )]
-    #[xlsx(value_format = FORMAT.date.clone())]
-    //#[xlsx(column_width = 14.0)]
     pub data_dcomp_ativa: Option<NaiveDate>,
 
-    #[serde(rename = "PER/DCOMP Ativo com Demonstrativo de Crédito")]
-    #[xlsx(value_format = FORMAT.centered.clone())]
-    //#[xlsx(column_width = 30.0)]
-    pub per_ativo_com_credito: Option<String>,
+    #[serde(default)]
+    #[serde(
+        rename = "PER/DCOMP Ativo com Demonstrativo de Crédito",
+        deserialize_with = "string_as_bool"
+    )]
+    pub per_ativo_com_credito: Option<bool>,
 
     #[serde(
         rename = "Processo Atribuído ao PER/DCOMP",
         alias = "Processo Atribuído PER/DCOMP"
     )]
-    #[xlsx(value_format = FORMAT.centered.clone())]
-    //#[xlsx(column_width = 22.0)]
     pub num_processo_atribuido_ao_perdcomp: Option<String>,
 
     #[serde(rename = "Processo Administrativo Anterior")]
-    //#[xlsx(column_width = 22.0)]
     pub num_processo_administrativo_anterior: Option<String>,
 
     #[serde(rename = "Processo Judicial")]
-    //#[xlsx(column_width = 22.0)]
     pub processo_judicial: Option<String>,
 
     #[serde(rename = "Origem Discussão Judicial")]
-    //#[xlsx(column_width = 20.0)]
     pub origem_judicial: Option<String>,
 
     #[serde(rename = "Situação")]
-    //#[xlsx(column_width = 20.0)]
-    pub situacao: Option<String>,
+    pub situacao: Option<Situacao>,
 
     #[serde(rename = "Motivo")]
-    //#[xlsx(column_width = 40.0)]
     pub motivo: Option<String>,
+
+    /// Source file and line of the record, filled by `read_csv`.
+    #[serde(skip_deserializing)]
+    #[serde(rename = "Origem")]
+    pub origem: Option<Origem>,
+
+    /// Auditor annotations, merged back by `Annotations::apply`.
+    #[serde(default)]
+    #[serde(rename = "Análise")]
+    pub analise: Option<String>,
+
+    #[serde(default)]
+    #[serde(rename = "Responsável")]
+    pub responsavel: Option<String>,
+
+    #[serde(default)]
+    #[serde(rename = "Status interno")]
+    pub status_interno: Option<String>,
 }
 
 impl PerDcomp {
@@ -308,59 +361,176 @@ impl PerDcomp {
         }
     }
 
+    /// Returns the credit period as `(year, quarter)`.
+    ///
+    /// Expects `get_year` to have split "3º TRIMESTRE 2021" into
+    /// `trimestre_de_apuracao` and `ano`. Other period layouts, such as a
+    /// date or "07/2021", are handled by [`parse_quarter`].
+    pub fn quarter(&self) -> Option<(u32, u32)> {
+        let trimestre = self.trimestre_de_apuracao.as_deref()?;
+
+        if let (Some(captures), Some(ano)) = (REGEX_NUMERO_TRIMESTRE.captures(trimestre), self.ano)
+        {
+            let quarter: u32 = captures.get(1)?.as_str().parse().ok()?;
+            return Some((ano, quarter));
+        }
+
+        parse_quarter(trimestre)
+    }
+
+    /// Headers of the columns written in bold: the document number and the PER value.
+    pub const BOLD_HEADERS: &'static [&'static str] =
+        &["PER/DCOMP", "Valor Total do Pedido de Resssarcimento (PER)"];
+
+    /// The workbook columns: all of them with `origin`, otherwise all but "Origem".
+    pub fn sheet_columns(origin: bool) -> SheetColumns {
+        let fields: Vec<(&str, Option<Format>)> = PerDcomp::exported_columns(origin)
+            .iter()
+            .map(|column| (column.header, value_format(column)))
+            .collect();
+        SheetColumns::new("PerDcomp", &FORMAT.header, &fields)
+    }
+
     /// Detecta colunas vazias automaticamente sem listar os campos.
+    ///
+    /// Uma coluna é vazia quando todos os valores são nulos ou texto em branco;
+    /// números e booleanos contam como conteúdo, mesmo que sejam 0 ou false.
     pub fn get_empty_column_indices(data: &[PerDcomp]) -> Vec<u16> {
-        if data.is_empty() {
-            return Vec::new();
+        collect_column_stats(data, &ColumnWidths::default(), None).empty_column_indices()
+    }
+}
+
+/// Builder of the records used by the tests.
+#[cfg(test)]
+impl PerDcomp {
+    /// A COFINS export credit of the 1st quarter of 2021, declared by
+    /// "12.345.678/0001-90", with this number and `valor_do_per`.
+    pub(crate) fn sample(number: &str, valor_do_per: f64) -> Self {
+        Self {
+            per_dcomp: Some(number.to_string()),
+            cnpj_declarante: Some("12.345.678/0001-90".to_string()),
+            tipo_do_credito: Some("Cofins Não-Cumulativa - Exportação".to_string()),
+            codigo_do_credito: Some("COFINS_NC_EXP".to_string()),
+            trimestre_de_apuracao: Some("1º TRIMESTRE".to_string()),
+            ano: Some(2021),
+            valor_do_per,
+            ..Default::default()
         }
+    }
 
-        // 1. Descobrimos o número de colunas serializando o primeiro item
-        // O preserve_order garante que a ordem segue a definição da struct
-        let first_row_value = serde_json::to_value(&data[0]).unwrap_or(Value::Null);
-        let column_count = match &first_row_value {
-            Value::Object(map) => map.len(),
-            _ => return Vec::new(),
-        };
+    pub(crate) fn with_cnpj(mut self, cnpj: &str) -> Self {
+        self.cnpj_declarante = Some(cnpj.to_string());
+        self
+    }
 
-        let mut columns_empty: Vec<bool> = vec![true; column_count];
+    pub(crate) fn with_credito(mut self, valor_total_do_credito: f64) -> Self {
+        self.valor_total_do_credito = valor_total_do_credito;
+        self
+    }
 
-        // 2. Iteramos pelos dados
-        for row in data {
-            // Se todas as colunas já foram marcadas como "com conteúdo", paramos (Early Exit)
-            if columns_empty.iter().all(|&empty| !empty) {
-                break;
-            }
+    pub(crate) fn with_period(mut self, trimestre: &str, ano: u32) -> Self {
+        self.trimestre_de_apuracao = Some(trimestre.to_string());
+        self.ano = Some(ano);
+        self
+    }
 
-            if let Ok(Value::Object(map)) = serde_json::to_value(row) {
-                for (i, (_field_name, value)) in map.iter().enumerate() {
-                    if columns_empty[i] && !is_empty_value(value) {
-                        columns_empty[i] = false;
-                    }
-                }
-            }
-        }
+    pub(crate) fn with_transmission(mut self, year: i32, month: u32, day: u32) -> Self {
+        self.data_da_transmissao = NaiveDate::from_ymd_opt(year, month, day);
+        self
+    }
 
-        // 3. Coletamos os índices das que permaneceram vazias
-        columns_empty
-            .into_iter()
-            .enumerate()
-            .filter(|&(_, empty)| empty)
-            .map(|(idx, _)| idx as u16)
-            .collect()
+    pub(crate) fn with_pendente(mut self, pendente: bool) -> Self {
+        self.pendente_atuacao = Some(pendente);
+        self
     }
 }
 
-/// Define o que é considerado "vazio" para fins de ocultação de coluna
-fn is_empty_value(v: &Value) -> bool {
-    match v {
-        Value::Null => true,
-        Value::String(s) => s.trim().is_empty(),
-        // Para números (f64, u32) ou booleanos, consideramos que NÃO estão vazios
-        // mesmo que sejam 0 ou false, pois são valores válidos em Excel.
-        _ => false,
+impl XlsxSerialize for PerDcomp {
+    fn to_serialize_field_options() -> SerializeFieldOptions {
+        PerDcomp::sheet_columns(true).field_options().clone()
     }
 }
 
+/// Value format of a PerDcomp workbook column, by kind; codes and periods are
+/// centered, and the columns of [`PerDcomp::BOLD_HEADERS`] are bold.
+fn value_format(column: &Column) -> Option<Format> {
+    let bold = PerDcomp::BOLD_HEADERS.contains(&column.header);
+    let format = match (column.name, column.kind) {
+        (_, ColumnKind::Money) if bold => &FORMAT.bold_currency,
+        _ if bold => &FORMAT.bold_centered,
+        (_, ColumnKind::Money) => &FORMAT.currency,
+        (_, ColumnKind::Date) => &FORMAT.date,
+        (_, ColumnKind::Bool) => &FORMAT.checkbox,
+        (
+            "cnpj_declarante"
+            | "codigo_do_credito"
+            | "cnpj_detentor_do_credito"
+            | "trimestre_de_apuracao"
+            | "ano"
+            | "num_processo_atribuido_ao_perdcomp"
+            | "responsavel"
+            | "status_interno",
+            _,
+        ) => &FORMAT.centered,
+        _ => return None,
+    };
+    Some(format.clone())
+}
+
+/**
+Parses a period string into `(year, quarter)`.
+
+Accepts "3º TRIMESTRE 2021", "07/2021", "2021-07" and "18/10/2013".
+
+```
+use perdcomp_csv_to_xlsx::parse_quarter;
+
+assert_eq!(parse_quarter("3º TRIMESTRE de 2021"), Some((2021, 3)));
+assert_eq!(parse_quarter("07/2021"), Some((2021, 3)));
+assert_eq!(parse_quarter("2021-11"), Some((2021, 4)));
+assert_eq!(parse_quarter("18/02/2013"), Some((2013, 1)));
+assert_eq!(parse_quarter("Exercício 2014"), None);
+```
+*/
+pub fn parse_quarter(text: &str) -> Option<(u32, u32)> {
+    let month_to_quarter = |month: u32| (1..=12).contains(&month).then(|| (month - 1) / 3 + 1);
+    let number = |captures: &regex::Captures, i: usize| -> Option<u32> {
+        captures.get(i)?.as_str().parse().ok()
+    };
+
+    if let Some(captures) = REGEX_TRIMESTRE_ANO.captures(text.trim()) {
+        let quarter = REGEX_NUMERO_TRIMESTRE
+            .captures(captures.get(1)?.as_str())
+            .and_then(|c| number(&c, 1));
+        if let (Some(quarter), Some(year)) = (quarter, number(&captures, 2)) {
+            return Some((year, quarter));
+        }
+    }
+
+    if let Some(captures) = REGEX_MES_ANO.captures(text) {
+        return Some((
+            number(&captures, 2)?,
+            month_to_quarter(number(&captures, 1)?)?,
+        ));
+    }
+
+    if let Some(captures) = REGEX_ANO_MES.captures(text) {
+        return Some((
+            number(&captures, 1)?,
+            month_to_quarter(number(&captures, 2)?)?,
+        ));
+    }
+
+    if let Some(captures) = REGEX_DDMMYYYY.captures(text) {
+        return Some((
+            number(&captures, 3)?,
+            month_to_quarter(number(&captures, 2)?)?,
+        ));
+    }
+
+    None
+}
+
 /// Deserializes a string into an `f64`.
 ///
 /// This function attempts to parse a string into an `f64`, handling common
@@ -391,28 +561,72 @@ where
             .map_err(|e| {
                 // Capture the parse error 'e'
                 // Include the original error and the string in the error message.
-                Error::custom(format!(
-                    "\nfn string_as_f64()\nFailed to parse f64 from string \"{string}\": {e}"
-                ))
+                Error::custom(format!("número inválido \"{string}\": {e}"))
             })
     })
 }
 
+/// Deserializes an `Option<f64>` with the number format of [`string_as_f64`].
+///
+/// Empty strings become `None`.
+pub fn string_as_option_f64<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .filter(|string| !string.trim().is_empty())
+        .map(|string| {
+            string_as_f64(serde::de::value::StringDeserializer::<D::Error>::new(
+                string,
+            ))
+        })
+        .transpose()
+}
+
 // Define the expected date formats.
 // Using a constant improves readability and maintainability.
 // %-d and %-m remove leading zeros, so they accept single-digit days and months.
 const FORMAT_1: &str = "%-d/%-m/%Y"; // 17-2-2014
 const FORMAT_2: &str = "%Y/%-m/%-d"; // 2023-04-20
 
+/**
+Parses a date, ignoring any time part.
+
+Accepts day first (`17/2/2014`) or year first (`2023-04-20`), with hyphens
+or slashes; a time separated by whitespace or `T` is dropped.
+
+```
+use chrono::NaiveDate;
+use perdcomp_csv_to_xlsx::parse_date;
+
+assert_eq!(parse_date("17-2-2014 16:32:52.34"), NaiveDate::from_ymd_opt(2014, 2, 17));
+assert_eq!(parse_date("2023-04-20T10:00:00"), NaiveDate::from_ymd_opt(2023, 4, 20));
+assert_eq!(parse_date("20/13/2023"), None);
+```
+*/
+pub fn parse_date(text: &str) -> Option<NaiveDate> {
+    // Preprocess the string to handle different separators.
+    // Replace hyphens with slashes for consistent parsing.
+    // This handles cases like "17-2-2014 16:32:52.34" and "17/02/2014T16:32:52.34".
+    let normalized_string = text.trim().replace('-', "/");
+
+    // Split on whitespace or 'T' characters to isolate the date.
+    let date_str = normalized_string
+        .split(|c: char| c.is_ascii_whitespace() || c == 'T')
+        .next() // Take only the first part (the date)
+        .unwrap_or(text);
+
+    [FORMAT_1, FORMAT_2]
+        .iter()
+        .find_map(|fmt| NaiveDate::parse_from_str(date_str, fmt).ok())
+}
+
 /// Deserializes an `Option<NaiveDate>` from a string.
 ///
-/// This function attempts to parse a string into a `NaiveDate`.  It handles different
-/// date separators (hyphens and slashes) and attempts to extract the date part if the string
-/// also contains time information (separated by whitespace or 'T').
+/// This function attempts to parse a string into a `NaiveDate` with [`parse_date`].
 ///
 /// Returns `Some(NaiveDate)` if parsing is successful, `None` if the input string is `None`
-/// or if parsing fails for all tried formats.  Returns a `serde::de::Error` on parse failure,
-/// providing detailed error messages.
+/// or empty.  Returns a `serde::de::Error` on parse failure, providing detailed error messages.
 pub fn string_as_date<'de, D>(deserializer: D) -> Result<Option<NaiveDate>, D::Error>
 where
     D: Deserializer<'de>,
@@ -422,25 +636,225 @@ where
     Option::<String>::deserialize(deserializer)?
         .filter(|string| !string.trim().is_empty())
         .map(|string: String| {
-            // Preprocess the string to handle different separators.
-            // Replace hyphens with slashes for consistent parsing.
-            // This handles cases like "17-2-2014 16:32:52.34" and "17/02/2014T16:32:52.34".
-            let normalized_string = string.trim().replace('-', "/");
-
-            // Split on whitespace or 'T' characters to isolate the date.
-            let date_str = normalized_string
-                .split(|c: char| c.is_ascii_whitespace() || c == 'T')
-                .next() // Take only the first part (the date)
-                .unwrap_or(&string);
-
-            [FORMAT_1, FORMAT_2]
-                .iter()
-                .find_map(|fmt| NaiveDate::parse_from_str(date_str, fmt).ok())
-                .ok_or_else(|| Error::custom(format!("Formato de data inválido: {string}")))
+            parse_date(&string).ok_or_else(|| Error::custom(format!("data inválida \"{string}\"")))
         })
         .transpose()
 }
 
+/// Deserializes an `Option<bool>` from "Sim"/"Não" columns.
+///
+/// Matching is case- and accent-insensitive and also accepts "S"/"N",
+/// "true"/"false" and "1"/"0". Empty strings and "-" become `None`. Other
+/// values, such as "Não se aplica", are an error that names the value, so the
+/// row is rejected like one with an invalid date.
+pub fn string_as_bool<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .filter(|string| !matches!(string.trim(), "" | "-"))
+        .map(|string: String| match normalize_text(&string).as_str() {
+            "sim" | "s" | "true" | "verdadeiro" | "1" => Ok(true),
+            "nao" | "n" | "false" | "falso" | "0" => Ok(false),
+            _ => Err(Error::custom(format!(
+                "valor Sim/Não inválido \"{string}\""
+            ))),
+        })
+        .transpose()
+}
+
+/**
+Normalizes free text for tolerant comparisons.
+
+Converts to lowercase, removes Portuguese accents and collapses any run of
+non-alphanumeric characters into a single space.
+
+```
+use perdcomp_csv_to_xlsx::normalize_text;
+
+assert_eq!(normalize_text("  Não-Homologada "), "nao homologada");
+assert_eq!(normalize_text("COFINS NÃO CUMULATIVA – EXPORTAÇÃO"), "cofins nao cumulativa exportacao");
+```
+*/
+pub fn normalize_text(text: &str) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ã' | 'ä' | 'ª' => 'a',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'õ' | 'ö' | 'º' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'ç' => 'c',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Situação do PER/DCOMP.
+///
+/// Parsed with [`normalize_text`], so "NÃO HOMOLOGADA" and "Nao Homologada"
+/// map to the same variant. Unknown values are kept in [`Situacao::Outro`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Situacao {
+    EmAnalise,
+    Deferido,
+    DeferidoParcialmente,
+    Indeferido,
+    Homologada,
+    HomologadaParcialmente,
+    NaoHomologada,
+    NaoDeclarada,
+    Cancelado,
+    Retificado,
+    Outro(String),
+}
+
+impl Situacao {
+    /// Canonical text written to the workbook.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Situacao::EmAnalise => "Em Análise",
+            Situacao::Deferido => "Deferido",
+            Situacao::DeferidoParcialmente => "Deferido Parcialmente",
+            Situacao::Indeferido => "Indeferido",
+            Situacao::Homologada => "Homologada",
+            Situacao::HomologadaParcialmente => "Homologada Parcialmente",
+            Situacao::NaoHomologada => "Não Homologada",
+            Situacao::NaoDeclarada => "Não Declarada",
+            Situacao::Cancelado => "Cancelado",
+            Situacao::Retificado => "Retificado",
+            Situacao::Outro(text) => text,
+        }
+    }
+}
+
+impl From<&str> for Situacao {
+    fn from(text: &str) -> Self {
+        match normalize_text(text).as_str() {
+            "em analise" => Situacao::EmAnalise,
+            "deferido" | "deferida" => Situacao::Deferido,
+            "deferido parcialmente" | "deferida parcialmente" | "parcialmente deferido" => {
+                Situacao::DeferidoParcialmente
+            }
+            "indeferido" | "indeferida" => Situacao::Indeferido,
+            "homologada" | "homologado" => Situacao::Homologada,
+            "homologada parcialmente" | "homologado parcialmente" | "parcialmente homologada" => {
+                Situacao::HomologadaParcialmente
+            }
+            "nao homologada" | "nao homologado" => Situacao::NaoHomologada,
+            "nao declarada" | "nao declarado" => Situacao::NaoDeclarada,
+            "cancelado" | "cancelada" => Situacao::Cancelado,
+            "retificado" | "retificada" => Situacao::Retificado,
+            _ => Situacao::Outro(text.trim().to_string()),
+        }
+    }
+}
+
+/// Tipo de Documento: PER (ressarcimento, restituição, reembolso) ou DCOMP.
+///
+/// Parsed with [`normalize_text`]. Unknown values are kept in [`TipoDocumento::Outro`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TipoDocumento {
+    PedidoDeRessarcimento,
+    PedidoDeRestituicao,
+    PedidoDeReembolso,
+    DeclaracaoDeCompensacao,
+    Outro(String),
+}
+
+impl TipoDocumento {
+    /// Canonical text written to the workbook.
+    pub fn as_str(&self) -> &str {
+        match self {
+            TipoDocumento::PedidoDeRessarcimento => "Pedido de Ressarcimento",
+            TipoDocumento::PedidoDeRestituicao => "Pedido de Restituição",
+            TipoDocumento::PedidoDeReembolso => "Pedido de Reembolso",
+            TipoDocumento::DeclaracaoDeCompensacao => "Declaração de Compensação",
+            TipoDocumento::Outro(text) => text,
+        }
+    }
+
+    /// `true` for PER documents (ressarcimento, restituição or reembolso).
+    pub fn is_per(&self) -> bool {
+        matches!(
+            self,
+            TipoDocumento::PedidoDeRessarcimento
+                | TipoDocumento::PedidoDeRestituicao
+                | TipoDocumento::PedidoDeReembolso
+        )
+    }
+}
+
+impl From<&str> for TipoDocumento {
+    fn from(text: &str) -> Self {
+        match normalize_text(text).as_str() {
+            "pedido de ressarcimento" | "ressarcimento" | "per ressarcimento" => {
+                TipoDocumento::PedidoDeRessarcimento
+            }
+            "pedido de restituicao" | "restituicao" | "per restituicao" => {
+                TipoDocumento::PedidoDeRestituicao
+            }
+            "pedido de reembolso" | "reembolso" => TipoDocumento::PedidoDeReembolso,
+            "declaracao de compensacao" | "compensacao" | "dcomp" => {
+                TipoDocumento::DeclaracaoDeCompensacao
+            }
+            _ => TipoDocumento::Outro(text.trim().to_string()),
+        }
+    }
+}
+
+/// Implements `Display`, `Serialize` and `Deserialize` through the canonical text.
+macro_rules! impl_text_enum {
+    ($($name:ty),+) => {$(
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer).map(|text| Self::from(text.as_str()))
+            }
+        }
+    )+};
+}
+
+impl_text_enum!(Situacao, TipoDocumento);
+
+/// Where a record came from: the input file and the 1-based line where it starts.
+///
+/// Written as `"file:line"`, e.g. `"perdcomp.csv:12"`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Origem {
+    /// Input path, as given on the command line (shared by the file's records).
+    pub file: Arc<str>,
+    pub line: u64,
+}
+
+impl std::fmt::Display for Origem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+impl Serialize for Origem {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 #[cfg(test)]
 mod tests_string_as_f64 {
     use super::*;
@@ -577,3 +991,66 @@ mod string_as_date_tests {
         assert!(result.is_err());
     }
 }
+
+#[cfg(test)]
+mod tests_typed_fields {
+    use super::*;
+    use serde_json::Value;
+
+    #[derive(Debug, Deserialize)]
+    struct TestStruct {
+        #[serde(default, deserialize_with = "string_as_bool")]
+        flag: Option<bool>,
+        situacao: Option<Situacao>,
+        tipo: Option<TipoDocumento>,
+    }
+
+    fn parse(flag: &str, situacao: &str, tipo: &str) -> TestStruct {
+        let json = format!(r#"{{"flag": "{flag}", "situacao": "{situacao}", "tipo": "{tipo}"}}"#);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_sim_nao_variants() {
+        assert_eq!(parse("Sim", "", "").flag, Some(true));
+        assert_eq!(parse(" NÃO ", "", "").flag, Some(false));
+        assert_eq!(parse("nao", "", "").flag, Some(false));
+        assert_eq!(parse("", "", "").flag, None);
+        assert_eq!(parse("-", "", "").flag, None);
+
+        let json = r#"{"flag": "Não se aplica", "situacao": "", "tipo": ""}"#;
+        let error = serde_json::from_str::<TestStruct>(json).unwrap_err();
+        assert!(error.to_string().contains("\"Não se aplica\""));
+    }
+
+    #[test]
+    fn test_situacao_is_case_and_accent_insensitive() {
+        let expected = Some(Situacao::NaoHomologada);
+        assert_eq!(parse("", "Não Homologada", "").situacao, expected);
+        assert_eq!(parse("", "NAO HOMOLOGADA", "").situacao, expected);
+        assert_eq!(parse("", "não-homologada", "").situacao, expected);
+        assert_eq!(
+            parse("", "Em Discussão Judicial", "").situacao,
+            Some(Situacao::Outro("Em Discussão Judicial".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_tipo_documento() {
+        let tipo = parse("", "", "DECLARAÇÃO DE COMPENSAÇÃO").tipo.unwrap();
+        assert_eq!(tipo, TipoDocumento::DeclaracaoDeCompensacao);
+        assert!(!tipo.is_per());
+        assert!(
+            parse("", "", "Pedido de Ressarcimento")
+                .tipo
+                .unwrap()
+                .is_per()
+        );
+    }
+
+    #[test]
+    fn test_serializes_canonical_text() {
+        let value = serde_json::to_value(Situacao::from("nao homologada")).unwrap();
+        assert_eq!(value, Value::String("Não Homologada".to_string()));
+    }
+}
@@ -0,0 +1,308 @@
+//! Watch-folder mode: converts each CSV file dropped into a folder.
+//!
+//! Downloads arrive in pieces, so a file is converted only after its size
+//! and modification time have not changed for the debounce interval.
+//! The SHA-256 digests of the converted files are kept in
+//! [`PROCESSED_FILE`], inside the watched folder, so a restart does not
+//! convert them again. The digests of the failed files are only kept until
+//! the watch stops.
+
+use log::{debug, error, info, warn};
+use notify::{RecursiveMode, Watcher};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    Arguments, Converter, InputFile, MyResult, OutputFormat, PerDcompError, WatchArgs, WithPath,
+    input_role,
+};
+
+/// Digests of the converted files, one per line, inside the watched folder.
+pub const PROCESSED_FILE: &str = ".perdcomp_processed";
+
+/// How often the pending files are checked between events.
+const TICK: Duration = Duration::from_millis(500);
+
+/// Size and modification time: a file is stable while both stay the same.
+type Stamp = (u64, Option<SystemTime>);
+
+/// Files waiting for their size to settle.
+#[derive(Debug, Default)]
+struct Pending {
+    files: BTreeMap<PathBuf, (Stamp, Instant)>,
+}
+
+impl Pending {
+    /// Records the current stamp of `path`; `None` when the file is gone.
+    fn observe(&mut self, path: &Path, stamp: Option<Stamp>, now: Instant) {
+        match stamp {
+            None => {
+                self.files.remove(path);
+            }
+            Some(stamp) => match self.files.get(path) {
+                Some((previous, _)) if *previous == stamp => {}
+                _ => {
+                    self.files.insert(path.to_path_buf(), (stamp, now));
+                }
+            },
+        }
+    }
+
+    /// Takes out the files unchanged for at least `debounce`.
+    fn ready(&mut self, debounce: Duration, now: Instant) -> Vec<PathBuf> {
+        let ready: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(_, (_, since))| now.duration_since(*since) >= debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &ready {
+            self.files.remove(path);
+        }
+        ready
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        self.files.keys().cloned().collect()
+    }
+}
+
+/// The digests of the files already converted or failed.
+#[derive(Debug)]
+struct Processed {
+    path: PathBuf,
+    digests: HashSet<String>,
+    failed: HashSet<String>,
+}
+
+impl Processed {
+    /// Reads [`PROCESSED_FILE`] from `dir`, if any.
+    fn load(dir: &Path) -> MyResult<Self> {
+        let path = dir.join(PROCESSED_FILE);
+        let digests = match path.exists() {
+            true => fs::read_to_string(&path)
+                .with_path(&path)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect(),
+            false => HashSet::new(),
+        };
+        Ok(Self {
+            path,
+            digests,
+            failed: HashSet::new(),
+        })
+    }
+
+    fn contains(&self, digest: &str) -> bool {
+        self.digests.contains(digest) || self.failed.contains(digest)
+    }
+
+    /// Records `digest`, in memory and in the file.
+    fn insert(&mut self, digest: String) -> MyResult<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_path(&self.path)?;
+        writeln!(file, "{digest}").with_path(&self.path)?;
+        self.digests.insert(digest);
+        Ok(())
+    }
+}
+
+/// Whether `path` is a CSV file, leaving out hidden and lock files.
+fn is_csv(path: &Path) -> bool {
+    let visible = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| !name.starts_with(['.', '~']));
+    let csv = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    visible && csv
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+/// Watches `watch.dir` and converts its CSV files until the process is stopped.
+///
+/// Each file is converted with the options of `args` to a workbook; other
+/// output formats are not written, since a CSV output could replace its input.
+pub fn watch_folder(args: Arguments, watch: &WatchArgs) -> MyResult<()> {
+    if !watch.dir.is_dir() {
+        return Err(PerDcompError::Io {
+            path: Some(watch.dir.clone()),
+            source: io::Error::new(io::ErrorKind::NotADirectory, "não é uma pasta"),
+        });
+    }
+    // Events carry absolute paths
+    let dir = &fs::canonicalize(&watch.dir).with_path(&watch.dir)?;
+    if let Some(output_dir) = &watch.output_dir {
+        fs::create_dir_all(output_dir).with_path(output_dir)?;
+    }
+
+    let mut processed = Processed::load(dir)?;
+    let debounce = Duration::from_secs(watch.debounce);
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|err| watch_error(dir, err))?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|err| watch_error(dir, err))?;
+
+    // The files already in the folder are handled like new ones
+    let mut pending = Pending::default();
+    for entry in fs::read_dir(dir).with_path(dir)? {
+        let path = entry.with_path(dir)?.path();
+        if is_csv(&path) {
+            pending.observe(&path, stamp(&path), Instant::now());
+        }
+    }
+
+    info!("Watching {:?} (debounce {}s).", dir, watch.debounce);
+
+    loop {
+        match receiver.recv_timeout(TICK) {
+            // Reading a file also raises events: only writes count
+            Ok(Ok(event)) if event.kind.is_create() || event.kind.is_modify() => {
+                for path in event.paths.iter().filter(|path| is_csv(path)) {
+                    pending.observe(path, stamp(path), Instant::now());
+                }
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => warn!("{}: {err}", dir.display()),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        let now = Instant::now();
+        for path in pending.paths() {
+            pending.observe(&path, stamp(&path), now);
+        }
+        for path in pending.ready(debounce, now) {
+            convert_file(&args, watch, &mut processed, &path)?;
+        }
+    }
+}
+
+/// A failure to watch `dir`, as an I/O error of the folder.
+fn watch_error(dir: &Path, error: notify::Error) -> PerDcompError {
+    let source = match error.kind {
+        notify::ErrorKind::Io(source) => source,
+        kind => io::Error::other(format!("não é possível monitorar a pasta: {kind:?}")),
+    };
+    PerDcompError::Io {
+        path: Some(dir.to_path_buf()),
+        source,
+    }
+}
+
+/// Converts one stable file, writing a failure to `<name>.error.txt`.
+///
+/// Only a failure to record the digest stops the watch.
+fn convert_file(
+    args: &Arguments,
+    watch: &WatchArgs,
+    processed: &mut Processed,
+    path: &Path,
+) -> MyResult<()> {
+    let input = match InputFile::inspect(input_role(args), path, args.input.encoding) {
+        Ok(input) => input,
+        Err(err) => {
+            warn!("{err}");
+            return Ok(());
+        }
+    };
+    if processed.contains(&input.sha256) {
+        debug!("{path:?} skipped: already converted.");
+        return Ok(());
+    }
+
+    let stem = path.file_stem().unwrap_or_default();
+    let output = match &watch.output_dir {
+        Some(output_dir) => output_dir.join(stem),
+        None => path.with_file_name(stem),
+    };
+    let error_file = path.with_extension("error.txt");
+
+    let mut file_args = args.clone();
+    file_args.input.path = vec![path.to_path_buf()];
+    file_args.convert.format = vec![OutputFormat::Xlsx];
+    file_args.command = None;
+
+    match Converter::from_arguments(file_args).output(output).run() {
+        Ok(report) => {
+            info!("{path:?}: {} rows converted.", report.rows_written);
+            processed.insert(input.sha256)?;
+            if error_file.exists() {
+                fs::remove_file(&error_file).with_path(&error_file)?;
+            }
+        }
+        Err(err) => {
+            error!("{path:?}: {err}");
+            processed.failed.insert(input.sha256);
+            if let Err(write_err) = fs::write(&error_file, format!("{err}\n")) {
+                warn!("{error_file:?}: {write_err}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests_watch {
+    use super::*;
+
+    #[test]
+    fn files_are_ready_once_stable_and_remembered_by_digest() -> MyResult<()> {
+        let start = Instant::now();
+        let debounce = Duration::from_secs(5);
+        let path = Path::new("scc.csv");
+        let mut pending = Pending::default();
+
+        pending.observe(path, Some((10, None)), start);
+        pending.observe(path, Some((20, None)), start + Duration::from_secs(3));
+        assert!(
+            pending
+                .ready(debounce, start + Duration::from_secs(6))
+                .is_empty()
+        );
+
+        pending.observe(path, Some((20, None)), start + Duration::from_secs(7));
+        assert_eq!(
+            pending.ready(debounce, start + Duration::from_secs(8)),
+            [path]
+        );
+        assert!(pending.paths().is_empty());
+
+        pending.observe(path, Some((20, None)), start);
+        pending.observe(path, None, start);
+        assert!(pending.paths().is_empty());
+
+        assert!(is_csv(Path::new("dir/SCC.CSV")));
+        assert!(!is_csv(Path::new("dir/.~lock.scc.csv")));
+        assert!(!is_csv(Path::new("dir/scc.xlsx")));
+
+        let dir = tempfile::tempdir()?;
+        let mut processed = Processed::load(dir.path())?;
+        processed.insert("abc".to_string())?;
+        assert!(Processed::load(dir.path())?.contains("abc"));
+        assert!(!processed.contains("def"));
+        Ok(())
+    }
+}
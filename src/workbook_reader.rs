@@ -0,0 +1,226 @@
+//! Reads `PerDcomp` records back from a workbook.
+//!
+//! Any worksheet whose headers match the `PerDcomp` names or aliases is read,
+//! such as the "PERDComp" sheets written by this tool or a colleague's edited
+//! copy. Each row goes through the same deserializers as a CSV line: typed
+//! cells are first written as the text the CSV export would have, so the
+//! aliases, the pt-BR numbers and the date layouts are all handled in one place.
+
+use calamine::{Data, DataType, Reader, open_workbook_auto};
+use csv::StringRecord;
+use log::{debug, info};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    MyResult, Origem, PerDcomp, PerDcompError, WithPath,
+    inspect::{column_of, sample_value},
+    normalize_text,
+};
+
+/// `true` for the workbook extensions read by [`read_xlsx`].
+pub fn is_xlsx(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("xlsx") || extension.eq_ignore_ascii_case("xlsm")
+        })
+}
+
+/// Reads the `PerDcomp` records of every matching worksheet of a workbook.
+///
+/// A worksheet matches when its first row has a "PER/DCOMP" header and the
+/// headers of every required field (see [`check_required_headers`]); other worksheets
+/// (e.g. "Débitos") are skipped. Rows without a text PER/DCOMP, such as a
+/// total row, are left out. In a matching worksheet, a row that cannot be
+/// read is an error, with the worksheet and the row.
+///
+/// Each record keeps the [`Origem`] written in its "Origem" column, when
+/// there is one, or else gets the worksheet and the 1-based row it came from.
+pub fn read_xlsx(path: &Path) -> MyResult<Vec<PerDcomp>> {
+    let mut workbook = open_workbook_auto(path).with_path(path)?;
+    let mut perdcomps: Vec<PerDcomp> = Vec::new();
+
+    for sheet_name in workbook.sheet_names() {
+        let range = workbook.worksheet_range(&sheet_name).with_path(path)?;
+        let mut rows = range.rows();
+        let Some(header_row) = rows.next() else {
+            continue;
+        };
+
+        let headers: StringRecord = header_row.iter().map(cell_text).collect();
+        let position = |target: &str| {
+            headers
+                .iter()
+                .position(|header| normalize_text(header) == normalize_text(target))
+        };
+        let Some(key_col) = position("PER/DCOMP") else {
+            continue;
+        };
+        if let Err(err) = check_required_headers(&headers) {
+            debug!("Skipping worksheet '{sheet_name}': {err}");
+            continue;
+        }
+        let origem_col = position("Origem");
+        let file: Arc<str> = format!("{}:{sheet_name}", path.display()).into();
+
+        let mut records: Vec<PerDcomp> = Vec::new();
+        for (index, row) in rows.enumerate() {
+            if !matches!(row.get(key_col), Some(Data::String(key)) if !key.trim().is_empty()) {
+                continue;
+            }
+
+            let record: StringRecord = row.iter().map(cell_text).collect();
+            let line = index as u64 + 2; // Header row + 1-based row number
+            let mut perdcomp: PerDcomp = match record.deserialize(Some(&headers)) {
+                Ok(perdcomp) => perdcomp,
+                Err(err) => {
+                    let sheet = PathBuf::from(&*file);
+                    let mut error = PerDcompError::from_record(err, &headers, &record);
+                    if let PerDcompError::Parse { line: row, .. } = &mut error {
+                        *row = Some(line);
+                    }
+                    return Err(error.with_path(&sheet));
+                }
+            };
+
+            perdcomp.get_year();
+            perdcomp.origem = origem_col
+                .and_then(|col| row.get(col))
+                .and_then(|cell| parse_origem(&cell_text(cell)))
+                .or_else(|| {
+                    Some(Origem {
+                        file: Arc::clone(&file),
+                        line,
+                    })
+                });
+            records.push(perdcomp);
+        }
+
+        if !records.is_empty() {
+            info!(
+                "Read {} records from worksheet '{sheet_name}' of {path:?}.",
+                records.len()
+            );
+        }
+        perdcomps.append(&mut records);
+    }
+
+    Ok(perdcomps)
+}
+
+/// Checks that `headers` name every field a `PerDcomp` record needs, by name or alias.
+///
+/// A record with a valid sample value under each known header is deserialized,
+/// so it only fails for a missing (or repeated) field.
+fn check_required_headers(headers: &StringRecord) -> Result<(), csv::Error> {
+    let sample: StringRecord = headers
+        .iter()
+        .map(|header| column_of(header).map_or("", |column| sample_value(column.kind)))
+        .collect();
+    sample.deserialize::<PerDcomp>(Some(headers)).map(|_| ())
+}
+
+/// Writes a cell as the text of a CSV field.
+///
+/// Numbers use a decimal comma without thousands separators, as read by
+/// `string_as_f64`; dates use `dd/mm/yyyy` and booleans `true`/`false`.
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::String(text) | Data::DateTimeIso(text) | Data::DurationIso(text) => text.clone(),
+        Data::Int(value) => value.to_string(),
+        Data::Float(value) if value.fract() == 0.0 => format!("{value:.0}"),
+        Data::Float(value) => value.to_string().replace('.', ","),
+        Data::Bool(value) => value.to_string(),
+        Data::DateTime(_) => cell
+            .as_datetime()
+            .map(|datetime| datetime.format("%d/%m/%Y").to_string())
+            .unwrap_or_default(),
+        Data::Error(_) | Data::Empty => String::new(),
+    }
+}
+
+/// Parses the `"file:line"` text of an "Origem" cell.
+fn parse_origem(text: &str) -> Option<Origem> {
+    let (file, line) = text.rsplit_once(':')?;
+    Some(Origem {
+        file: file.into(),
+        line: line.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests_workbook_reader {
+    use super::*;
+    use crate::{PerDcomp, Situacao, new_workbook, push_worksheets};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn reads_back_a_generated_workbook() -> MyResult<()> {
+        let perdcomps = vec![PerDcomp {
+            per_dcomp: Some("11111.11111.120921.1.1.04-1111".to_string()),
+            valor_total_do_credito: 1234.56,
+            valor_do_per: 1000.0,
+            total_debitos: Some(0.5),
+            data_da_transmissao: NaiveDate::from_ymd_opt(2021, 9, 12),
+            demonstra_credito: Some(true),
+            trimestre_de_apuracao: Some("1º TRIMESTRE".to_string()),
+            ano: Some(2021),
+            situacao: Some(Situacao::NaoHomologada),
+            origem: Some(Origem {
+                file: "perdcomp.csv".into(),
+                line: 7,
+            }),
+            analise: Some("Conferido".to_string()),
+            ..Default::default()
+        }];
+
+        let output = tempfile::Builder::new().suffix(".xlsx").tempfile()?;
+        let mut workbook = new_workbook()?;
+        push_worksheets(&mut workbook, &perdcomps, "PERDComp", &[])?;
+        workbook.save(output.path())?;
+
+        assert!(is_xlsx(output.path()));
+        assert_eq!(read_xlsx(output.path())?, perdcomps);
+        Ok(())
+    }
+
+    #[test]
+    fn sheets_are_chosen_by_headers_and_bad_rows_are_errors() -> MyResult<()> {
+        let output = tempfile::Builder::new().suffix(".xlsx").tempfile()?;
+        let mut workbook = new_workbook()?;
+
+        // A "PER/DCOMP" column alone does not make a PerDcomp worksheet
+        let debitos = workbook.add_worksheet().set_name("Débitos")?;
+        debitos.write_row(0, 0, ["PER/DCOMP", "Código da Receita"])?;
+        debitos.write_row(1, 0, ["111", "5856"])?;
+
+        let headers = [
+            "PER/DCOMP",
+            "Valor Total Crédito",
+            "Valor Crédito Data Transmissão",
+            "Vl. Crédito Utilizado/Vl. PER",
+        ];
+        let sheet = workbook.add_worksheet().set_name("PERDComp")?;
+        sheet.write_row(0, 0, headers)?;
+        sheet.write_row(1, 0, ["111", "abc", "1", "1"])?;
+        workbook.save(output.path())?;
+
+        let error = read_xlsx(output.path()).unwrap_err();
+        assert_eq!(error.line(), Some(2));
+        assert!(error.to_string().contains("PERDComp"));
+
+        let mut workbook = new_workbook()?;
+        let sheet = workbook.add_worksheet().set_name("PERDComp")?;
+        sheet.write_row(0, 0, headers)?;
+        sheet.write_row(1, 0, ["111", "1.000,00", "1", "1"])?;
+        workbook.save(output.path())?;
+
+        let perdcomps = read_xlsx(output.path())?;
+        assert_eq!(perdcomps.len(), 1);
+        assert_eq!(perdcomps[0].valor_total_do_credito, 1000.0);
+        Ok(())
+    }
+}